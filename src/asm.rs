@@ -0,0 +1,495 @@
+//! An assembler for writing test programs, using Cowgod's syntax as printed
+//! by `Instruction`'s `Display` impl.
+//!
+//! ```text
+//! SPEED   EQU 2           ; a constant
+//! start:  LD V0, SPEED
+//!         LD I, sprite
+//!         DRW V0, V0, 4
+//!         JP start
+//! sprite: DB #F0, #90, #90, #F0
+//!         DW start + 2
+//!         include "more.asm"
+//! ```
+//!
+//! Numbers can be decimal or hex written `#2A`, `$2A` or `0x2A`, or binary
+//! written `%1010` or `0b1010`. Operands can add and subtract numbers,
+//! labels and constants. Everything after a `;` is a comment.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use instruction::{Address, Instruction, Register};
+
+const PROGRAM_START: Address = 0x200;
+/// Deep enough for any sensible program, shallow enough to catch a file including itself.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Why a program could not be assembled, and where.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsmError {
+    pub file: String,
+    /// The line the error is on, counting from 1, or 0 if it isn't on a line.
+    pub line: usize,
+    pub message: String,
+}
+
+impl AsmError {
+    fn new(file: &str, line: usize, message: &str) -> AsmError {
+        AsmError {
+            file: file.to_string(),
+            line,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}: {}", self.file, self.message)
+        } else {
+            write!(f, "{}:{}: {}", self.file, self.line, self.message)
+        }
+    }
+}
+
+impl Error for AsmError {}
+
+/// Assembles a program to be loaded at 0x200. Included files are looked up
+/// relative to the current directory.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut lines = Vec::new();
+    read_lines("<source>", source, Path::new("."), 0, &mut lines)?;
+    assemble_lines(&lines)
+}
+
+/// Assembles the program in a file. Included files are looked up relative
+/// to the file that includes them.
+pub fn assemble_file(path: &Path) -> Result<Vec<u8>, AsmError> {
+    let mut lines = Vec::new();
+    include(path, &path.display().to_string(), 0, 0, &mut lines)?;
+    assemble_lines(&lines)
+}
+
+/// A line of source with comments removed and includes expanded.
+struct Line {
+    file: String,
+    number: usize,
+    statement: Statement,
+}
+
+impl Line {
+    fn error(&self, message: String) -> AsmError {
+        AsmError {
+            file: self.file.clone(),
+            line: self.number,
+            message,
+        }
+    }
+}
+
+enum Statement {
+    Label(String),
+    Constant(String, String),
+    Bytes(Vec<String>),
+    Words(Vec<String>),
+    Instruction(String, Vec<String>),
+}
+
+impl Statement {
+    fn size(&self) -> usize {
+        match *self {
+            Statement::Label(_) | Statement::Constant(..) => 0,
+            Statement::Bytes(ref values) => values.len(),
+            Statement::Words(ref values) => values.len() * 2,
+            Statement::Instruction(ref mnemonic, ref operands) => {
+                let long = operands.iter().any(|operand| operand.to_uppercase().starts_with("LONG "));
+                if mnemonic == "LD" && long { 4 } else { 2 }
+            }
+        }
+    }
+}
+
+/// Reads the file `path`, which is included from line `line` of `file`.
+fn include(path: &Path, file: &str, line: usize, depth: usize, lines: &mut Vec<Line>) -> Result<(), AsmError> {
+    let name = path.display().to_string();
+    if depth > MAX_INCLUDE_DEPTH {
+        return Err(AsmError::new(file, line, "includes are nested too deeply"));
+    }
+    let source = fs::read_to_string(path).map_err(|error| {
+        AsmError::new(file, line, &format!("could not read {}: {}", name, error))
+    })?;
+    let dir = path.parent().map_or(PathBuf::from("."), |dir| dir.to_path_buf());
+    read_lines(&name, &source, &dir, depth, lines)
+}
+
+fn read_lines(file: &str, source: &str, dir: &Path, depth: usize, lines: &mut Vec<Line>) -> Result<(), AsmError> {
+    for (i, text) in source.lines().enumerate() {
+        let number = i + 1;
+        let mut text = text.split(';').next().unwrap_or("").trim();
+
+        let words: Vec<&str> = text.split_whitespace().collect();
+        if words.first().is_some_and(|word| word.eq_ignore_ascii_case("include")) {
+            let path = text[words[0].len()..].trim().trim_matches('"');
+            if path.is_empty() {
+                return Err(AsmError::new(file, number, "include needs a file name"));
+            }
+            include(&dir.join(path), file, number, depth + 1, lines)?;
+            continue;
+        }
+
+        let mut push = |statement| {
+            lines.push(Line {
+                file: file.to_string(),
+                number,
+                statement,
+            })
+        };
+
+        if words.len() >= 3 && words[1].eq_ignore_ascii_case("EQU") {
+            let value = text[words[0].len()..].trim_start()[words[1].len()..].trim();
+            push(Statement::Constant(words[0].to_string(), value.to_string()));
+            continue;
+        }
+
+        if let Some(colon) = text.find(':') {
+            let label = text[..colon].trim();
+            if !is_name(label) {
+                return Err(AsmError::new(file, number, &format!("'{}' is not a valid label", label)));
+            }
+            push(Statement::Label(label.to_string()));
+            text = text[colon + 1..].trim();
+        }
+        if text.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, operands) = match text.find(char::is_whitespace) {
+            Some(space) => (&text[..space], text[space..].trim()),
+            None => (text, ""),
+        };
+        let operands: Vec<String> = if operands.is_empty() {
+            Vec::new()
+        } else {
+            operands.split(',').map(|operand| operand.trim().to_string()).collect()
+        };
+        let mnemonic = mnemonic.to_uppercase();
+        push(match &mnemonic[..] {
+            "DB" => Statement::Bytes(operands),
+            "DW" => Statement::Words(operands),
+            _ => Statement::Instruction(mnemonic, operands),
+        });
+    }
+    Ok(())
+}
+
+/// Works out where every label is, then encodes the program now that every
+/// jump target is known.
+fn assemble_lines(lines: &[Line]) -> Result<Vec<u8>, AsmError> {
+    let mut symbols = HashMap::new();
+    let mut addr = PROGRAM_START as i64;
+    for line in lines {
+        match line.statement {
+            Statement::Label(ref name) => define(&mut symbols, line, name, addr)?,
+            Statement::Constant(ref name, ref value) => {
+                let value = evaluate(value, &symbols).map_err(|message| line.error(message))?;
+                define(&mut symbols, line, name, value)?;
+            }
+            ref statement => addr += statement.size() as i64,
+        }
+    }
+
+    let mut program = Vec::new();
+    for line in lines {
+        emit(&line.statement, &symbols, &mut program).map_err(|message| line.error(message))?;
+    }
+    Ok(program)
+}
+
+fn emit(statement: &Statement, symbols: &HashMap<String, i64>, program: &mut Vec<u8>) -> Result<(), String> {
+    match *statement {
+        Statement::Label(_) | Statement::Constant(..) => {}
+        Statement::Bytes(ref values) => {
+            for value in values {
+                program.push(byte(value, symbols)?);
+            }
+        }
+        Statement::Words(ref values) => {
+            for value in values {
+                push_word(program, number(value, symbols, 0xFFFF)? as u16);
+            }
+        }
+        Statement::Instruction(ref mnemonic, ref operands) => {
+            let instruction = encode(mnemonic, operands, symbols)?;
+            push_word(program, instruction.encode());
+            if let Instruction::LoadLongI(addr) = instruction {
+                push_word(program, addr);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn push_word(program: &mut Vec<u8>, word: u16) {
+    program.push((word >> 8) as u8);
+    program.push(word as u8);
+}
+
+fn define(symbols: &mut HashMap<String, i64>, line: &Line, name: &str, value: i64) -> Result<(), AsmError> {
+    if symbols.insert(name.to_string(), value).is_some() {
+        return Err(line.error(format!("'{}' is defined more than once", name)));
+    }
+    Ok(())
+}
+
+/// An operand, once register names have been told apart from numbers.
+enum Operand {
+    Reg(Register),
+    I,
+    IndirectI,
+    DelayTimer,
+    SoundTimer,
+    Key,
+    Font,
+    LargeFont,
+    Bcd,
+    Flags,
+    Long(String),
+    Value(String),
+}
+
+fn operand(text: &str) -> Operand {
+    let upper = text.to_uppercase();
+    if upper.len() == 2 && upper.starts_with('V') {
+        if let Ok(reg) = u8::from_str_radix(&upper[1..], 16) {
+            return Operand::Reg(reg);
+        }
+    }
+    match &upper[..] {
+        "I" => Operand::I,
+        "[I]" => Operand::IndirectI,
+        "DT" => Operand::DelayTimer,
+        "ST" => Operand::SoundTimer,
+        "K" => Operand::Key,
+        "F" => Operand::Font,
+        "HF" => Operand::LargeFont,
+        "B" => Operand::Bcd,
+        "R" => Operand::Flags,
+        // Matched on the text itself, since uppercasing can move char boundaries
+        _ if text.get(..5).is_some_and(|prefix| prefix.eq_ignore_ascii_case("LONG ")) => {
+            Operand::Long(text[5..].trim().to_string())
+        }
+        _ => Operand::Value(text.to_string()),
+    }
+}
+
+fn encode(mnemonic: &str, operands: &[String], symbols: &HashMap<String, i64>) -> Result<Instruction, String> {
+    use self::Operand::*;
+
+    let operands: Vec<Operand> = operands.iter().map(|text| operand(text)).collect();
+    let addr = |value: &str| number(value, symbols, 0xFFF).map(|addr| addr as Address);
+    let nibble = |value: &str| number(value, symbols, 0xF).map(|n| n as u8);
+
+    let instruction = match (mnemonic, &operands[..]) {
+        ("CLS", &[]) => Instruction::ClearDisplay,
+        ("RET", &[]) => Instruction::Return,
+        ("SCD", &[Value(ref n)]) => Instruction::ScrollDown(nibble(n)?),
+        ("SCU", &[Value(ref n)]) => Instruction::ScrollUp(nibble(n)?),
+        ("SCR", &[]) => Instruction::ScrollRight,
+        ("SCL", &[]) => Instruction::ScrollLeft,
+        ("EXIT", &[]) => Instruction::Exit,
+        ("LOW", &[]) => Instruction::LowRes,
+        ("HIGH", &[]) => Instruction::HighRes,
+        ("JP", &[Value(ref target)]) => Instruction::Jump(addr(target)?),
+        ("JP", &[Reg(0), Value(ref target)]) => Instruction::JumpPlusZero(addr(target)?),
+        ("CALL", &[Value(ref target)]) => Instruction::Call(addr(target)?),
+        ("SE", &[Reg(x), Reg(y)]) => Instruction::SkipIfEqual(x, y),
+        ("SE", &[Reg(x), Value(ref value)]) => Instruction::SkipIfEqualsByte(x, byte(value, symbols)?),
+        ("SNE", &[Reg(x), Reg(y)]) => Instruction::SkipIfNotEqual(x, y),
+        ("SNE", &[Reg(x), Value(ref value)]) => Instruction::SkipIfNotEqualsByte(x, byte(value, symbols)?),
+        ("SAVE", &[Reg(x), Reg(y)]) => Instruction::SaveRange(x, y),
+        ("LOAD", &[Reg(x), Reg(y)]) => Instruction::LoadRange(x, y),
+        ("LD", &[Reg(x), Reg(y)]) => Instruction::Move(x, y),
+        ("LD", &[Reg(x), Value(ref value)]) => Instruction::LoadByte(x, byte(value, symbols)?),
+        ("LD", &[I, Value(ref target)]) => Instruction::LoadI(addr(target)?),
+        ("LD", &[I, Long(ref target)]) => Instruction::LoadLongI(number(target, symbols, 0xFFFF)? as Address),
+        ("LD", &[Reg(x), DelayTimer]) => Instruction::LoadDelayTimer(x),
+        ("LD", &[Reg(x), Key]) => Instruction::WaitForKeyPress(x),
+        ("LD", &[DelayTimer, Reg(x)]) => Instruction::SetDelayTimer(x),
+        ("LD", &[SoundTimer, Reg(x)]) => Instruction::SetSoundTimer(x),
+        ("LD", &[Font, Reg(x)]) => Instruction::LoadSprite(x),
+        ("LD", &[LargeFont, Reg(x)]) => Instruction::LoadLargeSprite(x),
+        ("LD", &[Bcd, Reg(x)]) => Instruction::BCDRepresentation(x),
+        ("LD", &[IndirectI, Reg(x)]) => Instruction::StoreRegisters(x),
+        ("LD", &[Reg(x), IndirectI]) => Instruction::LoadRegisters(x),
+        ("LD", &[Flags, Reg(x)]) => Instruction::StoreFlags(x),
+        ("LD", &[Reg(x), Flags]) => Instruction::LoadFlags(x),
+        ("ADD", &[Reg(x), Reg(y)]) => Instruction::Add(x, y),
+        ("ADD", &[Reg(x), Value(ref value)]) => Instruction::AddByte(x, byte(value, symbols)?),
+        ("ADD", &[I, Reg(x)]) => Instruction::AddToI(x),
+        ("OR", &[Reg(x), Reg(y)]) => Instruction::Or(x, y),
+        ("AND", &[Reg(x), Reg(y)]) => Instruction::And(x, y),
+        ("XOR", &[Reg(x), Reg(y)]) => Instruction::Xor(x, y),
+        ("SUB", &[Reg(x), Reg(y)]) => Instruction::Sub(x, y),
+        ("SUBN", &[Reg(x), Reg(y)]) => Instruction::ReverseSub(x, y),
+        ("SHR", &[Reg(x), Reg(y)]) => Instruction::ShiftRight(x, y),
+        ("SHR", &[Reg(x)]) => Instruction::ShiftRight(x, x),
+        ("SHL", &[Reg(x), Reg(y)]) => Instruction::ShiftLeft(x, y),
+        ("SHL", &[Reg(x)]) => Instruction::ShiftLeft(x, x),
+        ("RND", &[Reg(x), Value(ref value)]) => Instruction::Random(x, byte(value, symbols)?),
+        ("PLANE", &[Value(ref planes)]) => Instruction::SelectPlanes(nibble(planes)?),
+        ("DRW", &[Reg(x), Reg(y), Value(ref n)]) => {
+            match nibble(n)? {
+                0 => Instruction::DrawLarge(x, y),
+                n => Instruction::Draw(x, y, n),
+            }
+        }
+        ("SKP", &[Reg(x)]) => Instruction::SkipIfPressed(x),
+        ("SKNP", &[Reg(x)]) => Instruction::SkipIfNotPressed(x),
+        ("AUDIO", &[]) => Instruction::LoadAudioPattern,
+        ("PITCH", &[Reg(x)]) => Instruction::SetPitch(x),
+        _ => return Err(format!("unknown instruction or operands for {}", mnemonic)),
+    };
+    Ok(instruction)
+}
+
+/// A byte, which can also be written as a negative number, e.g. `ADD V0, -1`.
+fn byte(value: &str, symbols: &HashMap<String, i64>) -> Result<u8, String> {
+    let byte = evaluate(value, symbols)?;
+    if !(-0x80..=0xFF).contains(&byte) {
+        return Err(format!("{} does not fit in a byte", value));
+    }
+    Ok(byte as u8)
+}
+
+fn number(value: &str, symbols: &HashMap<String, i64>, max: i64) -> Result<i64, String> {
+    let number = evaluate(value, symbols)?;
+    if number < 0 || number > max {
+        return Err(format!("{} is out of range, it must be from 0 to {:#X}", value, max));
+    }
+    Ok(number)
+}
+
+/// Adds and subtracts numbers, labels and constants, e.g. `sprite + SIZE - 1`.
+fn evaluate(expression: &str, symbols: &HashMap<String, i64>) -> Result<i64, String> {
+    let mut total = 0;
+    let mut terms = 0;
+    let mut sign = 1;
+    let mut term = String::new();
+    for c in expression.chars().chain(Some('+')) {
+        if c != '+' && c != '-' {
+            term.push(c);
+        } else if term.trim().is_empty() {
+            if c == '-' {
+                sign = -sign;
+            }
+        } else {
+            total += sign * value(term.trim(), symbols)?;
+            terms += 1;
+            term.clear();
+            sign = if c == '-' { -1 } else { 1 };
+        }
+    }
+    if terms == 0 || sign != 1 {
+        return Err(format!("'{}' is not a valid expression", expression));
+    }
+    Ok(total)
+}
+
+fn value(term: &str, symbols: &HashMap<String, i64>) -> Result<i64, String> {
+    let lower = term.to_lowercase();
+    let (digits, radix) = if let Some(digits) = lower.strip_prefix('#').or_else(|| lower.strip_prefix('$')) {
+        (digits, 16)
+    } else if let Some(digits) = lower.strip_prefix("0x") {
+        (digits, 16)
+    } else if let Some(digits) = lower.strip_prefix('%') {
+        (digits, 2)
+    } else if let Some(digits) = lower.strip_prefix("0b") {
+        (digits, 2)
+    } else if lower.starts_with(|c: char| c.is_ascii_digit()) {
+        (&lower[..], 10)
+    } else {
+        return symbols.get(term).cloned().ok_or(format!("'{}' is not defined", term));
+    };
+    i64::from_str_radix(digits, radix).map_err(|_| format!("'{}' is not a valid number", term))
+}
+
+fn is_name(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_alphabetic() || c == '_') &&
+    text.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use instruction::RawInstruction;
+
+    #[test]
+    fn assembles_what_instructions_display_as() {
+        for raw in 0..=0xFFFF {
+            if let Some(instruction) = RawInstruction::new(raw).to_instruction() {
+                let opcode = instruction.encode();
+                assert_eq!(assemble(&instruction.to_string()),
+                           Ok(vec![(opcode >> 8) as u8, opcode as u8]),
+                           "{}",
+                           instruction);
+            }
+        }
+        assert_eq!(assemble(&Instruction::LoadLongI(0x1234).to_string()),
+                   Ok(vec![0xF0, 0x00, 0x12, 0x34]));
+    }
+
+    #[test]
+    fn resolves_labels_constants_and_data() {
+        let source = "
+            SIZE    EQU 4
+            start:  LD I, sprite    ; forward reference
+                    DRW V0, V1, SIZE
+                    JP start
+            sprite: DB %11110000, $90, 0x90, #F0
+                    DW sprite + SIZE - 1, 10
+        ";
+        assert_eq!(assemble(source),
+                   Ok(vec![0xA2, 0x06, 0xD0, 0x14, 0x12, 0x00, 0xF0, 0x90, 0x90, 0xF0, 0x02,
+                           0x09, 0x00, 0x0A]));
+    }
+
+    #[test]
+    fn reports_the_line_of_an_error() {
+        let error = assemble("CLS\nJP nowhere").unwrap_err();
+        assert_eq!(error.line, 2);
+        assert_eq!(error.message, "'nowhere' is not defined");
+
+        assert!(assemble("LD V0, 256").is_err());
+        assert!(assemble("JP #1000").is_err());
+        assert!(assemble("a:\na:").is_err());
+        assert!(assemble("LD I, LONG \u{131}").is_err());
+        assert!(assemble("LD I, \u{131}\u{131}\u{131} 1").is_err());
+    }
+
+    #[test]
+    fn includes_are_found_next_to_the_file_that_includes_them() {
+        let dir = std::env::temp_dir().join(format!("rust-8-asm-{}", std::process::id()));
+        fs::create_dir_all(dir.join("parts")).unwrap();
+        let write = |name: &str, source: &str| fs::write(dir.join(name), source).unwrap();
+        write("main.asm", "start: CLS\n        include \"parts/sprite.asm\"\n        JP start\n");
+        write("parts/sprite.asm", "include more.asm\nDB #F0\n");
+        write("parts/more.asm", "RET\n");
+        write("missing.asm", "CLS\ninclude \"nothing.asm\"\n");
+        write("itself.asm", "include itself.asm\n");
+
+        let result = assemble_file(&dir.join("main.asm"));
+        let missing = assemble_file(&dir.join("missing.asm")).unwrap_err();
+        let itself = assemble_file(&dir.join("itself.asm")).unwrap_err();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(result, Ok(vec![0x00, 0xE0, 0x00, 0xEE, 0xF0, 0x12, 0x00]));
+        assert_eq!((missing.file, missing.line), (dir.join("missing.asm").display().to_string(), 2));
+        assert!(missing.message.starts_with("could not read"), "{}", missing.message);
+        assert_eq!(itself.message, "includes are nested too deeply");
+    }
+}
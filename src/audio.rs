@@ -0,0 +1,150 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+pub const SAMPLE_RATE: u32 = 48000;
+pub const BEEP_FREQUENCY: f32 = 440.0;
+pub const PATTERN_SIZE: usize = 16;
+/// The pitch register value at which an XO-CHIP pattern plays at 4000 bits a second.
+pub const DEFAULT_PITCH: u8 = 64;
+const BEEP_VOLUME: f32 = 0.25;
+
+/// Something that can make the CHIP-8 buzzer audible.
+///
+/// The machine only ever tells the sink whether the buzzer is on or off;
+/// it is up to the sink to decide what that sounds like.
+pub trait AudioSink {
+    fn set_beeping(&mut self, beeping: bool);
+
+    /// Replaces the buzzer tone with an XO-CHIP 1-bit waveform played at the given pitch.
+    fn set_pattern(&mut self, _pattern: [u8; PATTERN_SIZE], _pitch: u8) {}
+}
+
+/// A sink that throws the buzzer state away, for builds without a sound device.
+pub struct SilentSink;
+
+impl AudioSink for SilentSink {
+    fn set_beeping(&mut self, _: bool) {}
+}
+
+/// A sink that remembers every buzzer change so it can be inspected later.
+///
+/// Clones share the same log, so keep one clone around and hand the other
+/// to the machine.
+#[derive(Clone, Default)]
+pub struct RecordingSink {
+    changes: Rc<RefCell<Vec<bool>>>,
+}
+
+impl RecordingSink {
+    pub fn new() -> RecordingSink {
+        RecordingSink { changes: Rc::new(RefCell::new(Vec::new())) }
+    }
+
+    pub fn is_beeping(&self) -> bool {
+        self.changes.borrow().last().cloned().unwrap_or(false)
+    }
+
+    pub fn changes(&self) -> Vec<bool> {
+        self.changes.borrow().clone()
+    }
+}
+
+impl AudioSink for RecordingSink {
+    fn set_beeping(&mut self, beeping: bool) {
+        self.changes.borrow_mut().push(beeping);
+    }
+}
+
+/// An endless square wave, one channel at `SAMPLE_RATE`.
+#[derive(Clone)]
+pub struct SquareWave {
+    frequency: f32,
+    sample_number: u64,
+}
+
+impl SquareWave {
+    pub fn new(frequency: f32) -> SquareWave {
+        SquareWave {
+            frequency,
+            sample_number: 0,
+        }
+    }
+}
+
+impl Iterator for SquareWave {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let samples_per_period = SAMPLE_RATE as f32 / self.frequency;
+        let position = (self.sample_number as f32 % samples_per_period) / samples_per_period;
+        self.sample_number = self.sample_number.wrapping_add(1);
+
+        if position < 0.5 {
+            Some(BEEP_VOLUME)
+        } else {
+            Some(-BEEP_VOLUME)
+        }
+    }
+}
+
+/// An XO-CHIP audio pattern: 128 1-bit samples played on a loop.
+#[derive(Clone)]
+pub struct PatternWave {
+    pattern: [u8; PATTERN_SIZE],
+    bits_per_sample: f32,
+    position: f32,
+}
+
+impl PatternWave {
+    pub fn new(pattern: [u8; PATTERN_SIZE], pitch: u8) -> PatternWave {
+        let bits_per_second = 4000.0 * 2f32.powf((pitch as f32 - DEFAULT_PITCH as f32) / 48.0);
+        PatternWave {
+            pattern,
+            bits_per_sample: bits_per_second / SAMPLE_RATE as f32,
+            position: 0.0,
+        }
+    }
+}
+
+impl Iterator for PatternWave {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let bit_number = self.position as usize % (PATTERN_SIZE * 8);
+        let bit = (self.pattern[bit_number / 8] >> (7 - bit_number % 8)) & 1;
+        self.position = (self.position + self.bits_per_sample) % (PATTERN_SIZE * 8) as f32;
+
+        if bit == 1 {
+            Some(BEEP_VOLUME)
+        } else {
+            Some(-BEEP_VOLUME)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip8::Chip8;
+    use platform::Platform;
+
+    #[test]
+    fn the_buzzer_sounds_for_the_sound_timer_at_60_hz() {
+        let rom = vec![0x60, 0x1E, // V0 := 30
+                       0xF0, 0x18, // buzzer := V0
+                       0x12, 0x04]; // loop: jump loop
+        let mut chip8 = Chip8::new(rom, Platform::Chip8, Platform::Chip8.default_quirks()).unwrap();
+        let sink = RecordingSink::new();
+        chip8.audio = Box::new(sink.clone());
+
+        chip8.cycle(0.25).unwrap();
+        assert_eq!(chip8.sound_timer(), 15);
+        assert_eq!(sink.changes(), vec![true]);
+
+        chip8.cycle(0.25).unwrap();
+        assert_eq!(chip8.sound_timer(), 0);
+        chip8.cycle(0.5).unwrap();
+        assert_eq!(sink.changes(), vec![true, false]);
+        assert!(!sink.is_beeping());
+    }
+}
@@ -0,0 +1,35 @@
+//! Assembles a program into a ROM.
+//!
+//! ```text
+//! rust-8-asm SOURCE OUTPUT
+//! ```
+
+extern crate rust_8;
+
+use std::env;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::process;
+
+use rust_8::asm;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() != 2 {
+        eprintln!("Usage: rust-8-asm SOURCE OUTPUT");
+        process::exit(2);
+    }
+
+    let rom = match asm::assemble_file(Path::new(&args[0])) {
+        Ok(rom) => rom,
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    };
+    if let Err(error) = File::create(&args[1]).and_then(|mut file| file.write_all(&rom)) {
+        eprintln!("Could not write {}: {}", args[1], error);
+        process::exit(1);
+    }
+}
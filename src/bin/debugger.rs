@@ -0,0 +1,211 @@
+//! An interactive debugger that runs a ROM in the terminal.
+//!
+//! ```text
+//! rust-8-debugger GAME [--platform NAME]
+//! ```
+//!
+//! The program starts paused at 0x200. Type `help` for the commands; an
+//! empty line repeats the last one.
+
+extern crate rust_8;
+
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, Read, Write};
+use std::process;
+
+use rust_8::{Chip8, Platform};
+use rust_8::debugger::{self, Debugger, OpcodePattern};
+
+const HELP: &str = "\
+step [N]          run N instructions (default 1)
+continue          run until a breakpoint, watchpoint or error
+break ADDR        stop when the program counter reaches ADDR
+delete ADDR       remove the breakpoint at ADDR
+opbreak PATTERN   stop before any opcode matching PATTERN, e.g. DXYN
+opdelete PATTERN  remove an opcode breakpoint
+watch ADDR        stop when the byte at ADDR changes
+unwatch ADDR      remove a watchpoint
+list              show breakpoints and watchpoints
+regs              show V0-VF, I, the program counter and the timers
+stack             show the return addresses on the stack
+mem ADDR [N]      show N bytes of memory from ADDR (default 16)
+dis [N]           disassemble N instructions either side of the program counter
+screen            show the display
+key K / up K      press / release hex key K
+quit              leave the debugger
+Addresses and keys are hex.";
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let mut file_name = None;
+    let mut platform = Platform::Chip8;
+    while let Some(arg) = args.next() {
+        if arg == "--platform" {
+            let name = args.next().unwrap_or_default();
+            platform = match name.parse() {
+                Ok(platform) => platform,
+                Err(message) => exit_with(&message),
+            };
+        } else {
+            file_name = Some(arg);
+        }
+    }
+    let file_name = match file_name {
+        Some(file_name) => file_name,
+        None => exit_with("Usage: rust-8-debugger GAME [--platform NAME]"),
+    };
+
+    let mut game_data = Vec::new();
+    if let Err(error) = File::open(&file_name).and_then(|mut file| file.read_to_end(&mut game_data)) {
+        exit_with(&format!("Could not read {}: {}", file_name, error));
+    }
+    let mut computer = match Chip8::new(game_data, platform, platform.default_quirks()) {
+        Ok(computer) => computer,
+        Err(error) => exit_with(&format!("Could not load the game: {}", error)),
+    };
+
+    let mut debugger = Debugger::new();
+    let mut last_command = String::new();
+    print!("{}", debugger::disassemble_around(&computer, 0, 4));
+
+    let stdin = io::stdin();
+    loop {
+        print!("(rust-8) ");
+        io::stdout().flush().expect("Failure to write to the terminal");
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 {
+            break;
+        }
+        let line = line.trim().to_string();
+        let command = if line.is_empty() { last_command.clone() } else { line };
+        if command == "quit" || command == "q" {
+            break;
+        }
+
+        if let Err(message) = run_command(&command, &mut computer, &mut debugger) {
+            println!("{}", message);
+        }
+        last_command = command;
+    }
+}
+
+fn run_command(command: &str, computer: &mut Chip8, debugger: &mut Debugger) -> Result<(), String> {
+    let words: Vec<&str> = command.split_whitespace().collect();
+    let argument = |n: usize| words.get(n).ok_or(format!("{} needs more arguments", words[0]));
+
+    match words.first().cloned().unwrap_or("") {
+        "" => {}
+        "help" | "h" => println!("{}", HELP),
+        "step" | "s" => {
+            let count = match words.get(1) {
+                Some(count) => count.parse().map_err(|_| format!("bad count '{}'", count))?,
+                None => 1,
+            };
+            println!("{}", debugger.step(computer, count));
+            print!("{}", debugger::disassemble_around(computer, 0, 0));
+        }
+        "continue" | "c" => {
+            println!("{}", debugger.resume(computer));
+            print!("{}", debugger::disassemble_around(computer, 0, 0));
+        }
+        "break" | "b" => debugger.add_breakpoint(parse_hex(argument(1)?)?),
+        "delete" | "d" => {
+            if !debugger.remove_breakpoint(parse_hex(argument(1)?)?) {
+                println!("No breakpoint there");
+            }
+        }
+        "opbreak" => debugger.add_opcode_breakpoint(argument(1)?.parse()?),
+        "opdelete" => {
+            let pattern: OpcodePattern = argument(1)?.parse()?;
+            if !debugger.remove_opcode_breakpoint(&pattern) {
+                println!("No opcode breakpoint {}", pattern);
+            }
+        }
+        "watch" | "w" => debugger.add_watch(computer, parse_hex(argument(1)?)?),
+        "unwatch" => {
+            if !debugger.remove_watch(parse_hex(argument(1)?)?) {
+                println!("No watchpoint there");
+            }
+        }
+        "list" | "l" => {
+            for addr in debugger.breakpoints() {
+                println!("break {:03X}", addr);
+            }
+            for pattern in debugger.opcode_breakpoints() {
+                println!("opbreak {}", pattern);
+            }
+            for addr in debugger.watches() {
+                println!("watch {:03X}", addr);
+            }
+        }
+        "regs" | "r" => print_registers(computer),
+        "stack" => {
+            if computer.stack().is_empty() {
+                println!("The stack is empty");
+            }
+            for (depth, addr) in computer.stack().iter().enumerate().rev() {
+                println!("#{} {:03X}", depth, addr);
+            }
+        }
+        "mem" | "m" => {
+            let from = parse_hex(argument(1)?)? as usize;
+            let len = match words.get(2) {
+                Some(len) => len.parse().map_err(|_| format!("bad length '{}'", len))?,
+                None => 16,
+            };
+            let memory = computer.memory();
+            let to = from.saturating_add(len).min(memory.len());
+            for (row, bytes) in memory[from.min(to)..to].chunks(16).enumerate() {
+                let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+                println!("{:03X}: {}", from + row * 16, hex.join(" "));
+            }
+        }
+        "dis" => {
+            let around = match words.get(1) {
+                Some(around) => around.parse().map_err(|_| format!("bad count '{}'", around))?,
+                None => 5,
+            };
+            print!("{}", debugger::disassemble_around(computer, around, around));
+        }
+        "screen" => print!("{}", computer.display.to_ascii()),
+        "key" => computer.handle_key_press(parse_key(argument(1)?)?),
+        "up" => computer.handle_key_release(parse_key(argument(1)?)?),
+        other => return Err(format!("Unknown command '{}', try help", other)),
+    }
+    Ok(())
+}
+
+fn print_registers(computer: &Chip8) {
+    for (number, value) in computer.registers().iter().enumerate() {
+        print!("V{:X}={:02X} ", number, value);
+        if number == 7 {
+            println!();
+        }
+    }
+    println!();
+    println!("I={:03X} PC={:03X} SP={} DT={} ST={}",
+             computer.i_register(),
+             computer.program_counter(),
+             computer.stack_pointer(),
+             computer.delay_timer(),
+             computer.sound_timer());
+}
+
+fn parse_hex(value: &str) -> Result<u16, String> {
+    let digits = value.trim_start_matches("0x");
+    u16::from_str_radix(digits, 16).map_err(|_| format!("bad hex number '{}'", value))
+}
+
+fn parse_key(value: &str) -> Result<u8, String> {
+    match u8::from_str_radix(value, 16) {
+        Ok(key) if key <= 0xF => Ok(key),
+        _ => Err(format!("bad key '{}', expected 0 to F", value)),
+    }
+}
+
+fn exit_with(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(2);
+}
@@ -0,0 +1,46 @@
+//! Prints a ROM as assembly.
+//!
+//! ```text
+//! rust-8-disasm GAME [--syntax cowgod|octo]
+//! ```
+
+extern crate rust_8;
+
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::process;
+
+use rust_8::disasm::{self, Syntax};
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let mut file_name = None;
+    let mut syntax = Syntax::Cowgod;
+    while let Some(arg) = args.next() {
+        if arg == "--syntax" {
+            let name = args.next().unwrap_or_default();
+            syntax = match name.parse() {
+                Ok(syntax) => syntax,
+                Err(message) => exit_with(&message),
+            };
+        } else {
+            file_name = Some(arg);
+        }
+    }
+    let file_name = match file_name {
+        Some(file_name) => file_name,
+        None => exit_with("Usage: rust-8-disasm GAME [--syntax cowgod|octo]"),
+    };
+
+    let mut game_data = Vec::new();
+    if let Err(error) = File::open(&file_name).and_then(|mut file| file.read_to_end(&mut game_data)) {
+        exit_with(&format!("Could not read {}: {}", file_name, error));
+    }
+    print!("{}", disasm::disassemble(&game_data, syntax));
+}
+
+fn exit_with(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(2);
+}
@@ -0,0 +1,351 @@
+//! Runs a ROM without a window and prints the screen and registers it ends with.
+//!
+//! ```text
+//! rust-8-headless GAME [--frames N] [--until-pc ADDR] [--until-opcode OPCODE]
+//!                      [--keys FRAME:+KEY,FRAME:-KEY,...] [--platform NAME]
+//!                      [--quirks NAME] [--format ascii|pbm] [--movie FILE] [--trace FILE]
+//!                      [--trace-format text|binary] [--trace-pc FIRST-LAST]
+//!                      [--trace-frames FIRST-LAST]
+//! ```
+//!
+//! Addresses, opcodes and keys are hex. `--keys 30:+5,40:-5` holds key 5
+//! down from the start of frame 30 until the start of frame 40.
+//!
+//! `--platform` and `--quirks` default the same way as in the desktop
+//! frontend, for a game that isn't in the ROM database.
+//!
+//! `--movie` plays back a movie recorded by the desktop frontend, on the
+//! platform and with the seed it was recorded with.
+//!
+//! `--trace` logs every instruction to a file, which `rust-8-tracediff` can
+//! compare with another trace. `--trace-pc 200-2FF` only logs instructions
+//! at those addresses and `--trace-frames 100-200` only those in those frames.
+
+extern crate rust_8;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufWriter, Read};
+use std::process;
+
+use rust_8::{Chip8, Chip8Error, Key, Platform, Quirks};
+use rust_8::movie::{Movie, Playback};
+use rust_8::romdb::RomConfig;
+use rust_8::trace::{TraceFilter, TraceFormat, TraceWriter};
+
+const DEFAULT_FRAMES: u64 = 600;
+
+enum Format {
+    Ascii,
+    Pbm,
+}
+
+struct KeyEvent {
+    frame: u64,
+    key: Key,
+    pressed: bool,
+}
+
+struct Options {
+    rom: String,
+    frames: u64,
+    until_pc: Option<u16>,
+    until_opcode: Option<u16>,
+    keys: Vec<KeyEvent>,
+    platform: Option<Platform>,
+    quirks: Option<Quirks>,
+    format: Format,
+    movie: Option<String>,
+    trace: Option<String>,
+    trace_format: TraceFormat,
+    trace_filter: TraceFilter,
+}
+
+fn main() {
+    let options = match parse_args(env::args().skip(1).collect()) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}", message);
+            process::exit(2);
+        }
+    };
+
+    let mut game_data = Vec::new();
+    if let Err(error) = File::open(&options.rom).and_then(|mut file| file.read_to_end(&mut game_data)) {
+        eprintln!("Could not read {}: {}", options.rom, error);
+        process::exit(1);
+    }
+
+    let movie = options.movie.as_ref().map(|path| {
+        match fs::read_to_string(path).map_err(|error| error.to_string()).and_then(|text| Movie::parse(&text)) {
+            Ok(movie) => movie,
+            Err(error) => {
+                eprintln!("Could not read {}: {}", path, error);
+                process::exit(1);
+            }
+        }
+    });
+
+    let (platform, quirks) = RomConfig::default().machine(options.platform, options.quirks);
+    let computer = match movie {
+        Some(ref movie) => movie.start(game_data),
+        None => Chip8::new(game_data, platform, quirks).map_err(|error| error.to_string()),
+    };
+    let mut computer = match computer {
+        Ok(computer) => computer,
+        Err(error) => {
+            eprintln!("Could not load the game: {}", error);
+            process::exit(1);
+        }
+    };
+
+    if let Some(ref path) = options.trace {
+        match File::create(path) {
+            Ok(file) => {
+                let writer = TraceWriter::new(BufWriter::new(file), options.trace_format, options.trace_filter.clone());
+                computer.tracer = Some(Box::new(writer));
+            }
+            Err(error) => {
+                eprintln!("Could not create {}: {}", path, error);
+                process::exit(1);
+            }
+        }
+    }
+
+    let result = run(&mut computer, &options, movie.as_ref().map(Playback::new));
+    // Finishes writing the trace, which exiting wouldn't
+    computer.tracer = None;
+    match result {
+        Ok(Some(frame)) => eprintln!("Stopped in frame {}", frame),
+        Ok(None) => eprintln!("Ran {} frames", options.frames),
+        Err(_) => {}
+    }
+
+    match options.format {
+        Format::Ascii => print!("{}", computer.display.to_ascii()),
+        Format::Pbm => print!("{}", computer.display.to_pbm()),
+    }
+    print_registers(&computer);
+
+    if let Err(error) = result {
+        eprintln!("The emulator stopped: {}", error);
+        process::exit(1);
+    }
+}
+
+/// Runs the frames asked for, and returns the frame it stopped early in if
+/// it reached `--until-pc` or `--until-opcode` or the program exited.
+fn run(computer: &mut Chip8, options: &Options, mut playback: Option<Playback>) -> Result<Option<u64>, Chip8Error> {
+    for frame in 0..options.frames {
+        if let Some(ref mut playback) = playback {
+            playback.apply(computer);
+        }
+        for event in options.keys.iter().filter(|event| event.frame == frame) {
+            if event.pressed {
+                computer.handle_key_press(event.key);
+            } else {
+                computer.handle_key_release(event.key);
+            }
+        }
+
+        let stopped = computer.step_frame_until(|computer| {
+            options.until_pc == Some(computer.program_counter()) ||
+            (options.until_opcode.is_some() && options.until_opcode == computer.current_opcode())
+        })?;
+
+        if stopped || computer.has_exited() {
+            return Ok(Some(frame));
+        }
+    }
+    Ok(None)
+}
+
+fn print_registers(computer: &Chip8) {
+    for (number, value) in computer.registers().iter().enumerate() {
+        print!("V{:X}={:02X} ", number, value);
+    }
+    println!();
+    println!("I={:04X} PC={:04X} SP={} DT={} ST={}",
+             computer.i_register(),
+             computer.program_counter(),
+             computer.stack_pointer(),
+             computer.delay_timer(),
+             computer.sound_timer());
+}
+
+fn parse_args(args: Vec<String>) -> Result<Options, String> {
+    let mut options = Options {
+        rom: String::new(),
+        frames: DEFAULT_FRAMES,
+        until_pc: None,
+        until_opcode: None,
+        keys: Vec::new(),
+        platform: None,
+        quirks: None,
+        format: Format::Ascii,
+        movie: None,
+        trace: None,
+        trace_format: TraceFormat::Text,
+        trace_filter: TraceFilter::default(),
+    };
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            options.rom = arg;
+            continue;
+        }
+
+        let value = args.next().ok_or(format!("{} needs a value", arg))?;
+        match arg.as_str() {
+            "--frames" => {
+                options.frames = value.parse().map_err(|_| format!("bad frame count '{}'", value))?
+            }
+            "--until-pc" => options.until_pc = Some(parse_hex(&value)?),
+            "--until-opcode" => options.until_opcode = Some(parse_hex(&value)?),
+            "--keys" => options.keys = parse_keys(&value)?,
+            "--platform" => options.platform = Some(value.parse()?),
+            "--quirks" => options.quirks = Some(value.parse()?),
+            "--movie" => options.movie = Some(value),
+            "--trace" => options.trace = Some(value),
+            "--trace-format" => {
+                options.trace_format = match value.as_str() {
+                    "text" => TraceFormat::Text,
+                    "binary" => TraceFormat::Binary,
+                    _ => return Err(format!("unknown trace format '{}', expected text or binary", value)),
+                }
+            }
+            "--trace-pc" => {
+                let (first, last) = parse_range(&value)?;
+                options.trace_filter.addresses = Some((parse_hex(first)?, parse_hex(last)?));
+            }
+            "--trace-frames" => {
+                let (first, last) = parse_range(&value)?;
+                let frame = |frame: &str| frame.parse().map_err(|_| format!("bad frame '{}'", frame));
+                options.trace_filter.frames = Some((frame(first)?, frame(last)?));
+            }
+            "--format" => {
+                options.format = match value.as_str() {
+                    "ascii" => Format::Ascii,
+                    "pbm" => Format::Pbm,
+                    _ => return Err(format!("unknown format '{}', expected ascii or pbm", value)),
+                }
+            }
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+
+    if options.rom.is_empty() {
+        return Err("Usage: rust-8-headless GAME [--frames N] [--until-pc ADDR] \
+                    [--until-opcode OPCODE] [--keys FRAME:+KEY,...] [--platform NAME] \
+                    [--quirks NAME] [--format ascii|pbm] [--movie FILE] [--trace FILE] \
+                    [--trace-format text|binary] [--trace-pc FIRST-LAST] \
+                    [--trace-frames FIRST-LAST]"
+            .to_string());
+    }
+    Ok(options)
+}
+
+fn parse_hex(value: &str) -> Result<u16, String> {
+    let digits = value.trim_start_matches("0x");
+    u16::from_str_radix(digits, 16).map_err(|_| format!("bad hex number '{}'", value))
+}
+
+fn parse_range(value: &str) -> Result<(&str, &str), String> {
+    let mut ends = value.splitn(2, '-');
+    match (ends.next(), ends.next()) {
+        (Some(first), Some(last)) => Ok((first, last)),
+        _ => Err(format!("bad range '{}', expected FIRST-LAST", value)),
+    }
+}
+
+fn parse_keys(script: &str) -> Result<Vec<KeyEvent>, String> {
+    script.split(',')
+        .map(|event| {
+            let bad_event = || format!("bad key event '{}', expected FRAME:+KEY or FRAME:-KEY", event);
+            let mut parts = event.splitn(2, ':');
+            let frame = parts.next().and_then(|frame| frame.parse().ok()).ok_or_else(&bad_event)?;
+            let action = parts.next().ok_or_else(&bad_event)?;
+            let pressed = action.starts_with('+');
+            if !pressed && !action.starts_with('-') {
+                return Err(bad_event());
+            }
+            let key = u8::from_str_radix(&action[1..], 16).map_err(|_| bad_event())?;
+            if key > 0xF {
+                return Err(bad_event());
+            }
+            Ok(KeyEvent {
+                frame,
+                key,
+                pressed,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Exits once key 5 is held.
+    const ROM: [u8; 8] = [0x65, 0x05, // V5 := 5
+                          0xE5, 0x9E, // loop: if V5 -key then
+                          0x12, 0x02, //   jump loop
+                          0x00, 0xFD]; // exit
+
+    fn options(args: &[&str]) -> Options {
+        parse_args(args.iter().map(|arg| arg.to_string()).collect()).unwrap()
+    }
+
+    fn machine(options: &Options) -> Chip8 {
+        let (platform, quirks) = RomConfig::default().machine(options.platform, options.quirks);
+        Chip8::new(ROM.to_vec(), platform, quirks).unwrap()
+    }
+
+    #[test]
+    fn key_scripts_give_a_frame_and_a_key_to_press_or_release() {
+        let events = parse_keys("30:+5,40:-a").unwrap();
+        let events: Vec<(u64, Key, bool)> = events.iter().map(|event| (event.frame, event.key, event.pressed)).collect();
+        assert_eq!(events, vec![(30, 0x5, true), (40, 0xA, false)]);
+
+        for &script in ["30", "30:5", "x:+5", "30:+10", "30:+5,"].iter() {
+            assert!(parse_keys(script).is_err(), "{}", script);
+        }
+    }
+
+    #[test]
+    fn ranges_split_at_the_first_dash() {
+        assert_eq!(parse_range("200-2FF"), Ok(("200", "2FF")));
+        assert!(parse_range("200").is_err());
+    }
+
+    #[test]
+    fn runs_until_the_frames_are_used_up_or_the_program_stops() {
+        let options = options(&["game.ch8", "--frames", "10"]);
+        let mut computer = machine(&options);
+        assert_eq!(run(&mut computer, &options, None), Ok(None));
+        assert_eq!(computer.frame_count(), 10);
+
+        let options = self::options(&["game.ch8", "--frames", "10", "--keys", "3:+5"]);
+        let mut computer = machine(&options);
+        assert_eq!(run(&mut computer, &options, None), Ok(Some(3)));
+        assert!(computer.has_exited());
+
+        let options = self::options(&["game.ch8", "--keys", "3:+5", "--until-pc", "206"]);
+        let mut computer = machine(&options);
+        assert_eq!(run(&mut computer, &options, None), Ok(Some(3)));
+        assert_eq!((computer.program_counter(), computer.has_exited()), (0x206, false));
+    }
+
+    #[test]
+    fn platform_and_quirks_default_like_the_desktop() {
+        let options = options(&["game.ch8", "--keys", "0:+5"]);
+        assert_eq!(machine(&options).platform(), Platform::SuperChip);
+        assert_eq!(run(&mut machine(&options), &options, None), Ok(Some(0)));
+
+        // 00FD isn't an instruction before SUPER-CHIP
+        let options = self::options(&["game.ch8", "--keys", "0:+5", "--platform", "chip8", "--quirks", "modern"]);
+        assert_eq!((options.platform, options.quirks), (Some(Platform::Chip8), Some(Quirks::MODERN)));
+        assert_eq!(run(&mut machine(&options), &options, None), Err(Chip8Error::UnknownOpcode(0x206, 0x00FD)));
+    }
+}
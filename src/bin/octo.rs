@@ -0,0 +1,41 @@
+//! Compiles an Octo program into a ROM.
+//!
+//! ```text
+//! rust-8-octo SOURCE OUTPUT
+//! ```
+
+extern crate rust_8;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::process;
+
+use rust_8::octo;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() != 2 {
+        eprintln!("Usage: rust-8-octo SOURCE OUTPUT");
+        process::exit(2);
+    }
+
+    let source = match fs::read_to_string(&args[0]) {
+        Ok(source) => source,
+        Err(error) => {
+            eprintln!("Could not read {}: {}", args[0], error);
+            process::exit(1);
+        }
+    };
+    let rom = match octo::compile(&source) {
+        Ok(rom) => rom,
+        Err(error) => {
+            eprintln!("{}:{}", args[0], error);
+            process::exit(1);
+        }
+    };
+    if let Err(error) = File::create(&args[1]).and_then(|mut file| file.write_all(&rom)) {
+        eprintln!("Could not write {}: {}", args[1], error);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,60 @@
+//! Compares two execution traces and shows where they first differ.
+//!
+//! ```text
+//! rust-8-tracediff FIRST SECOND
+//! ```
+//!
+//! Either trace can be text or binary, so a binary trace from the headless
+//! runner can be checked against a text trace converted from another
+//! emulator. Exits with 1 if the traces differ.
+
+extern crate rust_8;
+
+use std::env;
+use std::fs;
+use std::process;
+
+use rust_8::trace::{self, TraceEntry};
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() != 2 {
+        eprintln!("Usage: rust-8-tracediff FIRST SECOND");
+        process::exit(2);
+    }
+
+    let first = read_trace(&args[0]);
+    let second = read_trace(&args[1]);
+    let index = match trace::first_difference(&first, &second) {
+        Some(index) => index,
+        None => {
+            println!("The traces match for all {} instructions", first.len());
+            return;
+        }
+    };
+
+    println!("The traces differ at instruction {}:", index);
+    if index > 0 {
+        println!("  both:   {}", first[index - 1]);
+    }
+    println!("  first:  {}", describe(first.get(index)));
+    println!("  second: {}", describe(second.get(index)));
+    process::exit(1);
+}
+
+fn read_trace(path: &str) -> Vec<TraceEntry> {
+    match fs::read(path).map_err(|error| error.to_string()).and_then(|bytes| trace::read(&bytes)) {
+        Ok(entries) => entries,
+        Err(error) => {
+            eprintln!("Could not read {}: {}", path, error);
+            process::exit(2);
+        }
+    }
+}
+
+fn describe(entry: Option<&TraceEntry>) -> String {
+    match entry {
+        Some(entry) => entry.to_string(),
+        None => "(the trace ends)".to_string(),
+    }
+}
@@ -1,18 +1,87 @@
+use std::error::Error;
 use std::fmt;
 
+#[cfg(feature = "os-random")]
 extern crate rand;
 
-use instruction::{Instruction, RawInstruction};
-use display::{Display,SPRITES};
-use rand::distributions::{IndependentSample, Range};
+use audio::{AudioSink, SilentSink, DEFAULT_PITCH, PATTERN_SIZE};
+use instruction::{Address, Instruction, RawInstruction, Register, LOAD_LONG_I};
+use display::{Collision, Display, LARGE_SPRITES, SPRITES};
+use platform::Platform;
+use quirks::Quirks;
+use random::{RandomSource, XorShift};
+use sha1::{self, Hash};
+use state::{StateError, StateReader, StateWriter};
+use trace::{TraceEntry, Tracer};
 
-const NUM_GENERAL_PURPOSE_REGS: usize = 16;
-const MEMORY_SIZE: usize = 4 * 1024;
+pub const NUM_GENERAL_PURPOSE_REGS: usize = 16;
 const NUM_STACK_FRAMES: usize = 16;
 const PROGRAM_CODE_OFFSET: usize = 0x200;
-const CLOCK_RATE: f64 = 600.0;
-const NUM_KEYS: usize = 16;
+/// Instructions run per second unless `set_clock_rate` says otherwise.
+pub const DEFAULT_CLOCK_RATE: f64 = 600.0;
+const TIMER_RATE: f64 = 60.0;
+pub const NUM_KEYS: usize = 16;
+const NUM_FLAGS: usize = 16;
+const LARGE_SPRITES_OFFSET: usize = 0x50;
+/// Stands in for `key_to_wait_for` being `None` in save states.
+const NO_KEY_TO_WAIT_FOR: u8 = 0xFF;
 
+/// One of the sixteen keys on the hex keypad, from 0x0 to 0xF.
+pub type Key = u8;
+
+/// A different seed for every machine, from the operating system.
+#[cfg(feature = "os-random")]
+fn new_seed() -> u64 {
+    rand::random()
+}
+
+/// Without the operating system's random numbers, such as on the web, every
+/// machine starts from the same seed until `set_seed` is called.
+#[cfg(not(feature = "os-random"))]
+fn new_seed() -> u64 {
+    0
+}
+
+/// Why the machine had to stop running a program.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Chip8Error {
+    /// The word at the given address does not decode to an instruction.
+    UnknownOpcode(Address, u16),
+    /// A call was made with every stack frame already in use.
+    StackOverflow,
+    /// A return was made with no call to return from.
+    StackUnderflow,
+    /// An instruction touched memory past the end of RAM, starting at the given address.
+    MemoryOutOfBounds(usize),
+    /// The ROM of the given size does not fit in program memory.
+    RomTooLarge(usize),
+    /// A snapshot the machine took of itself, such as one kept for
+    /// rewinding, could not be restored.
+    BadSnapshot(StateError),
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Chip8Error::UnknownOpcode(pc, raw) => {
+                write!(f, "unknown opcode {:04X} at address {:03X}", raw, pc)
+            }
+            Chip8Error::StackOverflow => write!(f, "stack overflow"),
+            Chip8Error::StackUnderflow => write!(f, "return with an empty stack"),
+            Chip8Error::MemoryOutOfBounds(addr) => {
+                write!(f, "memory access out of bounds at address {:03X}", addr)
+            }
+            Chip8Error::RomTooLarge(size) => {
+                write!(f, "ROM is {} bytes, which is too large to fit in memory", size)
+            }
+            Chip8Error::BadSnapshot(ref error) => write!(f, "could not restore a snapshot: {}", error),
+        }
+    }
+}
+
+impl Error for Chip8Error {}
+
+/// The virtual machine: CPU, memory, timers, keypad, screen and buzzer.
 pub struct Chip8 {
     regs: [u8; NUM_GENERAL_PURPOSE_REGS],
     i_reg: u16,
@@ -20,256 +89,500 @@ pub struct Chip8 {
     sound_timer_reg: u8,
     stack_pointer_reg: u8,
     program_counter_reg: u16,
-    memory: [u8; MEMORY_SIZE],
+    memory: Vec<u8>,
     stack: [u16; NUM_STACK_FRAMES],
-    key_to_wait_for: Option<u8>,
+    key_to_wait_for: Option<Register>,
     keyboard: [bool; NUM_KEYS],
+    flags: [u8; NUM_FLAGS],
+    exited: bool,
+    beeping: bool,
+    pending_frames: f64,
+    pending_instructions: f64,
+    clock_rate: f64,
+    frame_count: u64,
+    platform: Platform,
+    quirks: Quirks,
+    audio_pattern: [u8; PATTERN_SIZE],
+    pitch: u8,
+    rom_hash: Hash,
+    seed: u64,
     pub display: Box<Display>,
+    pub audio: Box<dyn AudioSink>,
+    pub random: Box<dyn RandomSource>,
+    /// Told about every instruction before it runs, if set.
+    pub tracer: Option<Box<dyn Tracer>>,
 }
 
 impl Chip8 {
-    pub fn new(program: Vec<u8>) -> Chip8 {
-        let mut memory = [0; MEMORY_SIZE];
+    /// Loads a program at 0x200, ready to run on the given platform with the given quirks.
+    pub fn new(program: Vec<u8>, platform: Platform, quirks: Quirks) -> Result<Chip8, Chip8Error> {
+        if program.len() > platform.memory_size() - PROGRAM_CODE_OFFSET {
+            return Err(Chip8Error::RomTooLarge(program.len()));
+        }
+
+        let rom_hash = sha1::sha1(&program);
+        let seed = new_seed();
+        let mut memory = vec![0; platform.memory_size()];
         // TODO: do this more efficiently
         for (i, byte) in program.iter().enumerate() {
-            memory[PROGRAM_CODE_OFFSET + i] = byte.clone();
+            memory[PROGRAM_CODE_OFFSET + i] = *byte;
         }
         for (i, byte) in SPRITES.iter().enumerate() {
-            memory[i] = byte.clone();
+            memory[i] = *byte;
+        }
+        for (i, byte) in LARGE_SPRITES.iter().enumerate() {
+            memory[LARGE_SPRITES_OFFSET + i] = *byte;
         }
 
-        Chip8 {
+        Ok(Chip8 {
             regs: [0; NUM_GENERAL_PURPOSE_REGS],
             i_reg: 0,
             delay_timer_reg: 0,
             sound_timer_reg: 0,
             stack_pointer_reg: 0,
             program_counter_reg: PROGRAM_CODE_OFFSET as u16,
-            memory: memory,
+            memory,
             stack: [0; NUM_STACK_FRAMES],
             key_to_wait_for: None,
             keyboard: [false; NUM_KEYS],
+            flags: [0; NUM_FLAGS],
+            exited: false,
+            beeping: false,
+            pending_frames: 0.0,
+            pending_instructions: 0.0,
+            clock_rate: DEFAULT_CLOCK_RATE,
+            frame_count: 0,
+            platform,
+            quirks,
+            audio_pattern: [0; PATTERN_SIZE],
+            pitch: DEFAULT_PITCH,
+            rom_hash,
+            seed,
             display: Box::new(Display::new()),
+            audio: Box::new(SilentSink),
+            random: Box::new(XorShift::new(seed)),
+            tracer: None,
+        })
+    }
+
+    /// Runs the machine for the given amount of wall-clock time.
+    ///
+    /// Time is handed out in whole 60 Hz frames; whatever is left over is
+    /// carried into the next call so no time is lost to rounding.
+    pub fn cycle(&mut self, seconds_since_last_cycle: f64) -> Result<(), Chip8Error> {
+        self.pending_frames += seconds_since_last_cycle * TIMER_RATE;
+
+        while self.pending_frames >= 1.0 {
+            self.pending_frames -= 1.0;
+            self.step_frame()?;
         }
+        Ok(())
     }
 
-    pub fn cycle(&mut self, seconds_since_last_cycle: f64) {
-        let num_instructions = (seconds_since_last_cycle * CLOCK_RATE).round() as u64;
+    /// Runs one 60 Hz frame: a frame's worth of instructions followed by a
+    /// single tick of the delay and sound timers.
+    pub fn step_frame(&mut self) -> Result<(), Chip8Error> {
+        self.step_frame_until(|_| false).map(|_| ())
+    }
+
+    /// Like `step_frame`, but checks `stop` before every instruction and
+    /// returns early, without finishing the frame, as soon as it is true.
+    ///
+    /// Returns whether it stopped early. The next call picks the frame up
+    /// where it left off.
+    pub fn step_frame_until<F>(&mut self, mut stop: F) -> Result<bool, Chip8Error>
+        where F: FnMut(&Chip8) -> bool
+    {
+        if self.pending_instructions < 1.0 {
+            self.pending_instructions += self.clock_rate / TIMER_RATE;
+        }
 
-        for _ in 1..num_instructions {
-            if self.delay_timer_reg > 0 {
-                self.delay_timer_reg -= 1;
+        while self.pending_instructions >= 1.0 {
+            if stop(self) {
+                return Ok(true);
             }
+            self.pending_instructions -= 1.0;
+            self.step_instruction()?;
+        }
 
-            if self.key_to_wait_for == None {
-                let instruction = self.instruction();
-                self.program_counter_reg = self.run_instruction(&instruction);
+        self.tick_timers();
+        self.frame_count += 1;
+        Ok(false)
+    }
+
+    /// Runs a single instruction, unless the machine is waiting for a key or has exited.
+    pub fn step_instruction(&mut self) -> Result<(), Chip8Error> {
+        if self.key_to_wait_for.is_none() && !self.exited {
+            let instruction = self.instruction()?;
+            if self.tracer.is_some() {
+                let entry = self.trace_entry()?;
+                if let Some(ref mut tracer) = self.tracer {
+                    tracer.trace(&entry, &instruction);
+                }
             }
+            self.program_counter_reg = self.run_instruction(&instruction)?;
+        }
+        Ok(())
+    }
+
+    fn tick_timers(&mut self) {
+        if self.delay_timer_reg > 0 {
+            self.delay_timer_reg -= 1;
         }
+        if self.sound_timer_reg > 0 {
+            self.sound_timer_reg -= 1;
+        }
+        self.update_beeper();
     }
 
-    fn run_instruction(&mut self, instruction: &Instruction) -> u16 {
-        match *instruction {
+    fn run_instruction(&mut self, instruction: &Instruction) -> Result<Address, Chip8Error> {
+        let next_pc = match *instruction {
             Instruction::ClearDisplay => {
                 self.display.clear();
-                self.program_counter_reg + 2
+                self.next_instruction()?
             }
             Instruction::Return => {
+                if self.stack_pointer_reg == 0 {
+                    return Err(Chip8Error::StackUnderflow);
+                }
                 let addr = self.stack[(self.stack_pointer_reg - 1) as usize];
                 self.stack_pointer_reg -= 1;
-                addr + 2
+                self.offset_address(addr, 2)?
+            }
+            Instruction::ScrollDown(rows) => {
+                let rows = self.scroll_distance(rows as usize);
+                self.display.scroll_down(rows);
+                self.next_instruction()?
+            }
+            Instruction::ScrollUp(rows) => {
+                let rows = self.scroll_distance(rows as usize);
+                self.display.scroll_up(rows);
+                self.next_instruction()?
+            }
+            Instruction::ScrollRight => {
+                let columns = self.scroll_distance(4);
+                self.display.scroll_right(columns);
+                self.next_instruction()?
+            }
+            Instruction::ScrollLeft => {
+                let columns = self.scroll_distance(4);
+                self.display.scroll_left(columns);
+                self.next_instruction()?
+            }
+            Instruction::Exit => {
+                self.exited = true;
+                self.program_counter_reg
+            }
+            Instruction::LowRes => {
+                self.display.set_hires(false);
+                self.next_instruction()?
+            }
+            Instruction::HighRes => {
+                self.display.set_hires(true);
+                self.next_instruction()?
             }
             Instruction::Jump(addr) => addr,
             Instruction::Call(addr) => {
+                if self.stack_pointer_reg as usize == NUM_STACK_FRAMES {
+                    return Err(Chip8Error::StackOverflow);
+                }
                 self.stack_pointer_reg += 1;
                 self.stack[(self.stack_pointer_reg - 1) as usize] = self.program_counter_reg;
                 addr
             }
             Instruction::SkipIfEqualsByte(reg, value) => {
                 if self.read_reg(reg) == value {
-                    self.program_counter_reg + 4
+                    self.skip_next_instruction()?
                 } else {
-                    self.program_counter_reg + 2
+                    self.next_instruction()?
                 }
             }
             Instruction::SkipIfNotEqualsByte(reg, value) => {
                 if self.read_reg(reg) != value {
-                    self.program_counter_reg + 4
+                    self.skip_next_instruction()?
                 } else {
-                    self.program_counter_reg + 2
+                    self.next_instruction()?
                 }
             }
             Instruction::SkipIfEqual(reg1, reg2) => {
                 if self.read_reg(reg1) == self.read_reg(reg2) {
-                    self.program_counter_reg + 4
+                    self.skip_next_instruction()?
                 } else {
-                    self.program_counter_reg + 2
+                    self.next_instruction()?
                 }
             }
+            Instruction::SaveRange(first_reg, last_reg) => {
+                let regs = register_range(first_reg, last_reg);
+                let i = self.i_reg as usize;
+                self.check_memory(i, i + regs.len())?;
+                for (offset, reg_number) in regs.into_iter().enumerate() {
+                    self.memory[i + offset] = self.read_reg(reg_number);
+                }
+                self.next_instruction()?
+            }
+            Instruction::LoadRange(first_reg, last_reg) => {
+                let regs = register_range(first_reg, last_reg);
+                let i = self.i_reg as usize;
+                self.check_memory(i, i + regs.len())?;
+                for (offset, reg_number) in regs.into_iter().enumerate() {
+                    let value = self.memory[i + offset];
+                    self.load_reg(reg_number, value);
+                }
+                self.next_instruction()?
+            }
             Instruction::LoadByte(reg, value) => {
                 self.load_reg(reg, value);
-                self.program_counter_reg + 2
+                self.next_instruction()?
             }
             Instruction::AddByte(reg_number, value) => {
                 let reg_value = self.read_reg(reg_number);
                 self.load_reg(reg_number, value.wrapping_add(reg_value));
-                self.program_counter_reg + 2
+                self.next_instruction()?
             }
             Instruction::Move(reg1, reg2) => {
                 let value = self.read_reg(reg2);
                 self.load_reg(reg1, value);
-                self.program_counter_reg + 2
+                self.next_instruction()?
             }
-            Instruction::Or(_, _) => {
-                panic!("Not yet implemeneted: {:?}", instruction);
+            Instruction::Or(reg1, reg2) => {
+                let first = self.read_reg(reg1);
+                let second = self.read_reg(reg2);
+                self.load_reg(reg1, first | second);
+                self.reset_vf_after_logic();
+                self.next_instruction()?
             }
             Instruction::And(reg1, reg2) => {
                 let first = self.read_reg(reg1);
                 let second = self.read_reg(reg2);
                 self.load_reg(reg1, first & second);
-                self.program_counter_reg + 2
+                self.reset_vf_after_logic();
+                self.next_instruction()?
             }
             Instruction::Xor(reg1, reg2) => {
                 let first = self.read_reg(reg1);
                 let second = self.read_reg(reg2);
                 self.load_reg(reg1, first ^ second);
-                self.program_counter_reg + 2
+                self.reset_vf_after_logic();
+                self.next_instruction()?
             }
             Instruction::Add(reg1, reg2) => {
                 let first = self.read_reg(reg1) as u16;
                 let second = self.read_reg(reg2) as u16;
                 let answer = first + second;
-                self.load_reg(0xF, (answer > 255) as u8);
+                // VF is written after the result so the flag wins when reg1 is VF
                 self.load_reg(reg1, answer as u8);
-                self.program_counter_reg + 2
+                self.load_reg(0xF, (answer > 255) as u8);
+                self.next_instruction()?
             }
             Instruction::Sub(reg1, reg2) => {
                 let first = self.read_reg(reg1);
                 let second = self.read_reg(reg2);
-                self.load_reg(0xF, (first > second) as u8);
                 self.load_reg(reg1, first.wrapping_sub(second));
-                self.program_counter_reg + 2
+                // VF is set when there is no borrow
+                self.load_reg(0xF, (first >= second) as u8);
+                self.next_instruction()?
             }
-            Instruction::ShiftRight(reg) => {
-                let value = self.read_reg(reg);
-                self.load_reg(0xF, value & 0b1);
+            Instruction::ShiftRight(reg, source_reg) => {
+                let value = self.read_shift_source(reg, source_reg);
                 self.load_reg(reg, value >> 1);
-                self.program_counter_reg + 2
+                self.load_reg(0xF, value & 0b1);
+                self.next_instruction()?
             }
-            Instruction::ReverseSub(_, _) => {
-                panic!("Not yet implemeneted: {:?}", instruction);
+            Instruction::ReverseSub(reg1, reg2) => {
+                let first = self.read_reg(reg1);
+                let second = self.read_reg(reg2);
+                self.load_reg(reg1, second.wrapping_sub(first));
+                self.load_reg(0xF, (second >= first) as u8);
+                self.next_instruction()?
             }
-            Instruction::ShiftLeft(reg) => {
-                let value = self.read_reg(reg);
-                self.load_reg(0xF, value >> 7);
+            Instruction::ShiftLeft(reg, source_reg) => {
+                let value = self.read_shift_source(reg, source_reg);
                 self.load_reg(reg, value << 1);
-                self.program_counter_reg + 2
+                self.load_reg(0xF, value >> 7);
+                self.next_instruction()?
             }
             Instruction::SkipIfNotEqual(reg1, reg2) => {
                 let first = self.read_reg(reg1);
                 let second = self.read_reg(reg2);
                 if first != second {
-                    self.program_counter_reg + 4
+                    self.skip_next_instruction()?
                 } else {
-                    self.program_counter_reg + 2
+                    self.next_instruction()?
                 }
             }
             Instruction::LoadI(value) => {
                 self.i_reg = value;
-                self.program_counter_reg + 2
+                self.next_instruction()?
             }
-            Instruction::JumpPlusZero(_) => {
-                panic!("Not yet implemeneted: {:?}", instruction);
+            Instruction::LoadLongI(addr) => {
+                self.i_reg = addr;
+                self.offset_address(self.program_counter_reg, 4)?
+            }
+            Instruction::JumpPlusZero(addr) => {
+                let offset_reg = if self.quirks.jump_uses_vx {
+                    (addr >> 8) as u8
+                } else {
+                    0
+                };
+                addr + self.read_reg(offset_reg) as u16
             }
             Instruction::Random(reg, value) => {
-                let rng = &mut rand::thread_rng();
-                let rand_number = Range::new(0, 255).ind_sample(rng);
+                let rand_number = self.random.next_byte();
 
                 self.load_reg(reg, rand_number & value);
-                self.program_counter_reg + 2
+                self.next_instruction()?
+            }
+            Instruction::SelectPlanes(planes) => {
+                self.display.select_planes(planes);
+                self.next_instruction()?
             }
             Instruction::Draw(reg1, reg2, n) => {
                 let x = self.read_reg(reg1);
                 let y = self.read_reg(reg2);
                 let from = self.i_reg as usize;
-                let to = from + (n as usize);
+                let to = from + (n as usize) * self.display.selected_plane_count();
+                self.check_memory(from, to)?;
+
+                let wrap = self.quirks.wrap_sprites;
+                let collision = self.display.draw(x, y, &self.memory[from..to], wrap);
+                self.regs[0xF] = self.collision_flag(collision);
+                self.next_instruction()?
+            }
+            Instruction::DrawLarge(reg1, reg2) => {
+                let x = self.read_reg(reg1);
+                let y = self.read_reg(reg2);
+                // SUPER-CHIP 1.1 only draws 8x16 sprites in lo-res mode
+                let narrow = self.platform == Platform::SuperChip && !self.display.is_hires();
+                let sprite_size = if narrow { 16 } else { 32 };
+                let from = self.i_reg as usize;
+                let to = from + sprite_size * self.display.selected_plane_count();
+                self.check_memory(from, to)?;
 
-                self.regs[0xF] = self.display.draw(x, y, &self.memory[from..to]) as u8;
-                self.program_counter_reg + 2
+                let wrap = self.quirks.wrap_sprites;
+                let collision = if narrow {
+                    self.display.draw(x, y, &self.memory[from..to], wrap)
+                } else {
+                    self.display.draw_large(x, y, &self.memory[from..to], wrap)
+                };
+                self.regs[0xF] = self.collision_flag(collision);
+                self.next_instruction()?
             }
             Instruction::SkipIfPressed(reg) => {
                 let value = self.read_reg(reg);
-                let pressed = self.keyboard[value as usize];
+                let pressed = self.keyboard[(value & 0xF) as usize];
                 if pressed {
-                    self.program_counter_reg + 4
+                    self.skip_next_instruction()?
                 } else {
-                    self.program_counter_reg + 2
+                    self.next_instruction()?
                 }
             }
             Instruction::SkipIfNotPressed(reg) => {
                 let value = self.read_reg(reg);
-                let pressed = self.keyboard[value as usize];
+                let pressed = self.keyboard[(value & 0xF) as usize];
                 if !pressed {
-                    self.program_counter_reg + 4
+                    self.skip_next_instruction()?
                 } else {
-                    self.program_counter_reg + 2
+                    self.next_instruction()?
                 }
             }
             Instruction::LoadDelayTimer(reg) => {
                 let delay_value = self.delay_timer_reg;
                 self.load_reg(reg, delay_value);
-                self.program_counter_reg + 2
+                self.next_instruction()?
             }
             Instruction::WaitForKeyPress(reg) => {
                 // TODO rename key_to_wait_for
                 self.key_to_wait_for = Some(reg);
-                self.program_counter_reg + 2
+                self.next_instruction()?
             }
             Instruction::SetDelayTimer(reg) => {
                 let value = self.read_reg(reg);
                 self.delay_timer_reg = value;
-                self.program_counter_reg + 2
+                self.next_instruction()?
             }
-            Instruction::SetSoundTimer(_) => {
-                // TODO: set sound timer
-                self.program_counter_reg + 2
+            Instruction::SetSoundTimer(reg) => {
+                let value = self.read_reg(reg);
+                self.sound_timer_reg = value;
+                self.update_beeper();
+                self.next_instruction()?
             }
             Instruction::AddToI(reg) => {
                 let value = self.read_reg(reg) as u16;
-                self.i_reg = self.i_reg + value;
-                self.program_counter_reg + 2
+                self.i_reg = self.i_reg.wrapping_add(value);
+                self.next_instruction()?
             }
             Instruction::LoadSprite(reg) => {
                 let digit = self.read_reg(reg);
-                self.i_reg = (digit * 5) as u16;
-                self.program_counter_reg + 2
+                self.i_reg = (digit & 0xF) as u16 * 5;
+                self.next_instruction()?
+            }
+            Instruction::LoadLargeSprite(reg) => {
+                let digit = self.read_reg(reg);
+                self.i_reg = (LARGE_SPRITES_OFFSET + (digit & 0xF) as usize * 10) as u16;
+                self.next_instruction()?
             }
             Instruction::BCDRepresentation(reg) => {
                 let value = self.read_reg(reg);
+                self.check_memory(self.i_reg as usize, self.i_reg as usize + 3)?;
                 self.memory[self.i_reg as usize] = (value / 100) % 10;
                 self.memory[(self.i_reg + 1) as usize] = (value / 10) % 10;
                 self.memory[(self.i_reg + 2) as usize] = value % 10;
-                self.program_counter_reg + 2
+                self.next_instruction()?
             }
             Instruction::StoreRegisters(highest_reg) => {
                 let i = self.i_reg;
+                self.check_memory(i as usize, i as usize + highest_reg as usize + 1)?;
                 for reg_number in 0..(highest_reg + 1) {
                     self.memory[(i + reg_number as u16) as usize] = self.read_reg(reg_number);
                 }
-                self.program_counter_reg + 2
+                self.increment_i_after_load_store(highest_reg);
+                self.next_instruction()?
             }
             Instruction::LoadRegisters(highest_reg) => {
                 let i = self.i_reg;
+                self.check_memory(i as usize, i as usize + highest_reg as usize + 1)?;
                 for reg_number in 0..(highest_reg + 1) {
                     let value = self.memory[(i + reg_number as u16) as usize];
                     self.load_reg(reg_number, value);
                 }
-                self.program_counter_reg + 2
+                self.increment_i_after_load_store(highest_reg);
+                self.next_instruction()?
             }
-        }
+            Instruction::StoreFlags(highest_reg) => {
+                for reg_number in 0..((highest_reg & 0xF) + 1) {
+                    self.flags[reg_number as usize] = self.read_reg(reg_number);
+                }
+                self.next_instruction()?
+            }
+            Instruction::LoadFlags(highest_reg) => {
+                for reg_number in 0..((highest_reg & 0xF) + 1) {
+                    let value = self.flags[reg_number as usize];
+                    self.load_reg(reg_number, value);
+                }
+                self.next_instruction()?
+            }
+            Instruction::LoadAudioPattern => {
+                let i = self.i_reg as usize;
+                self.check_memory(i, i + PATTERN_SIZE)?;
+                self.audio_pattern.copy_from_slice(&self.memory[i..i + PATTERN_SIZE]);
+                self.audio.set_pattern(self.audio_pattern, self.pitch);
+                self.next_instruction()?
+            }
+            Instruction::SetPitch(reg) => {
+                self.pitch = self.read_reg(reg);
+                self.audio.set_pattern(self.audio_pattern, self.pitch);
+                self.next_instruction()?
+            }
+        };
+        Ok(next_pc)
     }
 
-    pub fn handle_key_press(&mut self, key: u8) {
+    /// Presses one of the keys from 0 to 0xF. Others are ignored, since keys
+    /// can come from files such as keymaps and movies.
+    pub fn handle_key_press(&mut self, key: Key) {
+        if key as usize >= NUM_KEYS {
+            return;
+        }
         self.keyboard[key as usize] = true;
         if let Some(reg) = self.key_to_wait_for {
             self.load_reg(reg, key);
@@ -277,29 +590,383 @@ impl Chip8 {
         }
     }
 
-    pub fn handle_key_release(&mut self, key: u8) {
+    /// Releases one of the keys from 0 to 0xF. Others are ignored.
+    pub fn handle_key_release(&mut self, key: Key) {
+        if key as usize >= NUM_KEYS {
+            return;
+        }
         self.keyboard[key as usize] = false;
     }
 
-    fn instruction(&self) -> Instruction {
-        let pc = self.program_counter_reg;
-        let higher_order = (self.memory[pc as usize] as u16) << 8;
-        let lower_order = self.memory[(pc + 1) as usize] as u16;
-        RawInstruction::new(higher_order + lower_order)
-            .to_instruction()
-            .expect("Unrecognized instruction")
+    /// V0 to VF.
+    pub fn registers(&self) -> [u8; NUM_GENERAL_PURPOSE_REGS] {
+        self.regs
+    }
+
+    pub fn i_register(&self) -> Address {
+        self.i_reg
+    }
+
+    pub fn program_counter(&self) -> Address {
+        self.program_counter_reg
+    }
+
+    /// The number of stack frames in use.
+    pub fn stack_pointer(&self) -> u8 {
+        self.stack_pointer_reg
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer_reg
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer_reg
+    }
+
+    /// The return addresses of the calls in progress, oldest first.
+    pub fn stack(&self) -> &[Address] {
+        &self.stack[..self.stack_pointer_reg as usize]
+    }
+
+    /// Whether the machine is stopped waiting for FX0A's key press.
+    pub fn is_waiting_for_key(&self) -> bool {
+        self.key_to_wait_for.is_some()
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// The raw opcode at the program counter, if the program counter is in memory.
+    pub fn current_opcode(&self) -> Option<u16> {
+        self.read_word(self.program_counter_reg as usize).ok()
+    }
+
+    /// The number of whole frames run since the program started.
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// The number of instructions run per second.
+    pub fn clock_rate(&self) -> f64 {
+        self.clock_rate
+    }
+
+    /// Changes how many instructions are run per second. The timers keep
+    /// ticking at 60 Hz whatever the clock rate.
+    pub fn set_clock_rate(&mut self, instructions_per_second: f64) {
+        self.clock_rate = instructions_per_second;
+    }
+
+    pub fn platform(&self) -> Platform {
+        self.platform
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// The seed the random numbers of CXNN were started from. Machines are
+    /// given a different one each time they are created. It means nothing
+    /// once `random` has been replaced with another kind of source.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Starts the random numbers over from `seed` with a `XorShift`, so that
+    /// the same inputs give the same game every time.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.random = Box::new(XorShift::new(seed));
+    }
+
+    /// The SHA-1 of the ROM the machine was created with.
+    pub fn rom_hash(&self) -> Hash {
+        self.rom_hash
+    }
+
+    /// Snapshots the whole machine. The snapshot can only be loaded back into
+    /// a machine running the same ROM on the same platform.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.header(self.platform as u8, &self.rom_hash);
+
+        writer.bytes(&self.regs);
+        writer.u16(self.i_reg);
+        writer.u8(self.delay_timer_reg);
+        writer.u8(self.sound_timer_reg);
+        writer.u8(self.stack_pointer_reg);
+        writer.u16(self.program_counter_reg);
+        for frame in self.stack.iter() {
+            writer.u16(*frame);
+        }
+        writer.u8(self.key_to_wait_for.unwrap_or(NO_KEY_TO_WAIT_FOR));
+        for key in self.keyboard.iter() {
+            writer.bool(*key);
+        }
+        writer.bytes(&self.flags);
+        writer.bool(self.exited);
+        writer.f64(self.pending_frames);
+        writer.f64(self.pending_instructions);
+        writer.u64(self.frame_count);
+        writer.bytes(&self.audio_pattern);
+        writer.u8(self.pitch);
+        let random_state = self.random.save_state();
+        writer.u16(random_state.len() as u16);
+        writer.bytes(&random_state);
+        self.display.save_state(&mut writer);
+        writer.bytes(&self.memory);
+
+        writer.into_bytes()
+    }
+
+    /// Restores a snapshot taken with `save_state`. If the snapshot can't be
+    /// loaded the machine is left as it was.
+    pub fn load_state(&mut self, snapshot: &[u8]) -> Result<(), StateError> {
+        let mut reader = StateReader::new(snapshot);
+        let version = reader.header(self.platform as u8, &self.rom_hash)?;
+
+        let mut regs = [0; NUM_GENERAL_PURPOSE_REGS];
+        regs.copy_from_slice(reader.bytes(NUM_GENERAL_PURPOSE_REGS)?);
+        let i_reg = reader.u16()?;
+        let delay_timer_reg = reader.u8()?;
+        let sound_timer_reg = reader.u8()?;
+        let stack_pointer_reg = reader.u8()?;
+        let program_counter_reg = reader.u16()?;
+        let mut stack = [0; NUM_STACK_FRAMES];
+        for frame in stack.iter_mut() {
+            *frame = reader.u16()?;
+        }
+        let key_to_wait_for = match reader.u8()? {
+            NO_KEY_TO_WAIT_FOR => None,
+            reg if (reg as usize) < NUM_GENERAL_PURPOSE_REGS => Some(reg),
+            _ => return Err(StateError::Corrupt),
+        };
+        let mut keyboard = [false; NUM_KEYS];
+        for key in keyboard.iter_mut() {
+            *key = reader.bool()?;
+        }
+        let mut flags = [0; NUM_FLAGS];
+        flags.copy_from_slice(reader.bytes(NUM_FLAGS)?);
+        let exited = reader.bool()?;
+        let pending_frames = reader.f64()?;
+        let pending_instructions = reader.f64()?;
+        let frame_count = if version >= 2 { reader.u64()? } else { 0 };
+        let mut audio_pattern = [0; PATTERN_SIZE];
+        audio_pattern.copy_from_slice(reader.bytes(PATTERN_SIZE)?);
+        let pitch = reader.u8()?;
+        // Older states leave the random source where it is
+        let random_state = if version >= 3 {
+            let random_state_len = reader.u16()? as usize;
+            Some(reader.bytes(random_state_len)?)
+        } else {
+            None
+        };
+        let display = Display::load_state(&mut reader)?;
+        let memory = reader.bytes(self.memory.len())?.to_vec();
+        reader.finish()?;
+
+        if stack_pointer_reg as usize > NUM_STACK_FRAMES {
+            return Err(StateError::Corrupt);
+        }
+        // The last thing that can fail, so a bad snapshot changes nothing
+        if let Some(random_state) = random_state {
+            self.random.load_state(random_state)?;
+        }
+
+        self.regs = regs;
+        self.i_reg = i_reg;
+        self.delay_timer_reg = delay_timer_reg;
+        self.sound_timer_reg = sound_timer_reg;
+        self.stack_pointer_reg = stack_pointer_reg;
+        self.program_counter_reg = program_counter_reg;
+        self.stack = stack;
+        self.key_to_wait_for = key_to_wait_for;
+        self.keyboard = keyboard;
+        self.flags = flags;
+        self.exited = exited;
+        self.pending_frames = pending_frames;
+        self.pending_instructions = pending_instructions;
+        self.frame_count = frame_count;
+        let pattern_changed = audio_pattern != self.audio_pattern || pitch != self.pitch;
+        self.audio_pattern = audio_pattern;
+        self.pitch = pitch;
+        *self.display = display;
+        self.memory = memory;
+
+        if pattern_changed {
+            self.audio.set_pattern(self.audio_pattern, self.pitch);
+        }
+        self.update_beeper();
+        Ok(())
+    }
+
+    /// Whether the program has stopped itself with 00FD.
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    /// Whether the sound timer is running, and so whether the buzzer should sound.
+    pub fn is_beeping(&self) -> bool {
+        self.beeping
+    }
+
+    fn update_beeper(&mut self) {
+        let beeping = self.sound_timer_reg > 0;
+        if beeping != self.beeping {
+            self.beeping = beeping;
+            self.audio.set_beeping(beeping);
+        }
+    }
+
+    fn trace_entry(&self) -> Result<TraceEntry, Chip8Error> {
+        Ok(TraceEntry {
+            frame: self.frame_count,
+            pc: self.program_counter_reg,
+            opcode: self.read_word(self.program_counter_reg as usize)?,
+            registers: self.regs,
+            i: self.i_reg,
+            sp: self.stack_pointer_reg,
+            delay_timer: self.delay_timer_reg,
+            sound_timer: self.sound_timer_reg,
+        })
+    }
+
+    fn instruction(&self) -> Result<Instruction, Chip8Error> {
+        self.instruction_at(self.program_counter_reg)
+    }
+
+    /// The instruction at `pc`, read the way this platform would run it.
+    pub fn instruction_at(&self, pc: Address) -> Result<Instruction, Chip8Error> {
+        let raw = self.read_word(pc as usize)?;
+
+        if raw == LOAD_LONG_I && self.platform == Platform::XoChip {
+            let addr = self.read_word(pc as usize + 2)?;
+            return Ok(Instruction::LoadLongI(addr));
+        }
+
+        let instruction = match RawInstruction::new(raw).to_instruction() {
+            // Before SUPER-CHIP, DXY0 was just a sprite with no rows
+            Some(Instruction::DrawLarge(reg1, reg2)) if self.platform == Platform::Chip8 => {
+                Instruction::Draw(reg1, reg2, 0)
+            }
+            Some(instruction) => instruction,
+            None => return Err(Chip8Error::UnknownOpcode(pc, raw)),
+        };
+
+        if self.platform.supports(&instruction) {
+            Ok(instruction)
+        } else {
+            Err(Chip8Error::UnknownOpcode(pc, raw))
+        }
+    }
+
+    fn read_word(&self, addr: usize) -> Result<u16, Chip8Error> {
+        self.check_memory(addr, addr + 2)?;
+        let higher_order = (self.memory[addr] as u16) << 8;
+        let lower_order = self.memory[addr + 1] as u16;
+        Ok(higher_order + lower_order)
+    }
+
+    /// The address `offset` bytes after `addr`, as long as it is still in
+    /// memory. Worked out in `usize` so the end of XO-CHIP's 64 KiB can't wrap.
+    fn offset_address(&self, addr: Address, offset: usize) -> Result<Address, Chip8Error> {
+        let next = addr as usize + offset;
+        self.check_memory(next, next + 1)?;
+        Ok(next as Address)
+    }
+
+    /// The address of the instruction after this one.
+    fn next_instruction(&self) -> Result<Address, Chip8Error> {
+        self.offset_address(self.program_counter_reg, 2)
+    }
+
+    /// The address of the instruction after next, stepping over the whole of
+    /// a four byte XO-CHIP instruction if that is what is being skipped.
+    fn skip_next_instruction(&self) -> Result<Address, Chip8Error> {
+        let next = self.next_instruction()?;
+        if self.platform == Platform::XoChip && self.read_word(next as usize) == Ok(LOAD_LONG_I) {
+            self.offset_address(next, 4)
+        } else {
+            self.offset_address(next, 2)
+        }
+    }
+
+    /// VF after drawing a sprite. In hi-res mode SUPER-CHIP 1.1 counts the
+    /// rows that collided or were clipped off the bottom; everything else
+    /// just says whether any pixel was turned off.
+    fn collision_flag(&self, collision: Collision) -> u8 {
+        if self.platform == Platform::SuperChip && self.display.is_hires() {
+            (collision.rows + collision.clipped_rows) as u8
+        } else {
+            collision.any() as u8
+        }
+    }
+
+    /// How far to scroll for a distance in pixels. SUPER-CHIP 1.1 always
+    /// scrolls by hi-res pixels, so in lo-res mode the screen moves half as far.
+    fn scroll_distance(&self, pixels: usize) -> usize {
+        if self.platform == Platform::SuperChip && !self.display.is_hires() {
+            pixels / 2
+        } else {
+            pixels
+        }
+    }
+
+    fn check_memory(&self, from: usize, to: usize) -> Result<(), Chip8Error> {
+        if to > self.memory.len() {
+            Err(Chip8Error::MemoryOutOfBounds(from))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn read_shift_source(&self, reg: u8, source_reg: u8) -> u8 {
+        if self.quirks.shift_uses_vy {
+            self.read_reg(source_reg)
+        } else {
+            self.read_reg(reg)
+        }
+    }
+
+    fn increment_i_after_load_store(&mut self, highest_reg: u8) {
+        if self.quirks.load_store_increments_i {
+            let increment = if self.quirks.load_store_increments_i_by_x {
+                highest_reg as u16
+            } else {
+                highest_reg as u16 + 1
+            };
+            self.i_reg = self.i_reg.wrapping_add(increment);
+        }
+    }
+
+    fn reset_vf_after_logic(&mut self) {
+        if self.quirks.logic_resets_vf {
+            self.load_reg(0xF, 0);
+        }
     }
 
     fn read_reg(&self, reg_number: u8) -> u8 {
-        self.regs[(reg_number as usize)]
+        self.regs[reg_number as usize]
     }
 
     fn load_reg(&mut self, reg_number: u8, value: u8) {
-        self.regs[(reg_number as usize)] = value;
+        self.regs[reg_number as usize] = value;
     }
 }
 
-impl<'a> fmt::Debug for Chip8 {
+/// The registers from `first` to `last` inclusive, in the order they are
+/// listed, which may be descending.
+fn register_range(first: u8, last: u8) -> Vec<u8> {
+    if first <= last {
+        (first..(last + 1)).collect()
+    } else {
+        (last..(first + 1)).rev().collect()
+    }
+}
+
+impl fmt::Debug for Chip8 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f,
                "CPU {{ regs: {:?}, i_reg: {}, program_counter_reg: {} }}",
@@ -308,3 +975,440 @@ impl<'a> fmt::Debug for Chip8 {
                self.program_counter_reg)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A CHIP-8 machine about to run `program`, with V0 onwards set to `regs`.
+    fn machine(program: &[u8], quirks: Quirks, regs: &[u8]) -> Chip8 {
+        let mut chip8 = Chip8::new(program.to_vec(), Platform::Chip8, quirks).unwrap();
+        chip8.regs[..regs.len()].copy_from_slice(regs);
+        chip8
+    }
+
+    /// Runs `program`'s first instruction and returns the registers after it.
+    fn run(program: &[u8], quirks: Quirks, regs: &[u8]) -> [u8; NUM_GENERAL_PURPOSE_REGS] {
+        let mut chip8 = machine(program, quirks, regs);
+        chip8.step_instruction().unwrap();
+        chip8.registers()
+    }
+
+    #[test]
+    fn or_sets_the_bits_of_either_register() {
+        let mut regs = [0; NUM_GENERAL_PURPOSE_REGS];
+        regs[0] = 0b1100;
+        regs[1] = 0b1010;
+        regs[0xF] = 0x55;
+
+        // V0 |= V1
+        let vip = run(&[0x80, 0x11], Quirks::COSMAC_VIP, &regs);
+        assert_eq!(vip[0], 0b1110);
+        assert_eq!(vip[0xF], 0);
+        let modern = run(&[0x80, 0x11], Quirks::MODERN, &regs);
+        assert_eq!(modern[0], 0b1110);
+        assert_eq!(modern[0xF], 0x55);
+    }
+
+    #[test]
+    fn reverse_sub_sets_vf_when_there_is_no_borrow() {
+        // V0 =- V1
+        let no_borrow = run(&[0x80, 0x17], Quirks::COSMAC_VIP, &[3, 5]);
+        assert_eq!((no_borrow[0], no_borrow[0xF]), (2, 1));
+        let borrow = run(&[0x80, 0x17], Quirks::COSMAC_VIP, &[5, 3]);
+        assert_eq!((borrow[0], borrow[0xF]), (0xFE, 0));
+        let equal = run(&[0x80, 0x17], Quirks::COSMAC_VIP, &[4, 4]);
+        assert_eq!((equal[0], equal[0xF]), (0, 1));
+    }
+
+    #[test]
+    fn reverse_sub_into_vf_keeps_the_flag() {
+        let mut regs = [0; NUM_GENERAL_PURPOSE_REGS];
+        regs[1] = 5;
+        regs[0xF] = 3;
+        // VF =- V1
+        assert_eq!(run(&[0x8F, 0x17], Quirks::COSMAC_VIP, &regs)[0xF], 1);
+        regs[0xF] = 6;
+        assert_eq!(run(&[0x8F, 0x17], Quirks::COSMAC_VIP, &regs)[0xF], 0);
+    }
+
+    #[test]
+    fn arithmetic_into_vf_keeps_the_flag() {
+        // Each case is an 8FYN opcode, V1 before it, VF before it and VF after it
+        let cases = [(0x14, 0x90, 0x80, 1), // VF += V1, carry
+                     (0x14, 0x02, 0x01, 0), // VF += V1
+                     (0x15, 0x03, 0x05, 1), // VF -= V1
+                     (0x15, 0x05, 0x03, 0), // VF -= V1, borrow
+                     (0x16, 0b101, 0, 1), // VF >>= V1, shifting out a 1
+                     (0x16, 0b100, 0, 0), // VF >>= V1
+                     (0x1E, 0x81, 0, 1), // VF <<= V1, shifting out a 1
+                     (0x1E, 0x01, 0, 0)]; // VF <<= V1
+        for &(opcode, v1, vf, flag) in cases.iter() {
+            let mut regs = [0; NUM_GENERAL_PURPOSE_REGS];
+            regs[1] = v1;
+            regs[0xF] = vf;
+            assert_eq!(run(&[0x8F, opcode], Quirks::COSMAC_VIP, &regs)[0xF], flag,
+                       "8F{:02X} with V1 = {:#X}, VF = {:#X}", opcode, v1, vf);
+        }
+    }
+
+    #[test]
+    fn jump_plus_zero_adds_v0_to_the_address() {
+        // jump0 0x300
+        let mut chip8 = machine(&[0xB3, 0x00], Quirks::COSMAC_VIP, &[0x10, 0x20, 0x30, 0x40]);
+        chip8.step_instruction().unwrap();
+        assert_eq!(chip8.program_counter(), 0x310);
+    }
+
+    #[test]
+    fn timers_tick_60_times_a_second_at_any_clock_rate() {
+        for &clock_rate in [60.0, 90.0, 600.0, 1000.0, 2000.0].iter() {
+            // loop: jump loop
+            let mut chip8 = machine(&[0x12, 0x00], Quirks::COSMAC_VIP, &[]);
+            chip8.set_clock_rate(clock_rate);
+            chip8.delay_timer_reg = 200;
+            chip8.sound_timer_reg = 200;
+            for _ in 0..8 {
+                chip8.cycle(0.125).unwrap();
+            }
+            assert_eq!((chip8.delay_timer(), chip8.sound_timer()), (140, 140), "at {} Hz", clock_rate);
+            assert_eq!(chip8.frame_count(), 60);
+        }
+    }
+
+    #[test]
+    fn part_instructions_carry_over_to_the_next_frame() {
+        // V0 += 1, over and over
+        let program: Vec<u8> = [0x70, 0x01].iter().cycle().take(200).cloned().collect();
+        let mut chip8 = machine(&program, Quirks::COSMAC_VIP, &[]);
+        chip8.set_clock_rate(90.0);
+
+        chip8.step_frame().unwrap();
+        assert_eq!(chip8.registers()[0], 1);
+        chip8.step_frame().unwrap();
+        assert_eq!(chip8.registers()[0], 3);
+        for _ in 0..8 {
+            chip8.step_frame().unwrap();
+        }
+        assert_eq!(chip8.registers()[0], 15);
+    }
+
+    /// Runs the first `steps` instructions of `program` on `platform`.
+    fn run_on(platform: Platform, program: &[u8], steps: usize) -> Chip8 {
+        let mut chip8 = Chip8::new(program.to_vec(), platform, platform.default_quirks()).unwrap();
+        for _ in 0..steps {
+            chip8.step_instruction().unwrap();
+        }
+        chip8
+    }
+
+    #[test]
+    fn scroll_down_moves_half_as_far_in_super_chip_lo_res() {
+        // sprite V0 V0 5, scroll-down 4
+        let program = [0xD0, 0x05, 0x00, 0xC4];
+        let lores = run_on(Platform::SuperChip, &program, 2).display.get_buffer();
+        assert_eq!((lores[1][0], lores[2][0]), (0, 1));
+        let xo_chip = run_on(Platform::XoChip, &program, 2).display.get_buffer();
+        assert_eq!((xo_chip[3][0], xo_chip[4][0]), (0, 1));
+
+        // hires, sprite V0 V0 5, scroll-down 4
+        let hires = run_on(Platform::SuperChip, &[0x00, 0xFF, 0xD0, 0x05, 0x00, 0xC4], 3).display.get_buffer();
+        assert_eq!((hires[3][0], hires[4][0]), (0, 1));
+    }
+
+    #[test]
+    fn scroll_right_and_left_move_four_hi_res_pixels() {
+        // sprite V0 V0 5, scroll-right
+        let lores = run_on(Platform::SuperChip, &[0xD0, 0x05, 0x00, 0xFB], 2).display.get_buffer();
+        assert_eq!((lores[0][1], lores[0][2]), (0, 1));
+        // hires, sprite V0 V0 5, scroll-right
+        let hires = run_on(Platform::SuperChip, &[0x00, 0xFF, 0xD0, 0x05, 0x00, 0xFB], 3).display.get_buffer();
+        assert_eq!((hires[0][3], hires[0][4]), (0, 1));
+        // hires, V0 := 8, sprite V0 V1 5, scroll-left
+        let program = [0x00, 0xFF, 0x60, 0x08, 0xD0, 0x15, 0x00, 0xFC];
+        let left = run_on(Platform::SuperChip, &program, 4).display.get_buffer();
+        assert_eq!((left[0][3], left[0][4], left[0][7], left[0][8]), (0, 1, 1, 0));
+    }
+
+    #[test]
+    fn exit_stops_the_machine() {
+        let mut chip8 = run_on(Platform::SuperChip, &[0x00, 0xFD], 1);
+        assert!(chip8.has_exited());
+        chip8.step_instruction().unwrap();
+        assert_eq!(chip8.program_counter(), 0x200);
+    }
+
+    #[test]
+    fn switching_resolution_clears_the_screen() {
+        // sprite V0 V0 5, hires
+        let hires = run_on(Platform::SuperChip, &[0xD0, 0x05, 0x00, 0xFF], 2);
+        assert_eq!((hires.display.width(), hires.display.height()), (128, 64));
+        assert!(hires.display.get_buffer().iter().all(|row| row.iter().all(|&pixel| pixel == 0)));
+        // hires, lores
+        let lores = run_on(Platform::SuperChip, &[0x00, 0xFF, 0x00, 0xFE], 2);
+        assert_eq!((lores.display.width(), lores.display.height()), (64, 32));
+    }
+
+    #[test]
+    fn large_sprites_are_8x16_in_super_chip_lo_res() {
+        // I := sprite, sprite V0 V0 0, then 16 rows of 16 lit pixels
+        let mut program = vec![0xA2, 0x04, 0xD0, 0x00];
+        program.extend_from_slice(&[0xFF; 32]);
+        let lores = run_on(Platform::SuperChip, &program, 2).display.get_buffer();
+        assert_eq!((lores[15][7], lores[15][8], lores[16][0]), (1, 0, 0));
+        let xo_chip = run_on(Platform::XoChip, &program, 2).display.get_buffer();
+        assert_eq!((xo_chip[15][15], xo_chip[15][16], xo_chip[16][0]), (1, 0, 0));
+    }
+
+    #[test]
+    fn super_chip_hi_res_counts_the_rows_that_collide() {
+        // hires, I := sprite, sprite V0 V0 0, sprite V0 V0 0, then the sprite
+        let mut program = vec![0x00, 0xFF, 0xA2, 0x08, 0xD0, 0x00, 0xD0, 0x00];
+        program.extend_from_slice(&[0xFF; 32]);
+        let once = run_on(Platform::SuperChip, &program, 3);
+        assert_eq!(once.registers()[0xF], 0);
+        assert_eq!((once.display.get_buffer()[15][15], once.display.get_buffer()[0][16]), (1, 0));
+        assert_eq!(run_on(Platform::SuperChip, &program, 4).registers()[0xF], 16);
+        // Only whether there was a collision on XO-CHIP
+        assert_eq!(run_on(Platform::XoChip, &program, 4).registers()[0xF], 1);
+
+        // hires, V0 := 56, I := sprite, sprite V0 V0 0, with 8 rows off the bottom
+        let mut program = vec![0x00, 0xFF, 0x60, 0x38, 0xA2, 0x08, 0xD0, 0x00];
+        program.extend_from_slice(&[0xFF; 32]);
+        assert_eq!(run_on(Platform::SuperChip, &program, 4).registers()[0xF], 8);
+    }
+
+    #[test]
+    fn load_large_sprite_points_i_at_the_big_digit() {
+        // V0 := 7, I := bighex V0
+        let chip8 = run_on(Platform::SuperChip, &[0x60, 0x07, 0xF0, 0x30], 2);
+        let i = chip8.i_register() as usize;
+        assert_eq!(i, LARGE_SPRITES_OFFSET + 70);
+        assert_eq!(&chip8.memory()[i..i + 10], &LARGE_SPRITES[70..80]);
+    }
+
+    #[test]
+    fn flags_keep_registers_across_loads() {
+        // V0 := 0x11, V1 := 0x22, saveflags V1, V0 := 0, V1 := 0, loadflags V1
+        let program = [0x60, 0x11, 0x61, 0x22, 0xF1, 0x75, 0x60, 0x00, 0x61, 0x00, 0xF1, 0x85];
+        let chip8 = run_on(Platform::SuperChip, &program, 6);
+        assert_eq!(&chip8.registers()[..2], &[0x11, 0x22]);
+    }
+
+    #[test]
+    fn load_long_i_takes_four_bytes() {
+        // i := long 0x1234
+        let chip8 = run_on(Platform::XoChip, &[0xF0, 0x00, 0x12, 0x34], 1);
+        assert_eq!(chip8.i_register(), 0x1234);
+        assert_eq!(chip8.program_counter(), 0x204);
+    }
+
+    #[test]
+    fn skips_step_over_the_whole_of_load_long_i() {
+        // if V0 != 0 then i := long 0x1234
+        let program = [0x30, 0x00, 0xF0, 0x00, 0x12, 0x34];
+        assert_eq!(run_on(Platform::XoChip, &program, 1).program_counter(), 0x206);
+        // F000 is only an instruction on XO-CHIP
+        assert_eq!(run_on(Platform::SuperChip, &program, 1).program_counter(), 0x204);
+    }
+
+    #[test]
+    fn save_and_load_range_go_either_way() {
+        // V0 := 1, V1 := 2, V2 := 3, I := 0x300, save V0 - V2, I := 0x310, save V2 - V0
+        let program = [0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0xA3, 0x00, 0x50, 0x22, 0xA3, 0x10, 0x52, 0x02,
+                       // load V1 - V2 from 0x310
+                       0x51, 0x23];
+        let chip8 = run_on(Platform::XoChip, &program, 7);
+        assert_eq!(&chip8.memory()[0x300..0x303], &[1, 2, 3]);
+        assert_eq!(&chip8.memory()[0x310..0x313], &[3, 2, 1]);
+        assert_eq!(chip8.i_register(), 0x310);
+        let chip8 = run_on(Platform::XoChip, &program, 8);
+        assert_eq!(&chip8.registers()[..3], &[1, 3, 2]);
+    }
+
+    #[test]
+    fn plane_selects_where_sprites_go() {
+        // plane 2, sprite V0 V0 1 with the 0 digit
+        let chip8 = run_on(Platform::XoChip, &[0xF2, 0x01, 0xD0, 0x01], 2);
+        assert_eq!(chip8.display.selected_planes(), 2);
+        assert_eq!(chip8.display.get_buffer()[0][0], 2);
+        // plane 3 draws the digit's first row, 0xF0, to plane 1 and its second, 0x90, to plane 2
+        let chip8 = run_on(Platform::XoChip, &[0xF3, 0x01, 0xD0, 0x01], 2);
+        assert_eq!(&chip8.display.get_buffer()[0][..5], &[3, 1, 1, 3, 0]);
+    }
+
+    #[test]
+    fn the_end_of_xo_chip_memory_is_an_error_not_an_overflow() {
+        let mut chip8 = Chip8::new(vec![], Platform::XoChip, Quirks::MODERN).unwrap();
+        // V0 := 0 as the last instruction in memory
+        chip8.memory[0xFFFE..].copy_from_slice(&[0x60, 0x00]);
+        chip8.program_counter_reg = 0xFFFE;
+        assert_eq!(chip8.step_instruction(), Err(Chip8Error::MemoryOutOfBounds(0x10000)));
+
+        // if V0 != 0 then, skipping past the end
+        chip8.memory[0xFFFC..0xFFFE].copy_from_slice(&[0x30, 0x00]);
+        chip8.program_counter_reg = 0xFFFC;
+        assert_eq!(chip8.step_instruction(), Err(Chip8Error::MemoryOutOfBounds(0x10000)));
+
+        // if V0 != 0 then i := long 0, skipping past the end
+        chip8.memory[0xFFFA..].copy_from_slice(&[0x30, 0x00, 0xF0, 0x00, 0x00, 0x00]);
+        chip8.program_counter_reg = 0xFFFA;
+        assert_eq!(chip8.step_instruction(), Err(Chip8Error::MemoryOutOfBounds(0x10000)));
+
+        // i := long 0 as the last instruction
+        chip8.memory[0xFFFC..].copy_from_slice(&[0xF0, 0x00, 0x00, 0x00]);
+        chip8.program_counter_reg = 0xFFFC;
+        assert_eq!(chip8.step_instruction(), Err(Chip8Error::MemoryOutOfBounds(0x10000)));
+    }
+
+    /// Draws a random digit and counts down the timers, so that most of the
+    /// machine is in use.
+    const BUSY: [u8; 14] = [0xC0, 0x0F, // loop: V0 := random 0x0F
+                            0xF0, 0x29, // I := font V0
+                            0xD1, 0x15, // sprite V1 V1 5
+                            0xF0, 0x15, // delay := V0
+                            0xF0, 0x18, // buzzer := V0
+                            0x71, 0x03, // V1 += 3
+                            0x12, 0x00]; // jump loop
+
+    #[test]
+    fn save_states_restore_the_whole_machine() {
+        let mut chip8 = machine(&BUSY, Quirks::COSMAC_VIP, &[]);
+        chip8.set_clock_rate(90.0);
+        chip8.handle_key_press(0x5);
+        for _ in 0..3 {
+            chip8.step_frame().unwrap();
+        }
+        let snapshot = chip8.save_state();
+        let expected = (chip8.registers(), chip8.program_counter(), chip8.display.get_buffer());
+
+        for _ in 0..5 {
+            chip8.step_frame().unwrap();
+        }
+        chip8.handle_key_release(0x5);
+        chip8.load_state(&snapshot).unwrap();
+        assert_eq!((chip8.registers(), chip8.program_counter(), chip8.display.get_buffer()), expected);
+        assert_eq!(chip8.save_state(), snapshot);
+
+        // The same random numbers, instructions and timers from there on
+        let mut other = machine(&BUSY, Quirks::COSMAC_VIP, &[]);
+        other.set_clock_rate(90.0);
+        other.load_state(&snapshot).unwrap();
+        for _ in 0..5 {
+            chip8.step_frame().unwrap();
+            other.step_frame().unwrap();
+        }
+        assert_eq!(other.save_state(), chip8.save_state());
+    }
+
+    #[test]
+    fn old_save_states_still_load() {
+        let mut chip8 = machine(&BUSY, Quirks::COSMAC_VIP, &[]);
+        for _ in 0..3 {
+            chip8.step_frame().unwrap();
+        }
+        let snapshot = chip8.save_state();
+        let expected = (chip8.registers(), chip8.program_counter(), chip8.display.get_buffer());
+
+        // Where the fields added since version 1 are
+        let frame_count_at = 26 + NUM_GENERAL_PURPOSE_REGS + 7 + NUM_STACK_FRAMES * 2 + 1 + NUM_KEYS + NUM_FLAGS + 17;
+        assert_eq!(&snapshot[frame_count_at..frame_count_at + 8], &[0, 0, 0, 0, 0, 0, 0, 3]);
+        let random_at = frame_count_at + 8 + PATTERN_SIZE + 1;
+        let random_len = (snapshot[random_at] as usize) << 8 | snapshot[random_at + 1] as usize;
+
+        let mut version_2 = snapshot.clone();
+        version_2[4] = 2;
+        version_2.drain(random_at..random_at + 2 + random_len);
+        let mut version_1 = version_2.clone();
+        version_1[4] = 1;
+        version_1.drain(frame_count_at..frame_count_at + 8);
+
+        for &(ref old, frame_count) in [(version_2, 3), (version_1, 0)].iter() {
+            let mut other = machine(&BUSY, Quirks::COSMAC_VIP, &[]);
+            other.load_state(old).unwrap();
+            assert_eq!((other.registers(), other.program_counter(), other.display.get_buffer()), expected);
+            assert_eq!(other.frame_count(), frame_count);
+        }
+        let mut version_0 = snapshot.clone();
+        version_0[4] = 0;
+        assert_eq!(chip8.load_state(&version_0), Err(StateError::UnsupportedVersion(0)));
+    }
+
+    #[test]
+    fn bad_save_states_change_nothing() {
+        let mut chip8 = machine(&BUSY, Quirks::COSMAC_VIP, &[]);
+        chip8.step_frame().unwrap();
+        let snapshot = chip8.save_state();
+        chip8.step_frame().unwrap();
+        let before = chip8.save_state();
+
+        let mut bad_magic = snapshot.clone();
+        bad_magic[0] = b'X';
+        assert_eq!(chip8.load_state(&bad_magic), Err(StateError::NotASaveState));
+        let mut bad_version = snapshot.clone();
+        bad_version[4] = 99;
+        assert_eq!(chip8.load_state(&bad_version), Err(StateError::UnsupportedVersion(99)));
+        let mut other_rom = machine(&[0x12, 0x00], Quirks::COSMAC_VIP, &[]);
+        assert_eq!(other_rom.load_state(&snapshot), Err(StateError::WrongRom));
+        let mut other_platform = Chip8::new(BUSY.to_vec(), Platform::SuperChip, Quirks::SUPER_CHIP).unwrap();
+        assert_eq!(other_platform.load_state(&snapshot), Err(StateError::WrongPlatform));
+        for &length in [0, 10, snapshot.len() / 2, snapshot.len() - 1].iter() {
+            assert!(chip8.load_state(&snapshot[..length]).is_err(), "{} bytes loaded", length);
+        }
+        assert_eq!(chip8.load_state(&snapshot[..snapshot.len() - 1]), Err(StateError::Truncated));
+        let mut too_long = snapshot.clone();
+        too_long.push(0);
+        assert_eq!(chip8.load_state(&too_long), Err(StateError::Corrupt));
+
+        assert_eq!(chip8.save_state(), before);
+    }
+
+    /// Runs `program` until it fails.
+    fn error(program: &[u8]) -> Chip8Error {
+        let mut chip8 = machine(program, Quirks::COSMAC_VIP, &[]);
+        loop {
+            if let Err(error) = chip8.step_instruction() {
+                return error;
+            }
+        }
+    }
+
+    #[test]
+    fn bad_programs_stop_with_an_error() {
+        assert_eq!(error(&[0xFF, 0xFF]), Chip8Error::UnknownOpcode(0x200, 0xFFFF));
+        // loop: call loop
+        assert_eq!(error(&[0x22, 0x00]), Chip8Error::StackOverflow);
+        // return
+        assert_eq!(error(&[0x00, 0xEE]), Chip8Error::StackUnderflow);
+        // I := 0xFFF, save V1
+        assert_eq!(error(&[0xAF, 0xFF, 0xF1, 0x55]), Chip8Error::MemoryOutOfBounds(0xFFF));
+        // I := 0xFFE, load V2
+        assert_eq!(error(&[0xAF, 0xFE, 0xF2, 0x65]), Chip8Error::MemoryOutOfBounds(0xFFE));
+        // I := 0xFFE, sprite V0 V0 3
+        assert_eq!(error(&[0xAF, 0xFE, 0xD0, 0x03]), Chip8Error::MemoryOutOfBounds(0xFFE));
+        // Running off the end of memory
+        assert_eq!(error(&[0x1F, 0xFF]), Chip8Error::MemoryOutOfBounds(0xFFF));
+    }
+
+    #[test]
+    fn roms_must_fit_in_memory() {
+        let rom = vec![0; 4096 - 0x200 + 1];
+        assert_eq!(Chip8::new(rom, Platform::Chip8, Quirks::COSMAC_VIP).err(),
+                   Some(Chip8Error::RomTooLarge(3585)));
+        assert!(Chip8::new(vec![0; 4096 - 0x200], Platform::Chip8, Quirks::COSMAC_VIP).is_ok());
+    }
+
+    #[test]
+    fn keys_past_f_are_ignored() {
+        // V3 := key
+        let mut chip8 = machine(&[0xF3, 0x0A], Quirks::COSMAC_VIP, &[]);
+        chip8.step_instruction().unwrap();
+        for &key in [0x10, 0xFF].iter() {
+            chip8.handle_key_press(key);
+            chip8.handle_key_release(key);
+        }
+        assert!(chip8.is_waiting_for_key());
+        chip8.handle_key_press(0xF);
+        assert_eq!(chip8.registers()[3], 0xF);
+    }
+}
@@ -0,0 +1,182 @@
+//! The desktop frontend's command line, kept in the library so it can be
+//! tested without opening a window.
+
+use platform::Platform;
+use quirks::Quirks;
+use romdb;
+
+const DEFAULT_SCALE: u32 = 20;
+
+pub const USAGE: &str = "\
+Usage: rust-8 [OPTIONS] GAME
+
+Options:
+  --clock HZ            instructions run per second (default 600)
+  --scale N             size of a low-res pixel in the window (default 20)
+  --fullscreen          fill the screen with the window
+  --terminal            play in the terminal instead of a window, which is
+                        also used if the window can't be opened
+  --ascii               draw in the terminal without Unicode, which is also
+                        done if the locale isn't UTF-8
+  --foreground RRGGBB   colour of lit pixels (default FFFFFF)
+  --background RRGGBB   colour of unlit pixels (default 000000)
+  --platform NAME       chip8, schip or xochip (default schip)
+  --quirks NAME         vip, chip48, schip or modern (default: the platform's,
+                        or modern if neither is given)
+  --database FILE       the ROM database to read settings for known games from
+                        (default ~/.rust-8/programs.json)
+  --mute                don't play any sound
+  --paused              start paused; F9 pauses and resumes
+  --record FILE         record a movie of the keys pressed to FILE
+  --play FILE           play back a movie instead of taking input
+  --help                show this message";
+
+/// Settings left as `None` come from the ROM database, or the defaults if
+/// the game isn't in it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub game: String,
+    pub clock_rate: Option<f64>,
+    pub scale: u32,
+    pub foreground: Option<[u8; 3]>,
+    pub background: Option<[u8; 3]>,
+    pub platform: Option<Platform>,
+    pub quirks: Option<Quirks>,
+    pub database: Option<String>,
+    pub record: Option<String>,
+    pub play: Option<String>,
+    pub mute: bool,
+    pub paused: bool,
+    pub fullscreen: bool,
+    pub terminal: bool,
+    pub ascii: bool,
+}
+
+/// What the command line asks for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Run(Options),
+    /// Show `USAGE` and stop.
+    Help,
+}
+
+/// Reads the arguments after the program name.
+pub fn parse_args(args: Vec<String>) -> Result<Command, String> {
+    let mut options = Options {
+        game: String::new(),
+        clock_rate: None,
+        scale: DEFAULT_SCALE,
+        foreground: None,
+        background: None,
+        platform: None,
+        quirks: None,
+        database: None,
+        record: None,
+        play: None,
+        mute: false,
+        paused: false,
+        fullscreen: false,
+        terminal: false,
+        ascii: false,
+    };
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--help" | "-h" => return Ok(Command::Help),
+            "--mute" => options.mute = true,
+            "--paused" => options.paused = true,
+            "--fullscreen" => options.fullscreen = true,
+            "--terminal" => options.terminal = true,
+            "--ascii" => options.ascii = true,
+            _ if !arg.starts_with("--") => {
+                if !options.game.is_empty() {
+                    return Err(format!("only one GAME can be given, not {} and {}\n\n{}", options.game, arg, USAGE));
+                }
+                options.game = arg;
+            }
+            _ => {
+                let value = args.next().ok_or(format!("{} needs a value", arg))?;
+                match arg.as_str() {
+                    "--clock" => {
+                        options.clock_rate = match value.parse() {
+                            Ok(rate) if rate > 0.0 => Some(rate),
+                            _ => return Err(format!("bad clock rate '{}'", value)),
+                        }
+                    }
+                    "--scale" => {
+                        options.scale = match value.parse() {
+                            Ok(scale) if scale > 0 => scale,
+                            _ => return Err(format!("bad scale '{}'", value)),
+                        }
+                    }
+                    "--foreground" => options.foreground = Some(parse_colour(&value)?),
+                    "--background" => options.background = Some(parse_colour(&value)?),
+                    "--platform" => options.platform = Some(value.parse()?),
+                    "--quirks" => options.quirks = Some(value.parse()?),
+                    "--database" => options.database = Some(value),
+                    "--record" => options.record = Some(value),
+                    "--play" => options.play = Some(value),
+                    _ => return Err(format!("unknown option {}\n\n{}", arg, USAGE)),
+                }
+            }
+        }
+    }
+
+    if options.game.is_empty() {
+        return Err(USAGE.to_string());
+    }
+    Ok(Command::Run(options))
+}
+
+fn parse_colour(value: &str) -> Result<[u8; 3], String> {
+    romdb::parse_colour(value).ok_or(format!("bad colour '{}', expected RRGGBB", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()).collect())
+    }
+
+    fn options(args: &[&str]) -> Options {
+        match parse(args) {
+            Ok(Command::Run(options)) => options,
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn reads_flags_and_values_in_any_order() {
+        let options = options(&["--mute", "game.ch8", "--clock", "1000", "--foreground", "FFB000", "--platform",
+                                "xochip", "--quirks", "vip", "--scale", "5", "--terminal"]);
+        assert_eq!(options.game, "game.ch8");
+        assert!(options.mute && options.terminal && !options.paused);
+        assert_eq!(options.clock_rate, Some(1000.0));
+        assert_eq!(options.foreground, Some([0xFF, 0xB0, 0x00]));
+        assert_eq!(options.background, None);
+        assert_eq!((options.platform, options.quirks), (Some(Platform::XoChip), Some(Quirks::COSMAC_VIP)));
+        assert_eq!(options.scale, 5);
+
+        assert_eq!(self::options(&["game.ch8"]).scale, DEFAULT_SCALE);
+    }
+
+    #[test]
+    fn help_is_a_command_not_an_exit() {
+        assert_eq!(parse(&["--help"]), Ok(Command::Help));
+        assert_eq!(parse(&["game.ch8", "-h"]), Ok(Command::Help));
+    }
+
+    #[test]
+    fn bad_command_lines_are_errors() {
+        assert_eq!(parse(&[]), Err(USAGE.to_string()));
+        assert!(parse(&["one.ch8", "two.ch8"]).unwrap_err().starts_with("only one GAME"));
+        assert!(parse(&["game.ch8", "--clock"]).unwrap_err().contains("needs a value"));
+        assert!(parse(&["game.ch8", "--clock", "0"]).is_err());
+        assert!(parse(&["game.ch8", "--scale", "-1"]).is_err());
+        assert!(parse(&["game.ch8", "--background", "blue"]).is_err());
+        assert!(parse(&["game.ch8", "--platform", "pdp11"]).is_err());
+        assert!(parse(&["game.ch8", "--volume", "11"]).unwrap_err().starts_with("unknown option --volume"));
+    }
+}
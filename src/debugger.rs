@@ -0,0 +1,418 @@
+//! Breakpoints, watchpoints and single-stepping for finding out what a
+//! program is doing.
+
+use std::fmt;
+use std::str::FromStr;
+
+use chip8::{Chip8, Chip8Error};
+use instruction::Address;
+
+/// An opcode with some nibbles left as wildcards, written the way opcodes
+/// are in references: `DXYN` matches every draw, `8XY4` every register add.
+/// Any character that isn't a hex digit is a wildcard.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpcodePattern {
+    text: String,
+    value: u16,
+    mask: u16,
+}
+
+impl OpcodePattern {
+    pub fn matches(&self, opcode: u16) -> bool {
+        opcode & self.mask == self.value
+    }
+}
+
+impl FromStr for OpcodePattern {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<OpcodePattern, String> {
+        if text.chars().count() != 4 {
+            return Err(format!("opcode pattern '{}' should be four characters, like DXYN", text));
+        }
+
+        let mut value = 0;
+        let mut mask = 0;
+        for c in text.chars() {
+            value <<= 4;
+            mask <<= 4;
+            if let Some(digit) = c.to_digit(16) {
+                value |= digit as u16;
+                mask |= 0xF;
+            }
+        }
+        Ok(OpcodePattern {
+            text: text.to_uppercase(),
+            value,
+            mask,
+        })
+    }
+}
+
+impl fmt::Display for OpcodePattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+/// Why the debugger handed control back.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stop {
+    /// The requested number of instructions has been run.
+    Stepped,
+    /// The program counter reached a breakpoint.
+    Breakpoint(Address),
+    /// The next instruction matches an opcode breakpoint.
+    Opcode(Address, u16),
+    /// The last instruction changed a watched byte.
+    Watch { addr: Address, old: u8, new: u8 },
+    /// The program is waiting for a key and won't go on until one is pressed.
+    WaitingForKey,
+    /// The program is jumping to itself, which is how most programs stop.
+    Halted(Address),
+    /// The program has exited with 00FD.
+    Exited,
+    Error(Chip8Error),
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Stop::Stepped => write!(f, "Stepped"),
+            Stop::Breakpoint(addr) => write!(f, "Breakpoint at {:03X}", addr),
+            Stop::Opcode(addr, opcode) => write!(f, "Opcode {:04X} at {:03X}", opcode, addr),
+            Stop::Watch { addr, old, new } => {
+                write!(f, "Watched byte {:03X} changed from {:02X} to {:02X}", addr, old, new)
+            }
+            Stop::WaitingForKey => write!(f, "Waiting for a key press"),
+            Stop::Halted(addr) => write!(f, "Halted in a jump to itself at {:03X}", addr),
+            Stop::Exited => write!(f, "The program has exited"),
+            Stop::Error(ref error) => write!(f, "Error: {}", error),
+        }
+    }
+}
+
+struct Watch {
+    addr: Address,
+    value: u8,
+}
+
+pub struct Debugger {
+    breakpoints: Vec<Address>,
+    opcode_breakpoints: Vec<OpcodePattern>,
+    watches: Vec<Watch>,
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger {
+            breakpoints: Vec::new(),
+            opcode_breakpoints: Vec::new(),
+            watches: Vec::new(),
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, addr: Address) {
+        if !self.breakpoints.contains(&addr) {
+            self.breakpoints.push(addr);
+        }
+    }
+
+    /// Returns whether there was a breakpoint to remove.
+    pub fn remove_breakpoint(&mut self, addr: Address) -> bool {
+        let before = self.breakpoints.len();
+        self.breakpoints.retain(|breakpoint| *breakpoint != addr);
+        self.breakpoints.len() != before
+    }
+
+    pub fn breakpoints(&self) -> &[Address] {
+        &self.breakpoints
+    }
+
+    pub fn add_opcode_breakpoint(&mut self, pattern: OpcodePattern) {
+        if !self.opcode_breakpoints.contains(&pattern) {
+            self.opcode_breakpoints.push(pattern);
+        }
+    }
+
+    pub fn remove_opcode_breakpoint(&mut self, pattern: &OpcodePattern) -> bool {
+        let before = self.opcode_breakpoints.len();
+        self.opcode_breakpoints.retain(|breakpoint| breakpoint != pattern);
+        self.opcode_breakpoints.len() != before
+    }
+
+    pub fn opcode_breakpoints(&self) -> &[OpcodePattern] {
+        &self.opcode_breakpoints
+    }
+
+    /// Stops execution whenever the byte at `addr` changes.
+    pub fn add_watch(&mut self, chip8: &Chip8, addr: Address) {
+        if let Some(value) = chip8.memory().get(addr as usize) {
+            self.remove_watch(addr);
+            self.watches.push(Watch {
+                addr,
+                value: *value,
+            });
+        }
+    }
+
+    pub fn remove_watch(&mut self, addr: Address) -> bool {
+        let before = self.watches.len();
+        self.watches.retain(|watch| watch.addr != addr);
+        self.watches.len() != before
+    }
+
+    pub fn watches(&self) -> Vec<Address> {
+        self.watches.iter().map(|watch| watch.addr).collect()
+    }
+
+    /// Runs `count` instructions, stopping early at anything the debugger is watching for.
+    pub fn step(&mut self, chip8: &mut Chip8, count: u64) -> Stop {
+        self.execute(chip8, Some(count))
+    }
+
+    /// Runs until the debugger sees something it is watching for.
+    pub fn resume(&mut self, chip8: &mut Chip8) -> Stop {
+        self.execute(chip8, None)
+    }
+
+    /// Runs the machine a frame at a time so the timers keep ticking at the
+    /// rate the program expects. Watches are checked after every instruction,
+    /// before anything else, so a change is blamed on the instruction that
+    /// made it. Breakpoints are checked before each instruction except the
+    /// first, so resuming from a breakpoint works.
+    fn execute(&mut self, chip8: &mut Chip8, limit: Option<u64>) -> Stop {
+        let mut executed = 0;
+        loop {
+            let mut stop = None;
+            let result = chip8.step_frame_until(|chip8| {
+                let watch = if executed > 0 { self.check_watches(chip8) } else { None };
+                stop = if watch.is_some() {
+                    watch
+                } else if chip8.has_exited() {
+                    Some(Stop::Exited)
+                } else if chip8.is_waiting_for_key() {
+                    Some(Stop::WaitingForKey)
+                } else if limit == Some(executed) {
+                    Some(Stop::Stepped)
+                } else if chip8.current_opcode() == Some(0x1000 | chip8.program_counter()) {
+                    Some(Stop::Halted(chip8.program_counter()))
+                } else if executed > 0 {
+                    self.check_breakpoints(chip8)
+                } else {
+                    None
+                };
+                executed += 1;
+                stop.is_some()
+            });
+
+            match result {
+                Ok(true) => return stop.unwrap_or(Stop::Stepped),
+                Ok(false) => {}
+                Err(error) => return Stop::Error(error),
+            }
+        }
+    }
+
+    fn check_watches(&mut self, chip8: &Chip8) -> Option<Stop> {
+        for watch in self.watches.iter_mut() {
+            let value = chip8.memory()[watch.addr as usize];
+            if value != watch.value {
+                let old = watch.value;
+                watch.value = value;
+                return Some(Stop::Watch {
+                    addr: watch.addr,
+                    old,
+                    new: value,
+                });
+            }
+        }
+        None
+    }
+
+    fn check_breakpoints(&self, chip8: &Chip8) -> Option<Stop> {
+        let pc = chip8.program_counter();
+        if self.breakpoints.contains(&pc) {
+            return Some(Stop::Breakpoint(pc));
+        }
+
+        if let Some(opcode) = chip8.current_opcode() {
+            if self.opcode_breakpoints.iter().any(|pattern| pattern.matches(opcode)) {
+                return Some(Stop::Opcode(pc, opcode));
+            }
+        }
+        None
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Debugger {
+        Debugger::new()
+    }
+}
+
+/// Lists `before` instructions before the program counter and `after` after
+/// it, one per line, marking the program counter with `=>`. Instructions are
+/// read the way the machine's platform would run them.
+pub fn disassemble_around(chip8: &Chip8, before: usize, after: usize) -> String {
+    let pc = chip8.program_counter() as usize;
+    let memory = chip8.memory();
+
+    let mut addr = lead_in(chip8, pc, before);
+    let mut listing = String::new();
+    for _ in 0..(before + after + 1) {
+        if addr + 1 >= memory.len() {
+            break;
+        }
+        let (mnemonic, size) = match chip8.instruction_at(addr as Address) {
+            Ok(instruction) => (instruction.to_string(), instruction.size()),
+            Err(_) => ("??".to_string(), 2),
+        };
+        let word = (memory[addr] as u16) << 8 | memory[addr + 1] as u16;
+        let marker = if addr == pc { "=>" } else { "  " };
+        listing.push_str(&format!("{} {:03X}: {:04X}  {}\n", marker, addr, word, mnemonic));
+        addr += size;
+    }
+    listing
+}
+
+/// How many bytes further back than `before` instructions could reach that
+/// `disassemble_around` starts decoding.
+const LEAD_IN_SLACK: usize = 16;
+
+/// Where to start listing so that `before` instructions come ahead of `pc`.
+///
+/// Instructions can't be read backwards: on XO-CHIP the word before `pc` may
+/// be the second half of an F000 NNNN, or an F000 may be data. So this
+/// decodes forwards from further back than needed, which gives the decoding
+/// time to fall into step, and takes the first starting point whose
+/// instructions line up with `pc`. It is a guess, and can be wrong where
+/// code and data are mixed.
+fn lead_in(chip8: &Chip8, pc: usize, before: usize) -> usize {
+    let size_at = |addr: usize| chip8.instruction_at(addr as Address).map_or(2, |instruction| instruction.size());
+    let mut start = pc - (before * 4 + LEAD_IN_SLACK).min(pc - pc % 2);
+    while start < pc {
+        let mut addrs = Vec::new();
+        let mut addr = start;
+        while addr < pc {
+            addrs.push(addr);
+            addr += size_at(addr);
+        }
+        if addr == pc {
+            return addrs.get(addrs.len().saturating_sub(before)).cloned().unwrap_or(pc);
+        }
+        start += 2;
+    }
+    pc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use platform::Platform;
+    use quirks::Quirks;
+
+    const ROM: [u8; 12] = [0x60, 0x05, // V0 := 5
+                           0xA3, 0x00, // I := 0x300
+                           0xF0, 0x55, // save V0
+                           0x70, 0x01, // V0 += 1
+                           0xD0, 0x05, // sprite V0 V0 5
+                           0x12, 0x0A]; // loop: jump loop
+
+    fn machine(rom: &[u8], platform: Platform) -> Chip8 {
+        Chip8::new(rom.to_vec(), platform, Quirks::MODERN).unwrap()
+    }
+
+    #[test]
+    fn breakpoints_stop_before_the_instruction_runs() {
+        let mut chip8 = machine(&ROM, Platform::Chip8);
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(0x206);
+        assert_eq!(debugger.resume(&mut chip8), Stop::Breakpoint(0x206));
+        assert_eq!(chip8.registers()[0], 5);
+        assert_eq!(debugger.resume(&mut chip8), Stop::Halted(0x20A));
+
+        assert!(debugger.remove_breakpoint(0x206));
+        assert!(!debugger.remove_breakpoint(0x206));
+        assert!(debugger.breakpoints().is_empty());
+    }
+
+    #[test]
+    fn opcode_patterns_match_any_value_in_the_wildcards() {
+        let pattern: OpcodePattern = "dxyn".parse().unwrap();
+        assert!(pattern.matches(0xD005));
+        assert!(!pattern.matches(0x7001));
+        assert_eq!(pattern.to_string(), "DXYN");
+        assert!("DXY".parse::<OpcodePattern>().is_err());
+
+        let mut chip8 = machine(&ROM, Platform::Chip8);
+        let mut debugger = Debugger::new();
+        debugger.add_opcode_breakpoint(pattern);
+        assert_eq!(debugger.resume(&mut chip8), Stop::Opcode(0x208, 0xD005));
+    }
+
+    #[test]
+    fn watches_blame_the_instruction_that_changed_the_byte() {
+        let mut chip8 = machine(&ROM, Platform::Chip8);
+        let mut debugger = Debugger::new();
+        debugger.add_watch(&chip8, 0x300);
+        assert_eq!(debugger.resume(&mut chip8), Stop::Watch { addr: 0x300, old: 0, new: 5 });
+        assert_eq!(chip8.program_counter(), 0x206);
+        assert_eq!(debugger.resume(&mut chip8), Stop::Halted(0x20A));
+    }
+
+    #[test]
+    fn watches_win_over_the_end_of_a_step() {
+        let mut chip8 = machine(&ROM, Platform::Chip8);
+        let mut debugger = Debugger::new();
+        debugger.add_watch(&chip8, 0x300);
+        // The third instruction is the save
+        assert_eq!(debugger.step(&mut chip8, 3), Stop::Watch { addr: 0x300, old: 0, new: 5 });
+        assert_eq!(chip8.program_counter(), 0x206);
+        assert_eq!(debugger.step(&mut chip8, 1), Stop::Stepped);
+        assert_eq!(chip8.program_counter(), 0x208);
+    }
+
+    #[test]
+    fn disassembly_follows_the_platform() {
+        let rom = [0x60, 0x01, // V0 := 1
+                   0xF0, 0x00, 0x12, 0x34, // i := long 0x1234
+                   0x70, 0x01, // V0 += 1
+                   0x12, 0x08]; // loop: jump loop
+        let mut xo_chip = machine(&rom, Platform::XoChip);
+        xo_chip.step_instruction().unwrap();
+        xo_chip.step_instruction().unwrap();
+        let listing = disassemble_around(&xo_chip, 2, 1);
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("   200: 6001"));
+        assert!(lines[1].starts_with("   202: F000") && lines[1].ends_with("#1234"));
+        assert!(lines[2].starts_with("=> 206: 7001"));
+        assert!(lines[3].starts_with("   208: 1208"));
+
+        // F000 is two words of nothing in particular before XO-CHIP
+        let chip8 = machine(&rom, Platform::Chip8);
+        let listing = disassemble_around(&chip8, 0, 2);
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(lines[1], "   202: F000  ??");
+        assert!(lines[2].starts_with("   204: 1234"));
+    }
+
+    #[test]
+    fn disassembly_lines_up_with_the_program_counter() {
+        // A long operand that is itself F000 isn't the start of another one
+        let rom = [0xF0, 0x00, 0xF0, 0x00, // i := long 0xF000
+                   0x60, 0x01, // V0 := 1
+                   0x70, 0x01]; // V0 += 1
+        let mut xo_chip = machine(&rom, Platform::XoChip);
+        xo_chip.step_instruction().unwrap();
+        xo_chip.step_instruction().unwrap();
+        let listing = disassemble_around(&xo_chip, 2, 0);
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("   200: F000") && lines[0].ends_with("#F000"));
+        assert!(lines[1].starts_with("   204: 6001"));
+        assert!(lines[2].starts_with("=> 206: 7001"));
+        assert!(disassemble_around(&xo_chip, 1, 0).starts_with("   204: 6001"));
+    }
+}
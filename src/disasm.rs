@@ -0,0 +1,296 @@
+//! Turns a ROM back into assembly.
+//!
+//! A ROM mixes code with sprites and other data, and decoding every word
+//! would print data as nonsense instructions. Instead the disassembler
+//! follows the program's control flow from 0x200: anything reached is code,
+//! everything else is listed as data bytes.
+
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+use instruction::{Address, Instruction, RawInstruction, LOAD_LONG_I};
+
+const PROGRAM_START: Address = 0x200;
+
+/// The assembly language to write the listing in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Syntax {
+    /// Cowgod's technical reference, e.g. `LD V1, #2A`.
+    Cowgod,
+    /// The Octo assembler, e.g. `v1 := 0x2A`.
+    Octo,
+}
+
+impl FromStr for Syntax {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Syntax, String> {
+        match name {
+            "cowgod" => Ok(Syntax::Cowgod),
+            "octo" => Ok(Syntax::Octo),
+            _ => Err(format!("unknown syntax '{}', expected cowgod or octo", name)),
+        }
+    }
+}
+
+/// Disassembles a ROM loaded at 0x200. Each line of the listing has the
+/// address, the raw bytes and the instruction or data, and every target of
+/// a jump or call gets a label named after its address.
+pub fn disassemble(rom: &[u8], syntax: Syntax) -> String {
+    let (code, labels) = trace(rom);
+    let label = |addr: Address| {
+        if labels.contains(&addr) {
+            format!("L{:03X}", addr)
+        } else {
+            format!("#{:03X}", addr)
+        }
+    };
+
+    let mut listing = String::new();
+    let mut offset = 0;
+    while offset < rom.len() {
+        let addr = PROGRAM_START + offset as Address;
+        if labels.contains(&addr) {
+            match syntax {
+                Syntax::Cowgod => listing.push_str(&format!("L{:03X}:\n", addr)),
+                Syntax::Octo => listing.push_str(&format!(": L{:03X}\n", addr)),
+            }
+        }
+
+        let (len, text) = match code.get(offset).cloned().unwrap_or(None) {
+            Some(instruction) => {
+                let text = match syntax {
+                    Syntax::Cowgod => cowgod(&instruction, &label),
+                    Syntax::Octo => octo(&instruction, &label),
+                };
+                (instruction.size(), text)
+            }
+            None => {
+                // Data runs up to eight bytes a line, stopping before code or a label
+                let mut len = 1;
+                while len < 8 && offset + len < rom.len() && code[offset + len].is_none() &&
+                      !labels.contains(&(addr + len as Address)) {
+                    len += 1;
+                }
+                let bytes = &rom[offset..offset + len];
+                let text = match syntax {
+                    Syntax::Cowgod => {
+                        let bytes: Vec<String> = bytes.iter().map(|byte| format!("#{:02X}", byte)).collect();
+                        format!("DB {}", bytes.join(", "))
+                    }
+                    Syntax::Octo => {
+                        let bytes: Vec<String> = bytes.iter().map(|byte| format!("0x{:02X}", byte)).collect();
+                        bytes.join(" ")
+                    }
+                };
+                (len, text)
+            }
+        };
+
+        let raw: String = rom[offset..offset + len].iter().map(|byte| format!("{:02X}", byte)).collect();
+        let comment = match syntax {
+            Syntax::Cowgod => ";",
+            Syntax::Octo => "#",
+        };
+        listing.push_str(&format!("    {:<24} {} {:03X}: {}\n", text, comment, addr, raw));
+        offset += len;
+    }
+    listing
+}
+
+/// Finds the instructions the program can reach from 0x200, indexed by their
+/// offset into the ROM, and the addresses that are jumped to or called.
+fn trace(rom: &[u8]) -> (Vec<Option<Instruction>>, BTreeSet<Address>) {
+    let mut code = vec![None; rom.len()];
+    let mut covered = vec![false; rom.len()];
+    let mut labels = BTreeSet::new();
+    // A stack, with the instruction that follows pushed last so straight-line
+    // code is decoded before anything that might jump into the middle of it
+    let mut pending = vec![PROGRAM_START];
+
+    while let Some(addr) = pending.pop() {
+        let offset = match (addr as usize).checked_sub(PROGRAM_START as usize) {
+            Some(offset) => offset,
+            None => continue,
+        };
+        if covered.get(offset) != Some(&false) {
+            continue;
+        }
+        let instruction = match decode(rom, offset) {
+            Some(instruction) => instruction,
+            None => continue,
+        };
+        let len = instruction.size();
+        if covered[offset..offset + len].iter().any(|covered| *covered) {
+            continue;
+        }
+        for covered in covered[offset..offset + len].iter_mut() {
+            *covered = true;
+        }
+        code[offset] = Some(instruction);
+
+        let next = addr.wrapping_add(len as Address);
+        match instruction {
+            Instruction::Jump(target) => {
+                labels.insert(target);
+                pending.push(target);
+            }
+            Instruction::Call(target) => {
+                labels.insert(target);
+                pending.push(target);
+                pending.push(next);
+            }
+            // Where these go depends on the machine's state, so tracing stops here
+            Instruction::Return | Instruction::Exit | Instruction::JumpPlusZero(_) => {}
+            Instruction::SkipIfEqualsByte(..) |
+            Instruction::SkipIfNotEqualsByte(..) |
+            Instruction::SkipIfEqual(..) |
+            Instruction::SkipIfNotEqual(..) |
+            Instruction::SkipIfPressed(_) |
+            Instruction::SkipIfNotPressed(_) => {
+                let skipped_len = decode(rom, offset + len).map_or(2, |skipped| skipped.size());
+                pending.push(next.wrapping_add(skipped_len as Address));
+                pending.push(next);
+            }
+            _ => pending.push(next),
+        }
+    }
+
+    // Jumps into the middle of data or outside the ROM get no label
+    let labels = labels.into_iter()
+        .filter(|addr| {
+            (*addr as usize).checked_sub(PROGRAM_START as usize)
+                .and_then(|offset| code.get(offset))
+                .is_some_and(|instruction| instruction.is_some())
+        })
+        .collect();
+    (code, labels)
+}
+
+fn decode(rom: &[u8], offset: usize) -> Option<Instruction> {
+    let word = |offset: usize| {
+        if offset + 1 < rom.len() {
+            Some((rom[offset] as u16) << 8 | rom[offset + 1] as u16)
+        } else {
+            None
+        }
+    };
+    let raw = word(offset)?;
+    if raw == LOAD_LONG_I {
+        return word(offset + 2).map(Instruction::LoadLongI);
+    }
+    RawInstruction::new(raw).to_instruction()
+}
+
+fn cowgod(instruction: &Instruction, label: &dyn Fn(Address) -> String) -> String {
+    match *instruction {
+        Instruction::Jump(addr) => format!("JP {}", label(addr)),
+        Instruction::Call(addr) => format!("CALL {}", label(addr)),
+        Instruction::JumpPlusZero(addr) => format!("JP V0, {}", label(addr)),
+        _ => instruction.to_string(),
+    }
+}
+
+/// Formats an instruction the way Octo's assembler writes it. Octo's
+/// conditionals say when the next instruction runs, not when it is skipped,
+/// so the comparisons are the opposite of the opcode's.
+fn octo(instruction: &Instruction, label: &dyn Fn(Address) -> String) -> String {
+    let octo_label = |addr: Address| label(addr).replace('#', "0x");
+    match *instruction {
+        Instruction::ClearDisplay => "clear".to_string(),
+        Instruction::Return => "return".to_string(),
+        Instruction::ScrollDown(n) => format!("scroll-down {}", n),
+        Instruction::ScrollUp(n) => format!("scroll-up {}", n),
+        Instruction::ScrollRight => "scroll-right".to_string(),
+        Instruction::ScrollLeft => "scroll-left".to_string(),
+        Instruction::Exit => "exit".to_string(),
+        Instruction::LowRes => "lores".to_string(),
+        Instruction::HighRes => "hires".to_string(),
+        Instruction::Jump(addr) => format!("jump {}", octo_label(addr)),
+        Instruction::Call(addr) => format!(":call {}", octo_label(addr)),
+        Instruction::SkipIfEqualsByte(reg, value) => format!("if v{:x} != 0x{:02X} then", reg, value),
+        Instruction::SkipIfNotEqualsByte(reg, value) => format!("if v{:x} == 0x{:02X} then", reg, value),
+        Instruction::SkipIfEqual(reg1, reg2) => format!("if v{:x} != v{:x} then", reg1, reg2),
+        Instruction::SaveRange(reg1, reg2) => format!("save v{:x} - v{:x}", reg1, reg2),
+        Instruction::LoadRange(reg1, reg2) => format!("load v{:x} - v{:x}", reg1, reg2),
+        Instruction::LoadByte(reg, value) => format!("v{:x} := 0x{:02X}", reg, value),
+        Instruction::AddByte(reg, value) => format!("v{:x} += 0x{:02X}", reg, value),
+        Instruction::Move(reg1, reg2) => format!("v{:x} := v{:x}", reg1, reg2),
+        Instruction::Or(reg1, reg2) => format!("v{:x} |= v{:x}", reg1, reg2),
+        Instruction::And(reg1, reg2) => format!("v{:x} &= v{:x}", reg1, reg2),
+        Instruction::Xor(reg1, reg2) => format!("v{:x} ^= v{:x}", reg1, reg2),
+        Instruction::Add(reg1, reg2) => format!("v{:x} += v{:x}", reg1, reg2),
+        Instruction::Sub(reg1, reg2) => format!("v{:x} -= v{:x}", reg1, reg2),
+        Instruction::ShiftRight(reg1, reg2) => format!("v{:x} >>= v{:x}", reg1, reg2),
+        Instruction::ReverseSub(reg1, reg2) => format!("v{:x} =- v{:x}", reg1, reg2),
+        Instruction::ShiftLeft(reg1, reg2) => format!("v{:x} <<= v{:x}", reg1, reg2),
+        Instruction::SkipIfNotEqual(reg1, reg2) => format!("if v{:x} == v{:x} then", reg1, reg2),
+        Instruction::LoadI(addr) => format!("i := 0x{:03X}", addr),
+        Instruction::LoadLongI(addr) => format!("i := long 0x{:04X}", addr),
+        Instruction::JumpPlusZero(addr) => format!("jump0 {}", octo_label(addr)),
+        Instruction::Random(reg, value) => format!("v{:x} := random 0x{:02X}", reg, value),
+        Instruction::SelectPlanes(planes) => format!("plane {}", planes),
+        Instruction::Draw(reg1, reg2, n) => format!("sprite v{:x} v{:x} {}", reg1, reg2, n),
+        Instruction::DrawLarge(reg1, reg2) => format!("sprite v{:x} v{:x} 0", reg1, reg2),
+        Instruction::SkipIfPressed(reg) => format!("if v{:x} -key then", reg),
+        Instruction::SkipIfNotPressed(reg) => format!("if v{:x} key then", reg),
+        Instruction::LoadDelayTimer(reg) => format!("v{:x} := delay", reg),
+        Instruction::WaitForKeyPress(reg) => format!("v{:x} := key", reg),
+        Instruction::SetDelayTimer(reg) => format!("delay := v{:x}", reg),
+        Instruction::SetSoundTimer(reg) => format!("buzzer := v{:x}", reg),
+        Instruction::AddToI(reg) => format!("i += v{:x}", reg),
+        Instruction::LoadSprite(reg) => format!("i := hex v{:x}", reg),
+        Instruction::LoadLargeSprite(reg) => format!("i := bighex v{:x}", reg),
+        Instruction::BCDRepresentation(reg) => format!("bcd v{:x}", reg),
+        Instruction::StoreRegisters(reg) => format!("save v{:x}", reg),
+        Instruction::LoadRegisters(reg) => format!("load v{:x}", reg),
+        Instruction::StoreFlags(reg) => format!("saveflags v{:x}", reg),
+        Instruction::LoadFlags(reg) => format!("loadflags v{:x}", reg),
+        Instruction::LoadAudioPattern => "audio".to_string(),
+        Instruction::SetPitch(reg) => format!("pitch := v{:x}", reg),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROM: [u8; 12] = [0x22, 0x08, // call sub
+                           0x12, 0x02, // loop: jump loop
+                           0xF0, 0x90, 0x90, 0xF0, // a sprite, never run
+                           0x60, 0x05, // sub: v0 := 5
+                           0x00, 0xEE]; // return
+
+    #[test]
+    fn cowgod_listing_labels_jumps_and_calls() {
+        assert_eq!(disassemble(&ROM, Syntax::Cowgod),
+                   "    CALL L208                ; 200: 2208\n\
+                    L202:\n\
+                    \x20   JP L202                  ; 202: 1202\n\
+                    \x20   DB #F0, #90, #90, #F0    ; 204: F09090F0\n\
+                    L208:\n\
+                    \x20   LD V0, #05               ; 208: 6005\n\
+                    \x20   RET                      ; 20A: 00EE\n");
+    }
+
+    #[test]
+    fn octo_listing_labels_jumps_and_calls() {
+        assert_eq!(disassemble(&ROM, Syntax::Octo),
+                   "    :call L208               # 200: 2208\n\
+                    : L202\n\
+                    \x20   jump L202                # 202: 1202\n\
+                    \x20   0xF0 0x90 0x90 0xF0      # 204: F09090F0\n\
+                    : L208\n\
+                    \x20   v0 := 0x05               # 208: 6005\n\
+                    \x20   return                   # 20A: 00EE\n");
+    }
+
+    #[test]
+    fn jumps_into_data_get_no_label() {
+        // jump 0x203, into the middle of a word that is not an instruction
+        let rom = [0x12, 0x03, 0x00, 0xE0, 0x00];
+        assert_eq!(disassemble(&rom, Syntax::Cowgod),
+                   "    JP #203                  ; 200: 1203\n\
+                    \x20   DB #00, #E0, #00         ; 202: 00E000\n");
+    }
+}
@@ -1,5 +1,9 @@
+use state::{StateError, StateReader, StateWriter};
+
 pub const WIDTH: usize = 64;
 pub const HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
 pub const SPRITES: [u8; 80] = [0xF0, 0x90, 0x90, 0x90, 0xF0 /* 0 */, 0x20, 0x60, 0x20, 0x20,
                                0x70 /* 1 */, 0xF0, 0x10, 0xF0, 0x80, 0xF0 /* 2 */, 0xF0,
                                0x10, 0xF0, 0x10, 0xF0 /* 3 */, 0x90, 0x90, 0xF0, 0x10,
@@ -11,46 +15,380 @@ pub const SPRITES: [u8; 80] = [0xF0, 0x90, 0x90, 0x90, 0xF0 /* 0 */, 0x20, 0x60,
                                0x80, 0x80, 0x80, 0xF0 /* c */, 0xE0, 0x90, 0x90, 0x90,
                                0xE0 /* d */, 0xF0, 0x80, 0xF0, 0x80, 0xF0 /* e */, 0xF0,
                                0x80, 0xF0, 0x80, 0x80];// f
+pub const LARGE_SPRITES: [u8; 160] = [0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF /* 0 */,
+                                      0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF /* 1 */,
+                                      0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF /* 2 */,
+                                      0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF /* 3 */,
+                                      0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03 /* 4 */,
+                                      0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF /* 5 */,
+                                      0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF /* 6 */,
+                                      0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18 /* 7 */,
+                                      0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF /* 8 */,
+                                      0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF /* 9 */,
+                                      0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3 /* a */,
+                                      0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC /* b */,
+                                      0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C /* c */,
+                                      0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC /* d */,
+                                      0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF /* e */,
+                                      0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0 /* f */];
+
+/// The screen as rows of pixels, `height()` rows of `width()` pixels each.
+///
+/// Each pixel holds one bit per bitplane, so plain CHIP-8 programs only ever
+/// produce 0 and 1 while XO-CHIP programs can produce all four colours 0-3.
+pub type Buffer = Vec<Vec<u8>>;
+
+pub const NUM_PLANES: usize = 2;
+const ALL_PLANES: u8 = 0b11;
 
-pub type Buffer = [[bool; WIDTH]; HEIGHT];
+/// What a sprite hit when it was drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Collision {
+    /// The rows of the sprite that turned off a pixel, in any plane.
+    pub rows: usize,
+    /// The rows of the sprite clipped off the bottom of the screen.
+    pub clipped_rows: usize,
+}
+
+impl Collision {
+    /// Whether any pixel was turned off, which is what VF is usually set to.
+    pub fn any(&self) -> bool {
+        self.rows > 0
+    }
+}
 
+/// The screen, which programs draw on by XORing sprites onto it.
 pub struct Display {
     buffer: Buffer,
+    hires: bool,
+    planes: u8,
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Display {
     pub fn new() -> Display {
-        Display { buffer: [[false; WIDTH]; HEIGHT] }
+        Display {
+            buffer: blank_buffer(WIDTH, HEIGHT),
+            hires: false,
+            planes: 0b01,
+        }
     }
 
-    pub fn draw(&mut self, starting_x: u8, starting_y: u8, memory: &[u8]) -> bool {
-        let mut pixel_turned_off = false;
+    pub fn width(&self) -> usize {
+        if self.hires { HIRES_WIDTH } else { WIDTH }
+    }
 
-        for (byte_number, block) in memory.iter().enumerate() {
-            let y = (starting_y as usize + byte_number) % HEIGHT;
+    pub fn height(&self) -> usize {
+        if self.hires { HIRES_HEIGHT } else { HEIGHT }
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
 
-            for bit_number in 0..8 {
-                let x = (starting_x as usize + bit_number) % WIDTH;
-                let current_pixel = self.buffer[y][x] as u8;
+    /// Switches between the 64x32 and 128x64 modes, clearing the screen.
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.buffer = blank_buffer(self.width(), self.height());
+    }
+
+    /// Chooses which bitplanes drawing, clearing and scrolling act on.
+    pub fn select_planes(&mut self, planes: u8) {
+        self.planes = planes & ALL_PLANES;
+    }
 
-                let current_bit = (block >> (7 - bit_number)) & 1;
+    pub fn selected_planes(&self) -> u8 {
+        self.planes
+    }
+
+    /// The number of selected bitplanes, which is how many copies of its
+    /// sprite data a draw consumes.
+    pub fn selected_plane_count(&self) -> usize {
+        self.planes.count_ones() as usize
+    }
+
+    /// XORs a sprite onto the screen, returning which rows turned a pixel off.
+    ///
+    /// `memory` holds the sprite once for every selected plane, lowest plane first.
+    /// The starting position always wraps onto the screen. Parts of the sprite
+    /// that run off the edge wrap around if `wrap` is set and are clipped otherwise.
+    pub fn draw(&mut self, starting_x: u8, starting_y: u8, memory: &[u8], wrap: bool) -> Collision {
+        self.draw_planes(starting_x, starting_y, memory, 1, wrap)
+    }
+
+    /// Like `draw`, but for the 16x16 SUPER-CHIP sprites stored as two bytes per row.
+    pub fn draw_large(&mut self, starting_x: u8, starting_y: u8, memory: &[u8], wrap: bool) -> Collision {
+        self.draw_planes(starting_x, starting_y, memory, 2, wrap)
+    }
+
+    fn draw_planes(&mut self,
+                   starting_x: u8,
+                   starting_y: u8,
+                   memory: &[u8],
+                   bytes_per_row: usize,
+                   wrap: bool)
+                   -> Collision {
+        let plane_count = self.selected_plane_count();
+        if plane_count == 0 || memory.is_empty() {
+            return Collision::default();
+        }
+
+        let bytes_per_plane = memory.len() / plane_count;
+        let row_count = bytes_per_plane / bytes_per_row;
+        let mut rows_hit = vec![false; row_count];
+        let mut plane_sprites = memory.chunks(bytes_per_plane);
+        for plane in 0..NUM_PLANES {
+            let plane_bit = 1 << plane;
+            if self.planes & plane_bit != 0 {
+                let sprite = plane_sprites.next().unwrap_or(&[]);
+                let plane_rows_hit = self.draw_rows(starting_x, starting_y, sprite, bytes_per_row, plane_bit, wrap);
+                for (hit, plane_hit) in rows_hit.iter_mut().zip(plane_rows_hit) {
+                    *hit |= plane_hit;
+                }
+            }
+        }
+
+        let height = self.height();
+        Collision {
+            rows: rows_hit.iter().filter(|&&hit| hit).count(),
+            clipped_rows: if wrap {
+                0
+            } else {
+                (starting_y as usize % height + row_count).saturating_sub(height)
+            },
+        }
+    }
+
+    fn draw_rows(&mut self,
+                 starting_x: u8,
+                 starting_y: u8,
+                 memory: &[u8],
+                 bytes_per_row: usize,
+                 plane_bit: u8,
+                 wrap: bool)
+                 -> Vec<bool> {
+        let width = self.width();
+        let height = self.height();
+        let mut rows_hit = vec![false; memory.len() / bytes_per_row];
+        let starting_x = starting_x as usize % width;
+        let starting_y = starting_y as usize % height;
+
+        for (row_number, row) in memory.chunks(bytes_per_row).enumerate() {
+            let y = starting_y + row_number;
+            if y >= height && !wrap {
+                break;
+            }
+            let y = y % height;
+
+            for bit_number in 0..(row.len() * 8) {
+                let x = starting_x + bit_number;
+                if x >= width && !wrap {
+                    break;
+                }
+                let x = x % width;
+                let current_pixel = self.buffer[y][x] & plane_bit;
+
+                let block = row[bit_number / 8];
+                let current_bit = if (block >> (7 - bit_number % 8)) & 1 == 1 {
+                    plane_bit
+                } else {
+                    0
+                };
                 let new_pixel = current_bit ^ current_pixel;
 
-                self.buffer[y][x] = new_pixel != 0;
+                self.buffer[y][x] = (self.buffer[y][x] & !plane_bit) | new_pixel;
 
-                if current_pixel == 1 && new_pixel == 0 {
-                    pixel_turned_off = true;
+                if current_pixel != 0 && new_pixel == 0 {
+                    rows_hit[row_number] = true;
                 }
             }
         }
-        pixel_turned_off
+        rows_hit
+    }
+
+    pub fn scroll_down(&mut self, rows: usize) {
+        self.scroll(0, rows as isize);
+    }
+
+    pub fn scroll_up(&mut self, rows: usize) {
+        self.scroll(0, -(rows as isize));
+    }
+
+    pub fn scroll_left(&mut self, columns: usize) {
+        self.scroll(-(columns as isize), 0);
+    }
+
+    pub fn scroll_right(&mut self, columns: usize) {
+        self.scroll(columns as isize, 0);
+    }
+
+    /// Moves the selected planes by the given offset. Pixels scrolled off the
+    /// screen are lost and the space they leave behind is blank.
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let width = self.width() as isize;
+        let height = self.height() as isize;
+        let old = self.buffer.clone();
+
+        for y in 0..height {
+            for x in 0..width {
+                let from_x = x - dx;
+                let from_y = y - dy;
+                let moved = if from_x >= 0 && from_x < width && from_y >= 0 && from_y < height {
+                    old[from_y as usize][from_x as usize] & self.planes
+                } else {
+                    0
+                };
+                let pixel = &mut self.buffer[y as usize][x as usize];
+                *pixel = (*pixel & !self.planes) | moved;
+            }
+        }
     }
 
     pub fn get_buffer(&self) -> Buffer {
-        self.buffer
+        self.buffer.clone()
+    }
+
+    /// Renders the screen as text, one line per row, with `*` for lit pixels.
+    pub fn to_ascii(&self) -> String {
+        let mut text = String::new();
+        for row in self.buffer.iter() {
+            text.push('|');
+            for val in row.iter() {
+                if *val != 0 {
+                    text.push('*')
+                } else {
+                    text.push('.')
+                }
+            }
+            text.push_str("|\n")
+        }
+        text
+    }
+
+    /// Renders the screen as text with two rows of pixels to a line, using
+    /// the Unicode half blocks, or `'`, `.` and `:` where Unicode can't be shown.
+    pub fn to_half_blocks(&self, ascii: bool) -> String {
+        let cells = if ascii { [' ', '\'', '.', ':'] } else { [' ', '▀', '▄', '█'] };
+        let mut text = String::new();
+        for rows in self.buffer.chunks(2) {
+            for (x, top) in rows[0].iter().enumerate() {
+                let bottom = rows.get(1).map_or(0, |row| row[x]);
+                text.push(cells[(*top != 0) as usize | ((bottom != 0) as usize) << 1]);
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Renders the screen as a plain (P1) PBM image where any lit pixel is black.
+    pub fn to_pbm(&self) -> String {
+        let mut text = format!("P1\n{} {}\n", self.width(), self.height());
+        for row in self.buffer.iter() {
+            let pixels: Vec<&str> = row.iter().map(|val| if *val != 0 { "1" } else { "0" }).collect();
+            text.push_str(&pixels.join(" "));
+            text.push('\n');
+        }
+        text
+    }
+
+    pub(crate) fn save_state(&self, writer: &mut StateWriter) {
+        writer.bool(self.hires);
+        writer.u8(self.planes);
+        for row in self.buffer.iter() {
+            writer.bytes(row);
+        }
     }
 
+    pub(crate) fn load_state(reader: &mut StateReader) -> Result<Display, StateError> {
+        let mut display = Display::new();
+        display.set_hires(reader.bool()?);
+        display.planes = reader.u8()?;
+        if display.planes & !ALL_PLANES != 0 {
+            return Err(StateError::Corrupt);
+        }
+
+        let width = display.width();
+        for row in display.buffer.iter_mut() {
+            row.copy_from_slice(reader.bytes(width)?);
+            if row.iter().any(|pixel| pixel & !ALL_PLANES != 0) {
+                return Err(StateError::Corrupt);
+            }
+        }
+        Ok(display)
+    }
+
+    /// Clears the selected planes.
     pub fn clear(&mut self) {
-        self.buffer = [[false; WIDTH]; HEIGHT];
+        for row in self.buffer.iter_mut() {
+            for pixel in row.iter_mut() {
+                *pixel &= !self.planes;
+            }
+        }
+    }
+}
+
+fn blank_buffer(width: usize, height: usize) -> Buffer {
+    vec![vec![0; width]; height]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn half_blocks_put_two_rows_in_a_line() {
+        let mut display = Display::new();
+        // A 2x2 square at the top left and a pixel on the second row beside it
+        display.draw(0, 0, &[0xC0, 0xC0], false);
+        display.draw(2, 1, &[0x80], false);
+
+        let text = display.to_half_blocks(false);
+        assert_eq!(text.lines().count(), HEIGHT / 2);
+        assert!(text.starts_with("██▄ "));
+        assert!(display.to_half_blocks(true).starts_with("::. "));
+    }
+
+    #[test]
+    fn collisions_count_rows_once_across_planes() {
+        let mut display = Display::new();
+        display.select_planes(0b11);
+        display.draw(0, 0, &[0x80, 0x80, 0x80, 0x80, 0x80, 0x80], false);
+        // The second row collides in both planes and the third in one
+        let collision = display.draw(0, 0, &[0x00, 0x80, 0x80, 0x00, 0x80, 0x00], false);
+        assert_eq!(collision, Collision { rows: 2, clipped_rows: 0 });
+        assert!(collision.any());
+        assert!(!display.draw(8, 0, &[0x80, 0x00], false).any());
+    }
+
+    #[test]
+    fn rows_off_the_bottom_are_clipped_or_wrapped() {
+        let mut display = Display::new();
+        display.set_hires(true);
+        let sprite = [0xFF; 32];
+        assert_eq!(display.draw_large(0, 60, &sprite, false).clipped_rows, 12);
+        assert_eq!(display.draw_large(16, 60, &sprite, true).clipped_rows, 0);
+        assert_eq!(display.get_buffer()[0][31], 1);
+        assert_eq!(display.get_buffer()[0][15], 0);
+    }
+
+    #[test]
+    fn scrolling_loses_what_goes_off_the_edge() {
+        let mut display = Display::new();
+        display.draw(60, 0, &[0xF0, 0xF0], false);
+        display.scroll_right(2);
+        assert_eq!(&display.get_buffer()[0][60..], &[0, 0, 1, 1]);
+        display.scroll_left(62);
+        assert_eq!(&display.get_buffer()[0][..3], &[1, 1, 0]);
+        display.scroll_up(1);
+        assert_eq!((display.get_buffer()[0][0], display.get_buffer()[1][0]), (1, 0));
+        display.scroll_down(31);
+        assert_eq!(display.get_buffer()[31][0], 1);
+        display.scroll_down(1);
+        assert!(display.get_buffer().iter().all(|row| row.iter().all(|&pixel| pixel == 0)));
     }
 }
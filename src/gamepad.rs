@@ -0,0 +1,249 @@
+//! Playing with a gamepad.
+//!
+//! A `Keymap` says which CHIP-8 key each of a gamepad's controls presses,
+//! naming them by number as `Button7`, `Axis3+` or `Axis3-`. The numbers
+//! are the ones piston passes on, which are the joystick's own ids and so
+//! differ from pad to pad. That rules out a default mapping: a pad does
+//! nothing until a profile has been written for it.
+//!
+//! For pads that do number their controls the way SDL's game controller
+//! layout does, controls can also be named after it, like `A`, `DPadUp` or
+//! `LeftStickLeft`.
+//!
+//! Frontends turn their controller events into `Input`s, which keeps the
+//! mapping independent of any windowing library and lets `SyntheticInput`
+//! stand in for real hardware.
+
+use std::collections::{HashSet, VecDeque};
+
+use chip8::{Chip8, Key, NUM_KEYS};
+use keymap::Keymap;
+
+/// How far a stick has to be pushed, out of 1.0, before it counts as a direction.
+pub const DEAD_ZONE: f64 = 0.5;
+
+const BUTTON_NAMES: [&str; 15] = ["A",
+                                           "B",
+                                           "X",
+                                           "Y",
+                                           "Back",
+                                           "Guide",
+                                           "Start",
+                                           "LeftStick",
+                                           "RightStick",
+                                           "LeftShoulder",
+                                           "RightShoulder",
+                                           "DPadUp",
+                                           "DPadDown",
+                                           "DPadLeft",
+                                           "DPadRight"];
+/// The names of each axis' negative and positive directions. Triggers only
+/// go one way.
+const AXIS_NAMES: [(&str, &str); 6] = [("LeftStickLeft", "LeftStickRight"),
+                                                        ("LeftStickUp", "LeftStickDown"),
+                                                        ("RightStickLeft", "RightStickRight"),
+                                                        ("RightStickUp", "RightStickDown"),
+                                                        ("", "LeftTrigger"),
+                                                        ("", "RightTrigger")];
+
+/// Something that happened on a gamepad.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Input {
+    Button { button: u8, pressed: bool },
+    /// An axis moved to `position`, from -1.0 to 1.0.
+    Axis { axis: u8, position: f64 },
+}
+
+/// Where inputs come from, whether a real gamepad or a script.
+pub trait InputSource {
+    /// The next input, or `None` if nothing has happened since the last call.
+    fn poll(&mut self) -> Option<Input>;
+}
+
+/// Inputs queued up by hand, for trying out a profile without a gamepad.
+pub struct SyntheticInput {
+    inputs: VecDeque<Input>,
+}
+
+impl Default for SyntheticInput {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SyntheticInput {
+    pub fn new() -> SyntheticInput {
+        SyntheticInput { inputs: VecDeque::new() }
+    }
+
+    pub fn push(&mut self, input: Input) {
+        self.inputs.push_back(input);
+    }
+
+    pub fn press(&mut self, button: u8) {
+        self.push(Input::Button {
+            button,
+            pressed: true,
+        });
+    }
+
+    pub fn release(&mut self, button: u8) {
+        self.push(Input::Button {
+            button,
+            pressed: false,
+        });
+    }
+
+    pub fn move_axis(&mut self, axis: u8, position: f64) {
+        self.push(Input::Axis {
+            axis,
+            position,
+        });
+    }
+}
+
+impl InputSource for SyntheticInput {
+    fn poll(&mut self) -> Option<Input> {
+        self.inputs.pop_front()
+    }
+}
+
+/// Turns a gamepad's inputs into CHIP-8 key presses and releases.
+pub struct Gamepad {
+    profile: Keymap,
+    /// The controls being held, by their raw name, e.g. `Button0` or `Axis1-`.
+    held: HashSet<String>,
+    /// How many held controls press each key, since several can share one.
+    holds: [u8; NUM_KEYS],
+}
+
+impl Gamepad {
+    pub fn new(profile: Keymap) -> Gamepad {
+        Gamepad {
+            profile,
+            held: HashSet::new(),
+            holds: [0; NUM_KEYS],
+        }
+    }
+
+    /// Works out which keys an input presses or releases, returning each
+    /// key with `true` for a press. A key held by two controls is only
+    /// released when both are let go.
+    pub fn update(&mut self, input: &Input) -> Vec<(Key, bool)> {
+        let mut changes = Vec::new();
+        match *input {
+            Input::Button { button, pressed } => {
+                let name = BUTTON_NAMES.get(button as usize).cloned().unwrap_or("");
+                self.set(&format!("Button{}", button), name, pressed, &mut changes);
+            }
+            Input::Axis { axis, position } => {
+                let (negative, positive) = AXIS_NAMES.get(axis as usize).cloned().unwrap_or(("", ""));
+                self.set(&format!("Axis{}-", axis), negative, position <= -DEAD_ZONE, &mut changes);
+                self.set(&format!("Axis{}+", axis), positive, position >= DEAD_ZONE, &mut changes);
+            }
+        }
+        changes
+    }
+
+    /// Passes an input on to the machine as key presses and releases.
+    pub fn handle(&mut self, input: &Input, chip8: &mut Chip8) {
+        for (key, pressed) in self.update(input) {
+            if pressed {
+                chip8.handle_key_press(key);
+            } else {
+                chip8.handle_key_release(key);
+            }
+        }
+    }
+
+    /// Handles every input the source has waiting.
+    pub fn poll(&mut self, source: &mut dyn InputSource, chip8: &mut Chip8) {
+        while let Some(input) = source.poll() {
+            self.handle(&input, chip8);
+        }
+    }
+
+    fn set(&mut self, raw_name: &str, name: &str, active: bool, changes: &mut Vec<(Key, bool)>) {
+        if active == self.held.contains(raw_name) {
+            return;
+        }
+        if active {
+            self.held.insert(raw_name.to_string());
+        } else {
+            self.held.remove(raw_name);
+        }
+
+        let key = match self.profile.get(name).or(self.profile.get(raw_name)) {
+            Some(key) => key,
+            None => return,
+        };
+        let holds = &mut self.holds[key as usize];
+        if active {
+            *holds += 1;
+            if *holds == 1 {
+                changes.push((key, true));
+            }
+        } else {
+            *holds -= 1;
+            if *holds == 0 {
+                changes.push((key, false));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use platform::Platform;
+
+    /// Directions on 2, 4, 6 and 8 and an action on 5, for a pad numbered
+    /// the SDL way.
+    fn profile() -> Keymap {
+        let mut profile = Keymap::empty();
+        profile.apply("2 = DPadUp LeftStickUp
+                       8 = DPadDown LeftStickDown
+                       4 = DPadLeft LeftStickLeft
+                       6 = DPadRight LeftStickRight
+                       5 = A")
+            .unwrap();
+        profile
+    }
+
+    #[test]
+    fn buttons_and_sticks_press_keys() {
+        let mut gamepad = Gamepad::new(profile());
+        assert_eq!(gamepad.update(&Input::Button { button: 0, pressed: true }), vec![(0x5, true)]);
+        assert_eq!(gamepad.update(&Input::Button { button: 0, pressed: false }), vec![(0x5, false)]);
+
+        assert_eq!(gamepad.update(&Input::Axis { axis: 1, position: -0.3 }), vec![]);
+        assert_eq!(gamepad.update(&Input::Axis { axis: 1, position: -0.9 }), vec![(0x2, true)]);
+        assert_eq!(gamepad.update(&Input::Axis { axis: 1, position: 0.8 }),
+                   vec![(0x2, false), (0x8, true)]);
+    }
+
+    #[test]
+    fn keys_stay_down_while_any_of_their_controls_is_held() {
+        let mut gamepad = Gamepad::new(profile());
+        assert_eq!(gamepad.update(&Input::Button { button: 13, pressed: true }), vec![(0x4, true)]);
+        assert_eq!(gamepad.update(&Input::Axis { axis: 0, position: -1.0 }), vec![]);
+        assert_eq!(gamepad.update(&Input::Button { button: 13, pressed: false }), vec![]);
+        assert_eq!(gamepad.update(&Input::Axis { axis: 0, position: 0.0 }), vec![(0x4, false)]);
+    }
+
+    #[test]
+    fn profiles_can_bind_raw_controls() {
+        let mut profile = Keymap::empty();
+        profile.apply("7 = Button20 Axis6+").unwrap();
+        let mut gamepad = Gamepad::new(profile);
+
+        let mut input = SyntheticInput::new();
+        input.press(20);
+        input.move_axis(6, 1.0);
+        input.release(20);
+        let mut chip8 = Chip8::new(vec![0x12, 0x00], Platform::Chip8, Platform::Chip8.default_quirks())
+            .unwrap();
+        gamepad.poll(&mut input, &mut chip8);
+        assert_eq!(gamepad.holds[0x7], 1);
+    }
+}
@@ -1,59 +1,281 @@
+use std::fmt;
+
+/// A location in the machine's memory.
 pub type Address = u16;
+/// The number of one of the general purpose registers V0 to VF.
 pub type Register = u8;
 
-#[derive(Debug)]
+/// XO-CHIP's F000 NNNN, the only instruction that is four bytes long. The
+/// address it loads into I is the word that follows it.
+pub const LOAD_LONG_I: u16 = 0xF000;
+
+/// A decoded instruction. Each variant is documented with the opcode it
+/// decodes from and, if it is an extension, the platform that added it.
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Instruction {
+    /// 00E0
     ClearDisplay,
+    /// 00EE
     Return,
+    /// 00CN (SUPER-CHIP)
+    ScrollDown(u8),
+    /// 00DN (XO-CHIP)
+    ScrollUp(u8),
+    /// 00FB (SUPER-CHIP)
+    ScrollRight,
+    /// 00FC (SUPER-CHIP)
+    ScrollLeft,
+    /// 00FD (SUPER-CHIP)
+    Exit,
+    /// 00FE (SUPER-CHIP)
+    LowRes,
+    /// 00FF (SUPER-CHIP)
+    HighRes,
+    /// 1NNN
     Jump(Address),
+    /// 2NNN
     Call(Address),
+    /// 3XNN
     SkipIfEqualsByte(Register, u8),
+    /// 4XNN
     SkipIfNotEqualsByte(Register, u8),
+    /// 5XY0
     SkipIfEqual(Register, Register),
+    /// 5XY2 (XO-CHIP)
+    SaveRange(Register, Register),
+    /// 5XY3 (XO-CHIP)
+    LoadRange(Register, Register),
+    /// 6XNN
     LoadByte(Register, u8),
+    /// 7XNN
     AddByte(Register, u8),
+    /// 8XY0
     Move(Register, Register),
+    /// 8XY1
     Or(Register, Register),
+    /// 8XY2
     And(Register, Register),
+    /// 8XY3
     Xor(Register, Register),
+    /// 8XY4
     Add(Register, Register),
+    /// 8XY5
     Sub(Register, Register),
-    ShiftRight(Register),
+    /// 8XY6
+    ShiftRight(Register, Register),
+    /// 8XY7
     ReverseSub(Register, Register),
-    ShiftLeft(Register),
+    /// 8XYE
+    ShiftLeft(Register, Register),
+    /// 9XY0
     SkipIfNotEqual(Register, Register),
+    /// ANNN
     LoadI(u16),
+    /// F000 NNNN (XO-CHIP)
+    LoadLongI(Address),
+    /// BNNN
     JumpPlusZero(Address),
+    /// CXNN
     Random(Register, u8),
+    /// FN01 (XO-CHIP)
+    SelectPlanes(u8),
+    /// DXYN
     Draw(Register, Register, u8),
+    /// DXY0 (SUPER-CHIP)
+    DrawLarge(Register, Register),
+    /// EX9E
     SkipIfPressed(Register),
+    /// EXA1
     SkipIfNotPressed(Register),
+    /// FX07
     LoadDelayTimer(Register),
+    /// FX0A
     WaitForKeyPress(Register),
+    /// FX15
     SetDelayTimer(Register),
+    /// FX18
     SetSoundTimer(Register),
+    /// FX1E
     AddToI(Register),
+    /// FX29
     LoadSprite(Register),
+    /// FX30 (SUPER-CHIP)
+    LoadLargeSprite(Register),
+    /// FX33
     BCDRepresentation(Register),
+    /// FX55
     StoreRegisters(Register),
+    /// FX65
     LoadRegisters(Register),
+    /// FX75 (SUPER-CHIP)
+    StoreFlags(Register),
+    /// FX85 (SUPER-CHIP)
+    LoadFlags(Register),
+    /// F002 (XO-CHIP)
+    LoadAudioPattern,
+    /// FX3A (XO-CHIP)
+    SetPitch(Register),
+}
+
+impl Instruction {
+    /// Encodes the instruction as an opcode, the inverse of
+    /// `RawInstruction::to_instruction`. `LoadLongI` encodes as `LOAD_LONG_I`
+    /// and its address has to be written as the word after it.
+    pub fn encode(&self) -> u16 {
+        let xy = |x: Register, y: Register| (x as u16 & 0xF) << 8 | (y as u16 & 0xF) << 4;
+        let xnn = |x: Register, nn: u8| (x as u16 & 0xF) << 8 | nn as u16;
+        let x = |x: Register| (x as u16 & 0xF) << 8;
+        match *self {
+            Instruction::ClearDisplay => 0x00E0,
+            Instruction::Return => 0x00EE,
+            Instruction::ScrollDown(n) => 0x00C0 | (n as u16 & 0xF),
+            Instruction::ScrollUp(n) => 0x00D0 | (n as u16 & 0xF),
+            Instruction::ScrollRight => 0x00FB,
+            Instruction::ScrollLeft => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::LowRes => 0x00FE,
+            Instruction::HighRes => 0x00FF,
+            Instruction::Jump(addr) => 0x1000 | (addr & 0xFFF),
+            Instruction::Call(addr) => 0x2000 | (addr & 0xFFF),
+            Instruction::SkipIfEqualsByte(reg, value) => 0x3000 | xnn(reg, value),
+            Instruction::SkipIfNotEqualsByte(reg, value) => 0x4000 | xnn(reg, value),
+            Instruction::SkipIfEqual(reg1, reg2) => 0x5000 | xy(reg1, reg2),
+            Instruction::SaveRange(reg1, reg2) => 0x5002 | xy(reg1, reg2),
+            Instruction::LoadRange(reg1, reg2) => 0x5003 | xy(reg1, reg2),
+            Instruction::LoadByte(reg, value) => 0x6000 | xnn(reg, value),
+            Instruction::AddByte(reg, value) => 0x7000 | xnn(reg, value),
+            Instruction::Move(reg1, reg2) => 0x8000 | xy(reg1, reg2),
+            Instruction::Or(reg1, reg2) => 0x8001 | xy(reg1, reg2),
+            Instruction::And(reg1, reg2) => 0x8002 | xy(reg1, reg2),
+            Instruction::Xor(reg1, reg2) => 0x8003 | xy(reg1, reg2),
+            Instruction::Add(reg1, reg2) => 0x8004 | xy(reg1, reg2),
+            Instruction::Sub(reg1, reg2) => 0x8005 | xy(reg1, reg2),
+            Instruction::ShiftRight(reg1, reg2) => 0x8006 | xy(reg1, reg2),
+            Instruction::ReverseSub(reg1, reg2) => 0x8007 | xy(reg1, reg2),
+            Instruction::ShiftLeft(reg1, reg2) => 0x800E | xy(reg1, reg2),
+            Instruction::SkipIfNotEqual(reg1, reg2) => 0x9000 | xy(reg1, reg2),
+            Instruction::LoadI(addr) => 0xA000 | (addr & 0xFFF),
+            Instruction::LoadLongI(_) => LOAD_LONG_I,
+            Instruction::JumpPlusZero(addr) => 0xB000 | (addr & 0xFFF),
+            Instruction::Random(reg, value) => 0xC000 | xnn(reg, value),
+            Instruction::SelectPlanes(planes) => 0xF001 | x(planes),
+            Instruction::Draw(reg1, reg2, n) => 0xD000 | xy(reg1, reg2) | (n as u16 & 0xF),
+            Instruction::DrawLarge(reg1, reg2) => 0xD000 | xy(reg1, reg2),
+            Instruction::SkipIfPressed(reg) => 0xE09E | x(reg),
+            Instruction::SkipIfNotPressed(reg) => 0xE0A1 | x(reg),
+            Instruction::LoadDelayTimer(reg) => 0xF007 | x(reg),
+            Instruction::WaitForKeyPress(reg) => 0xF00A | x(reg),
+            Instruction::SetDelayTimer(reg) => 0xF015 | x(reg),
+            Instruction::SetSoundTimer(reg) => 0xF018 | x(reg),
+            Instruction::AddToI(reg) => 0xF01E | x(reg),
+            Instruction::LoadSprite(reg) => 0xF029 | x(reg),
+            Instruction::LoadLargeSprite(reg) => 0xF030 | x(reg),
+            Instruction::BCDRepresentation(reg) => 0xF033 | x(reg),
+            Instruction::StoreRegisters(reg) => 0xF055 | x(reg),
+            Instruction::LoadRegisters(reg) => 0xF065 | x(reg),
+            Instruction::StoreFlags(reg) => 0xF075 | x(reg),
+            Instruction::LoadFlags(reg) => 0xF085 | x(reg),
+            Instruction::LoadAudioPattern => 0xF002,
+            Instruction::SetPitch(reg) => 0xF03A | x(reg),
+        }
+    }
+
+    /// The number of bytes the instruction takes up in memory.
+    pub fn size(&self) -> usize {
+        match *self {
+            Instruction::LoadLongI(_) => 4,
+            _ => 2,
+        }
+    }
+}
+
+/// Formats the instruction as assembly in Cowgod's syntax, e.g. `LD V1, #2A`.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::ClearDisplay => write!(f, "CLS"),
+            Instruction::Return => write!(f, "RET"),
+            Instruction::ScrollDown(n) => write!(f, "SCD {}", n),
+            Instruction::ScrollUp(n) => write!(f, "SCU {}", n),
+            Instruction::ScrollRight => write!(f, "SCR"),
+            Instruction::ScrollLeft => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::LowRes => write!(f, "LOW"),
+            Instruction::HighRes => write!(f, "HIGH"),
+            Instruction::Jump(addr) => write!(f, "JP #{:03X}", addr),
+            Instruction::Call(addr) => write!(f, "CALL #{:03X}", addr),
+            Instruction::SkipIfEqualsByte(reg, value) => write!(f, "SE V{:X}, #{:02X}", reg, value),
+            Instruction::SkipIfNotEqualsByte(reg, value) => {
+                write!(f, "SNE V{:X}, #{:02X}", reg, value)
+            }
+            Instruction::SkipIfEqual(reg1, reg2) => write!(f, "SE V{:X}, V{:X}", reg1, reg2),
+            Instruction::SaveRange(reg1, reg2) => write!(f, "SAVE V{:X}, V{:X}", reg1, reg2),
+            Instruction::LoadRange(reg1, reg2) => write!(f, "LOAD V{:X}, V{:X}", reg1, reg2),
+            Instruction::LoadByte(reg, value) => write!(f, "LD V{:X}, #{:02X}", reg, value),
+            Instruction::AddByte(reg, value) => write!(f, "ADD V{:X}, #{:02X}", reg, value),
+            Instruction::Move(reg1, reg2) => write!(f, "LD V{:X}, V{:X}", reg1, reg2),
+            Instruction::Or(reg1, reg2) => write!(f, "OR V{:X}, V{:X}", reg1, reg2),
+            Instruction::And(reg1, reg2) => write!(f, "AND V{:X}, V{:X}", reg1, reg2),
+            Instruction::Xor(reg1, reg2) => write!(f, "XOR V{:X}, V{:X}", reg1, reg2),
+            Instruction::Add(reg1, reg2) => write!(f, "ADD V{:X}, V{:X}", reg1, reg2),
+            Instruction::Sub(reg1, reg2) => write!(f, "SUB V{:X}, V{:X}", reg1, reg2),
+            Instruction::ShiftRight(reg1, reg2) => write!(f, "SHR V{:X}, V{:X}", reg1, reg2),
+            Instruction::ReverseSub(reg1, reg2) => write!(f, "SUBN V{:X}, V{:X}", reg1, reg2),
+            Instruction::ShiftLeft(reg1, reg2) => write!(f, "SHL V{:X}, V{:X}", reg1, reg2),
+            Instruction::SkipIfNotEqual(reg1, reg2) => write!(f, "SNE V{:X}, V{:X}", reg1, reg2),
+            Instruction::LoadI(addr) => write!(f, "LD I, #{:03X}", addr),
+            Instruction::LoadLongI(addr) => write!(f, "LD I, LONG #{:04X}", addr),
+            Instruction::JumpPlusZero(addr) => write!(f, "JP V0, #{:03X}", addr),
+            Instruction::Random(reg, value) => write!(f, "RND V{:X}, #{:02X}", reg, value),
+            Instruction::SelectPlanes(planes) => write!(f, "PLANE {}", planes),
+            Instruction::Draw(reg1, reg2, n) => write!(f, "DRW V{:X}, V{:X}, {}", reg1, reg2, n),
+            Instruction::DrawLarge(reg1, reg2) => write!(f, "DRW V{:X}, V{:X}, 0", reg1, reg2),
+            Instruction::SkipIfPressed(reg) => write!(f, "SKP V{:X}", reg),
+            Instruction::SkipIfNotPressed(reg) => write!(f, "SKNP V{:X}", reg),
+            Instruction::LoadDelayTimer(reg) => write!(f, "LD V{:X}, DT", reg),
+            Instruction::WaitForKeyPress(reg) => write!(f, "LD V{:X}, K", reg),
+            Instruction::SetDelayTimer(reg) => write!(f, "LD DT, V{:X}", reg),
+            Instruction::SetSoundTimer(reg) => write!(f, "LD ST, V{:X}", reg),
+            Instruction::AddToI(reg) => write!(f, "ADD I, V{:X}", reg),
+            Instruction::LoadSprite(reg) => write!(f, "LD F, V{:X}", reg),
+            Instruction::LoadLargeSprite(reg) => write!(f, "LD HF, V{:X}", reg),
+            Instruction::BCDRepresentation(reg) => write!(f, "LD B, V{:X}", reg),
+            Instruction::StoreRegisters(reg) => write!(f, "LD [I], V{:X}", reg),
+            Instruction::LoadRegisters(reg) => write!(f, "LD V{:X}, [I]", reg),
+            Instruction::StoreFlags(reg) => write!(f, "LD R, V{:X}", reg),
+            Instruction::LoadFlags(reg) => write!(f, "LD V{:X}, R", reg),
+            Instruction::LoadAudioPattern => write!(f, "AUDIO"),
+            Instruction::SetPitch(reg) => write!(f, "PITCH V{:X}", reg),
+        }
+    }
 }
 
+/// A two byte opcode as it is stored in memory, before it is decoded.
 pub struct RawInstruction {
     value: u16,
 }
 
 impl RawInstruction {
     pub fn new(value: u16) -> RawInstruction {
-        RawInstruction { value: value }
+        RawInstruction { value }
     }
 
+    /// Decodes the opcode, returning `None` if it isn't an instruction on any platform.
+    ///
+    /// The four byte `LoadLongI` can't be decoded from a single word; see `LOAD_LONG_I`.
     pub fn to_instruction(&self) -> Option<Instruction> {
         match self.xooo() {
             0x0 => {
                 match self.ooxx() {
                     0xE0 => Some(Instruction::ClearDisplay),
                     0xEE => Some(Instruction::Return),
+                    0xFB => Some(Instruction::ScrollRight),
+                    0xFC => Some(Instruction::ScrollLeft),
+                    0xFD => Some(Instruction::Exit),
+                    0xFE => Some(Instruction::LowRes),
+                    0xFF => Some(Instruction::HighRes),
+                    _ if self.ooxo() == 0xC => Some(Instruction::ScrollDown(self.ooox())),
+                    _ if self.ooxo() == 0xD => Some(Instruction::ScrollUp(self.ooox())),
                     _ => None,
                 }
             }
@@ -61,7 +283,13 @@ impl RawInstruction {
             0x2 => Some(Instruction::Call(self.oxxx())),
             0x3 => Some(Instruction::SkipIfEqualsByte(self.oxoo(), self.ooxx())),
             0x4 => Some(Instruction::SkipIfNotEqualsByte(self.oxoo(), self.ooxx())),
-            0x5 => Some(Instruction::SkipIfEqual(self.oxoo(), self.ooxo())),
+            0x5 => {
+                match self.ooox() {
+                    0x2 => Some(Instruction::SaveRange(self.oxoo(), self.ooxo())),
+                    0x3 => Some(Instruction::LoadRange(self.oxoo(), self.ooxo())),
+                    _ => Some(Instruction::SkipIfEqual(self.oxoo(), self.ooxo())),
+                }
+            }
             0x6 => Some(Instruction::LoadByte(self.oxoo(), self.ooxx())),
             0x7 => Some(Instruction::AddByte(self.oxoo(), self.ooxx())),
             0x8 => {
@@ -72,9 +300,9 @@ impl RawInstruction {
                     0x3 => Some(Instruction::Xor(self.oxoo(), self.ooxo())),
                     0x4 => Some(Instruction::Add(self.oxoo(), self.ooxo())),
                     0x5 => Some(Instruction::Sub(self.oxoo(), self.ooxo())),
-                    0x6 => Some(Instruction::ShiftRight(self.oxoo())),
+                    0x6 => Some(Instruction::ShiftRight(self.oxoo(), self.ooxo())),
                     0x7 => Some(Instruction::ReverseSub(self.oxoo(), self.ooxo())),
-                    0xE => Some(Instruction::ShiftLeft(self.oxoo())),
+                    0xE => Some(Instruction::ShiftLeft(self.oxoo(), self.ooxo())),
                     _ => None,
                 }
             }
@@ -82,7 +310,12 @@ impl RawInstruction {
             0xA => Some(Instruction::LoadI(self.oxxx())),
             0xB => Some(Instruction::JumpPlusZero(self.oxxx())),
             0xC => Some(Instruction::Random(self.oxoo(), self.ooxx())),
-            0xD => Some(Instruction::Draw(self.oxoo(), self.ooxo(), self.ooox())),
+            0xD => {
+                match self.ooox() {
+                    0x0 => Some(Instruction::DrawLarge(self.oxoo(), self.ooxo())),
+                    n => Some(Instruction::Draw(self.oxoo(), self.ooxo(), n)),
+                }
+            }
             0xE => {
                 match self.ooxx() {
                     0x9E => Some(Instruction::SkipIfPressed(self.oxoo())),
@@ -92,15 +325,21 @@ impl RawInstruction {
             }
             0xF => {
                 match self.ooxx() {
+                    0x01 => Some(Instruction::SelectPlanes(self.oxoo())),
+                    0x02 if self.oxoo() == 0 => Some(Instruction::LoadAudioPattern),
                     0x07 => Some(Instruction::LoadDelayTimer(self.oxoo())),
                     0x0A => Some(Instruction::WaitForKeyPress(self.oxoo())),
                     0x15 => Some(Instruction::SetDelayTimer(self.oxoo())),
                     0x18 => Some(Instruction::SetSoundTimer(self.oxoo())),
                     0x1E => Some(Instruction::AddToI(self.oxoo())),
                     0x29 => Some(Instruction::LoadSprite(self.oxoo())),
+                    0x30 => Some(Instruction::LoadLargeSprite(self.oxoo())),
+                    0x3A => Some(Instruction::SetPitch(self.oxoo())),
                     0x33 => Some(Instruction::BCDRepresentation(self.oxoo())),
                     0x55 => Some(Instruction::StoreRegisters(self.oxoo())),
                     0x65 => Some(Instruction::LoadRegisters(self.oxoo())),
+                    0x75 => Some(Instruction::StoreFlags(self.oxoo())),
+                    0x85 => Some(Instruction::LoadFlags(self.oxoo())),
                     _ => None,
                 }
             }
@@ -138,3 +377,31 @@ impl RawInstruction {
         self.value & 0xFFF
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every opcode that decodes must encode back to an opcode that decodes
+    /// to the same instruction. Opcodes with don't-care nibbles, like 5XY1,
+    /// encode to the canonical form, so the opcodes themselves can differ.
+    #[test]
+    fn encode_is_the_inverse_of_decode() {
+        for raw in 0..=0xFFFF {
+            if let Some(instruction) = RawInstruction::new(raw).to_instruction() {
+                let encoded = instruction.encode();
+                assert_eq!(RawInstruction::new(encoded).to_instruction(),
+                           Some(instruction),
+                           "{:04X} encoded as {:04X}",
+                           raw,
+                           encoded);
+            }
+        }
+    }
+
+    #[test]
+    fn load_long_i_encodes_as_its_prefix() {
+        assert_eq!(Instruction::LoadLongI(0x1234).encode(), LOAD_LONG_I);
+        assert_eq!(Instruction::LoadLongI(0x1234).size(), 4);
+    }
+}
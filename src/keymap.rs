@@ -0,0 +1,139 @@
+//! Which keys on the host's keyboard press which CHIP-8 keys.
+//!
+//! Host keys are named the way a frontend chooses, e.g. `Q`, `1` or `Up`,
+//! and compared without regard to case. A CHIP-8 key can have any number of
+//! host keys.
+//!
+//! A keymap file lists CHIP-8 keys in hex followed by the host keys that
+//! press them. Each line replaces all the host keys of its CHIP-8 key:
+//!
+//! ```text
+//! # Pong: move with the arrow keys as well as 1/Q and 4/R
+//! 1 = 1 Up
+//! 4 = Q Down
+//! ```
+
+use std::collections::HashMap;
+
+use chip8::{Key, NUM_KEYS};
+
+/// The keys of the COSMAC VIP's hex keypad, as laid out on it.
+const COSMAC_LAYOUT: [[Key; 4]; 4] = [[0x1, 0x2, 0x3, 0xC],
+                                       [0x4, 0x5, 0x6, 0xD],
+                                       [0x7, 0x8, 0x9, 0xE],
+                                       [0xA, 0x0, 0xB, 0xF]];
+/// The block of keys on a QWERTY keyboard in the same place as the keypad.
+const QWERTY_BLOCK: [[&str; 4]; 4] = [["1", "2", "3", "4"],
+                                              ["Q", "W", "E", "R"],
+                                              ["A", "S", "D", "F"],
+                                              ["Z", "X", "C", "V"]];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keymap {
+    /// Host key names, in lowercase, and the CHIP-8 key each one presses.
+    keys: HashMap<String, Key>,
+}
+
+impl Keymap {
+    /// A keymap with no keys bound.
+    pub fn empty() -> Keymap {
+        Keymap { keys: HashMap::new() }
+    }
+
+    /// The COSMAC VIP keypad laid over the `1234`/`QWER`/`ASDF`/`ZXCV`
+    /// block, which is where most programs expect their keys to be.
+    pub fn standard() -> Keymap {
+        let mut keymap = Keymap::empty();
+        for (keys, names) in COSMAC_LAYOUT.iter().zip(QWERTY_BLOCK.iter()) {
+            for (key, name) in keys.iter().zip(names.iter()) {
+                keymap.bind(name, *key);
+            }
+        }
+        keymap
+    }
+
+    /// Makes the host key press `key`, in addition to any other host keys
+    /// that already press it.
+    pub fn bind(&mut self, host_key: &str, key: Key) {
+        self.keys.insert(host_key.to_lowercase(), key);
+    }
+
+    /// Stops any host key from pressing `key`.
+    pub fn unbind(&mut self, key: Key) {
+        self.keys.retain(|_, bound| *bound != key);
+    }
+
+    /// Whether no host key presses anything.
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// The CHIP-8 key pressed by a host key, if there is one.
+    pub fn get(&self, host_key: &str) -> Option<Key> {
+        self.keys.get(&host_key.to_lowercase()).cloned()
+    }
+
+    /// The host keys that press `key`, in lowercase and in no particular order.
+    pub fn host_keys(&self, key: Key) -> Vec<&str> {
+        self.keys.iter().filter(|&(_, bound)| *bound == key).map(|(name, _)| &name[..]).collect()
+    }
+
+    /// Applies the lines of a keymap file on top of this keymap. Errors give
+    /// the line number they are on.
+    pub fn apply(&mut self, text: &str) -> Result<(), String> {
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let mut sides = line.splitn(2, '=');
+            let key = sides.next().unwrap_or("").trim();
+            let host_keys = sides.next().ok_or(format!("line {}: expected KEY = HOST KEYS", i + 1))?;
+            let key = match Key::from_str_radix(key, 16) {
+                Ok(key) if (key as usize) < NUM_KEYS => key,
+                _ => return Err(format!("line {}: '{}' is not a key from 0 to F", i + 1, key)),
+            };
+
+            self.unbind(key);
+            for host_key in host_keys.split_whitespace() {
+                self.bind(host_key, key);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        Keymap::standard()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn standard_layout_matches_the_cosmac_keypad() {
+        let keymap = Keymap::standard();
+        assert_eq!(keymap.get("1"), Some(0x1));
+        assert_eq!(keymap.get("q"), Some(0x4));
+        assert_eq!(keymap.get("X"), Some(0x0));
+        assert_eq!(keymap.get("V"), Some(0xF));
+        assert_eq!(keymap.get("5"), None);
+    }
+
+    #[test]
+    fn files_replace_the_keys_they_list() {
+        let mut keymap = Keymap::standard();
+        keymap.apply("# move with the arrows\n4 = Q Up\n6 = \n").unwrap();
+        assert_eq!(keymap.get("up"), Some(0x4));
+        assert_eq!(keymap.get("q"), Some(0x4));
+        assert_eq!(keymap.get("e"), None);
+        assert_eq!(keymap.get("w"), Some(0x5));
+
+        assert!(keymap.apply("G = Up").is_err());
+        assert!(keymap.apply("4 Up").is_err());
+    }
+}
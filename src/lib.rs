@@ -0,0 +1,56 @@
+//! The core of the Rust-8 CHIP-8 emulator.
+//!
+//! This crate has no windowing or sound dependencies. A frontend creates a
+//! `Chip8` from a ROM, calls `cycle` with the time that has passed, forwards
+//! key presses to it and draws its `Display`:
+//!
+//! ```no_run
+//! use rust_8::{Chip8, Platform};
+//!
+//! let rom = std::fs::read("pong.ch8").unwrap();
+//! let mut computer = Chip8::new(rom, Platform::Chip8, Platform::Chip8.default_quirks()).unwrap();
+//!
+//! computer.handle_key_press(0x1);
+//! computer.step_frame().unwrap();
+//! for row in computer.display.get_buffer() {
+//!     // draw the row
+//! }
+//! ```
+
+#[cfg(feature = "os-random")]
+extern crate rand;
+
+pub mod asm;
+pub mod audio;
+pub mod chip8;
+pub mod cli;
+pub mod debugger;
+pub mod disasm;
+pub mod display;
+pub mod gamepad;
+pub mod instruction;
+mod json;
+pub mod keymap;
+pub mod movie;
+pub mod octo;
+pub mod platform;
+pub mod quirks;
+pub mod random;
+pub mod rewind;
+pub mod romdb;
+pub mod session;
+mod sha1;
+mod state;
+pub mod trace;
+#[cfg(any(target_arch = "wasm32", test))]
+pub mod wasm;
+
+pub use audio::AudioSink;
+pub use chip8::{Chip8, Chip8Error, Key, NUM_KEYS};
+pub use display::Display;
+pub use instruction::{Instruction, RawInstruction};
+pub use platform::Platform;
+pub use quirks::Quirks;
+pub use random::RandomSource;
+pub use sha1::Hash;
+pub use state::StateError;
@@ -1,94 +1,331 @@
+extern crate crossterm;
 extern crate piston_window;
-extern crate rand;
+extern crate rodio;
+extern crate rust_8;
 
-mod display;
-mod instruction;
-mod chip8;
+mod speaker;
+mod terminal;
 use std::env;
-use std::fs::File;
-use std::io::Read;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read, Write};
+use std::process;
 
 use piston_window::*;
+use rust_8::{chip8, display, Chip8};
+use rust_8::cli::{self, Command, Options};
+use rust_8::gamepad::{self, Gamepad};
+use rust_8::keymap::Keymap;
+use rust_8::movie::Movie;
+use rust_8::romdb::Database;
+use rust_8::session::Session;
 
-const ENLARGEMENT_FACTOR: usize = 20;
-const WINDOW_DIMENSIONS: [u32; 2] = [(display::WIDTH * ENLARGEMENT_FACTOR) as u32,
-                                     (display::HEIGHT * ENLARGEMENT_FACTOR) as u32];
+/// The colours for each combination of the two XO-CHIP bitplanes. Programs
+/// that only use the first plane just see the first two.
+const DEFAULT_PALETTE: [[f32; 4]; 4] = [[0.0, 0.0, 0.0, 1.0],
+                                        [1.0, 1.0, 1.0, 1.0],
+                                        [0.67, 0.67, 0.67, 1.0],
+                                        [0.33, 0.33, 0.33, 1.0]];
+/// Where the ROM database and the local overrides for it are kept, under the home directory.
+const DATABASE_PATH: &str = ".rust-8/programs.json";
+const LOCAL_DATABASE_PATH: &str = ".rust-8/local.json";
 
 fn main() {
-    let file_name = env::args().nth(1).expect("Must give game name as first file");
-    let mut file = File::open(file_name).expect("There was an issue opening the file");
+    let options = match cli::parse_args(env::args().skip(1).collect()) {
+        Ok(Command::Run(options)) => options,
+        Ok(Command::Help) => {
+            println!("{}", cli::USAGE);
+            return;
+        }
+        Err(message) => {
+            eprintln!("{}", message);
+            process::exit(2);
+        }
+    };
+    let file_name = options.game.clone();
+
     let mut game_data = Vec::new();
-    file.read_to_end(&mut game_data).expect("Failure to read file");
+    if let Err(error) = File::open(&file_name).and_then(|mut file| file.read_to_end(&mut game_data)) {
+        eprintln!("Could not read {}: {}", file_name, error);
+        process::exit(1);
+    }
+    let config = load_database(&options).lookup(&game_data).cloned().unwrap_or_default();
+    if let Some(ref title) = config.title {
+        println!("Playing {}", title);
+    }
+
+    let (platform, quirks) = config.machine(options.platform, options.quirks);
+    let mut palette = DEFAULT_PALETTE;
+    for (entry, colour) in palette.iter_mut().zip(config.colours.iter()) {
+        *entry = rgba(colour);
+    }
+    if let Some(ref background) = options.background {
+        palette[0] = rgba(background);
+    }
+    if let Some(ref foreground) = options.foreground {
+        palette[1] = rgba(foreground);
+    }
 
-    let window: PistonWindow = WindowSettings::new("Rust-8 Emulator", WINDOW_DIMENSIONS)
-                                   .exit_on_esc(true)
-                                   .build()
-                                   .unwrap();
-    let mut computer = chip8::Chip8::new(game_data);
+    // A movie brings its own platform, quirks, clock rate and seed
+    let movie = options.play.as_ref().map(|path| read_movie(path));
+    let computer = match movie {
+        Some(ref movie) => movie.start(game_data),
+        None => Chip8::new(game_data, platform, quirks).map_err(|error| error.to_string()),
+    };
+    let mut computer = match computer {
+        Ok(computer) => computer,
+        Err(error) => {
+            eprintln!("Could not load the game: {}", error);
+            process::exit(1);
+        }
+    };
+    if movie.is_none() {
+        computer.set_clock_rate(options.clock_rate.or(config.clock_rate).unwrap_or(chip8::DEFAULT_CLOCK_RATE));
+    }
+    if !options.mute {
+        if let Some(speaker) = speaker::Speaker::new() {
+            computer.audio = Box::new(speaker);
+        }
+    }
+
+    let mut session = Session::new(computer, movie.as_ref(), options.record.is_some());
+    session.set_paused(options.paused);
+    let mut keymap = Keymap::standard();
+    config.bind_keys(&mut keymap);
+    let keymap = load_keymap(&file_name, "keys", keymap);
 
+    let window: Option<PistonWindow> = if options.terminal {
+        None
+    } else {
+        let window_dimensions = [display::WIDTH as u32 * options.scale, display::HEIGHT as u32 * options.scale];
+        match WindowSettings::new("Rust-8 Emulator", window_dimensions)
+                  .exit_on_esc(true)
+                  .fullscreen(options.fullscreen)
+                  .build() {
+            Ok(window) => Some(window),
+            Err(error) => {
+                eprintln!("Could not open a window, so playing in the terminal: {}", error);
+                None
+            }
+        }
+    };
+    match window {
+        Some(window) => {
+            let profile = load_keymap(&file_name, "pad", Keymap::empty());
+            if profile.is_empty() {
+                println!("No gamepad mapping is loaded. Every pad numbers its buttons differently, \
+                          so write one to {}.pad to play with a gamepad.",
+                         file_name);
+            }
+            let gamepad = Gamepad::new(profile);
+            run_window(window, &mut session, &file_name, &keymap, gamepad, &palette);
+        }
+        None => {
+            if let Err(error) = terminal::run(&mut session, &file_name, &keymap, &palette, options.ascii) {
+                eprintln!("{}", error);
+            }
+        }
+    }
+
+    if let (Some(path), Some(recording)) = (options.record, session.recording()) {
+        match File::create(&path).and_then(|mut file| file.write_all(recording.to_text().as_bytes())) {
+            Ok(()) => println!("Saved the movie to {}", path),
+            Err(error) => eprintln!("Could not save the movie to {}: {}", path, error),
+        }
+    }
+}
+
+/// Runs the game in the window until it exits or the window is closed.
+fn run_window(window: PistonWindow,
+              session: &mut Session,
+              file_name: &str,
+              keymap: &Keymap,
+              mut gamepad: Gamepad,
+              palette: &[[f32; 4]; 4]) {
     for e in window {
-        if let Some(_) = e.render_args() {
-            draw_screen(&computer.display.get_buffer(), &e);
+        if e.render_args().is_some() {
+            draw_screen(&session.computer.display.get_buffer(), palette, &e);
         }
 
         if let Some(u) = e.update_args() {
-            computer.cycle(u.dt);
+            if let Err(error) = session.update(u.dt) {
+                eprintln!("The emulator stopped: {}", error);
+                eprintln!("{:?}", session.computer);
+                break;
+            }
+            if session.computer.has_exited() {
+                break;
+            }
+        }
+
+        // Piston's controller button and axis numbers are the joystick's raw
+        // ids, so which key they press is up to the game's pad profile
+        let mut input = None;
+        if let Some(Button::Controller(button)) = e.press_args() {
+            input = Some(gamepad::Input::Button {
+                button: button.button,
+                pressed: true,
+            });
+        }
+        if let Some(Button::Controller(button)) = e.release_args() {
+            input = Some(gamepad::Input::Button {
+                button: button.button,
+                pressed: false,
+            });
+        }
+        if let Some(axis) = e.controller_axis_args() {
+            input = Some(gamepad::Input::Axis {
+                axis: axis.axis,
+                position: axis.position,
+            });
+        }
+        if let Some(input) = input {
+            for (key_value, pressed) in gamepad.update(&input) {
+                session.send_key(key_value, pressed);
+            }
         }
 
         if let Some(Button::Keyboard(key)) = e.release_args() {
-            if let Some(key_value) = key_value(&key) {
-                computer.handle_key_release(key_value);
+            if key == Key::Backspace {
+                session.stop_rewinding();
+            } else if let Some(key_value) = keymap.get(&key_name(&key)) {
+                session.send_key(key_value, false);
             }
         }
 
         if let Some(Button::Keyboard(key)) = e.press_args() {
-            if let Some(key_value) = key_value(&key) {
-                computer.handle_key_press(key_value);
+            if key == Key::Backspace {
+                session.start_rewinding();
+            } else if key == Key::F9 {
+                let paused = session.is_paused();
+                session.set_paused(!paused);
+            } else if let Some(slot) = save_slot(&key) {
+                match session.save_slot(file_name, slot) {
+                    Ok(()) => println!("Saved to slot {}", slot),
+                    Err(error) => eprintln!("{}", error),
+                }
+            } else if let Some(slot) = load_slot(&key) {
+                match session.load_slot(file_name, slot) {
+                    Ok(()) => println!("Loaded slot {}", slot),
+                    Err(error) => eprintln!("{}", error),
+                }
+            } else if let Some(key_value) = keymap.get(&key_name(&key)) {
+                session.send_key(key_value, true);
             }
         }
     }
 }
 
-fn key_value(key: &Key) -> Option<u8> {
-    if key.code() >= 48 && key.code() <= 57 {
-        Some((key.code() - 48) as u8)
-    } else if key.code() >= 97 && key.code() <= 102 {
-        Some((key.code() - 97 + 10) as u8)
-    } else {
-        None
+fn read_movie(path: &str) -> Movie {
+    match fs::read_to_string(path).map_err(|error| error.to_string()).and_then(|text| Movie::parse(&text)) {
+        Ok(movie) => movie,
+        Err(error) => {
+            eprintln!("Could not read {}: {}", path, error);
+            process::exit(1);
+        }
+    }
+}
+
+fn rgba(colour: &[u8; 3]) -> [f32; 4] {
+    [colour[0] as f32 / 255.0, colour[1] as f32 / 255.0, colour[2] as f32 / 255.0, 1.0]
+}
+
+/// The ROM database with the local overrides merged over it. Either can
+/// be missing.
+fn load_database(options: &Options) -> Database {
+    let home = env::var("HOME").unwrap_or_default();
+    let mut database = match options.database {
+        Some(ref path) => read_database(path, true),
+        None => read_database(&format!("{}/{}", home, DATABASE_PATH), false),
+    };
+    database.merge(read_database(&format!("{}/{}", home, LOCAL_DATABASE_PATH), false));
+    database
+}
+
+/// An empty database if the file can't be read, which is only worth
+/// mentioning if it was asked for or is broken.
+fn read_database(path: &str, required: bool) -> Database {
+    match fs::read_to_string(path) {
+        Ok(text) => {
+            Database::parse(&text).unwrap_or_else(|error| {
+                eprintln!("Ignoring {}: {}", path, error);
+                Database::new()
+            })
+        }
+        Err(ref error) if error.kind() == ErrorKind::NotFound && !required => Database::new(),
+        Err(error) => {
+            eprintln!("Could not read {}: {}", path, error);
+            Database::new()
+        }
     }
 }
 
-fn draw_screen(display_buffer: &display::Buffer, window: &PistonWindow) {
+/// `standard`, with anything the game's `$GAME.keys` or `$GAME.pad` file rebinds.
+fn load_keymap(file_name: &str, extension: &str, standard: Keymap) -> Keymap {
+    let mut keymap = standard.clone();
+    let path = format!("{}.{}", file_name, extension);
+    match fs::read_to_string(&path) {
+        Ok(text) => {
+            if let Err(error) = keymap.apply(&text) {
+                eprintln!("Ignoring {}: {}", path, error);
+                return standard;
+            }
+        }
+        Err(ref error) if error.kind() == ErrorKind::NotFound => {}
+        Err(error) => eprintln!("Could not read {}: {}", path, error),
+    }
+    keymap
+}
+
+/// The name a keymap file uses for a key, which is piston's name for it
+/// except for the digits, which piston calls D0 to D9.
+fn key_name(key: &Key) -> String {
+    let name = format!("{:?}", key);
+    match name.strip_prefix('D') {
+        Some(digit) if digit.len() == 1 => digit.to_string(),
+        _ => name,
+    }
+}
+
+/// F1 to F4 quick-save to slots 1 to 4.
+fn save_slot(key: &Key) -> Option<u8> {
+    match *key {
+        Key::F1 => Some(1),
+        Key::F2 => Some(2),
+        Key::F3 => Some(3),
+        Key::F4 => Some(4),
+        _ => None,
+    }
+}
+
+/// F5 to F8 quick-load from slots 1 to 4.
+fn load_slot(key: &Key) -> Option<u8> {
+    match *key {
+        Key::F5 => Some(1),
+        Key::F6 => Some(2),
+        Key::F7 => Some(3),
+        Key::F8 => Some(4),
+        _ => None,
+    }
+}
+
+fn draw_screen(display_buffer: &display::Buffer, palette: &[[f32; 4]; 4], window: &PistonWindow) {
+    // Hi-res mode has twice the pixels in the same window, so each is half the size
+    let pixel_size = window.size().height as usize / display_buffer.len();
+
     window.draw_2d(|context, graphics| {
-        piston_window::clear(color::BLACK, graphics);
+        piston_window::clear(palette[0], graphics);
 
         for (i, row) in display_buffer.iter().enumerate() {
             for (j, val) in row.iter().enumerate() {
-                if *val {
-                    let dimensions = [(j * ENLARGEMENT_FACTOR) as f64,
-                                      (i * ENLARGEMENT_FACTOR) as f64,
-                                      ENLARGEMENT_FACTOR as f64,
-                                      ENLARGEMENT_FACTOR as f64];
-                    Rectangle::new(color::WHITE)
+                if *val != 0 {
+                    let dimensions = [(j * pixel_size) as f64,
+                                      (i * pixel_size) as f64,
+                                      pixel_size as f64,
+                                      pixel_size as f64];
+                    Rectangle::new(palette[*val as usize])
                         .draw(dimensions, &context.draw_state, context.transform, graphics);
                 }
             }
         }
     })
 }
-
-#[allow(dead_code)]
-fn debug(display_buffer: &display::Buffer) {
-    for row in display_buffer.iter() {
-        print!("|");
-        for val in row.iter() {
-            if *val {
-                print!("*")
-            } else {
-                print!(".")
-            }
-        }
-        println!("|")
-    }
-}
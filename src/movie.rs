@@ -0,0 +1,321 @@
+//! Recording key presses so a game can be played back exactly.
+//!
+//! A movie holds everything a fresh machine needs to repeat a game: which
+//! ROM it was, the platform, quirks and clock rate, the seed for CXNN's
+//! random numbers and every key press and release with the frame it came
+//! before. Movies always start from the moment the machine was created.
+//!
+//! Movie files are text, so they can be read and edited by hand:
+//!
+//! ```text
+//! # rust-8 movie
+//! rom 0df2789f661358d8f7370e6cf93490c5bcd44b01
+//! platform chip8
+//! quirks shift_uses_vy load_store_increments_i logic_resets_vf
+//! clock 600
+//! seed 12345
+//! 30:+5
+//! 40:-5
+//! ```
+//!
+//! Events are written like the headless runner's `--keys`: `30:+5` presses
+//! key 5 before frame 30 runs and `40:-5` releases it before frame 40.
+
+use std::fmt::Write;
+
+use chip8::{Chip8, Chip8Error, Key, NUM_KEYS};
+use platform::Platform;
+use quirks::Quirks;
+use sha1::{self, Hash};
+
+const HEADER: &str = "# rust-8 movie";
+const QUIRK_NAMES: [&str; 6] = ["shift_uses_vy",
+                                        "load_store_increments_i",
+                                        "load_store_increments_i_by_x",
+                                        "logic_resets_vf",
+                                        "jump_uses_vx",
+                                        "wrap_sprites"];
+
+/// A key going down or up before the given frame ran.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MovieEvent {
+    pub frame: u64,
+    pub key: Key,
+    pub pressed: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Movie {
+    pub rom_hash: Hash,
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub clock_rate: f64,
+    pub seed: u64,
+    pub events: Vec<MovieEvent>,
+}
+
+impl Movie {
+    /// Starts recording a machine. It should not have run yet, since the
+    /// movie can only be played back from the start.
+    pub fn new(chip8: &Chip8) -> Movie {
+        Movie {
+            rom_hash: chip8.rom_hash(),
+            platform: chip8.platform(),
+            quirks: chip8.quirks(),
+            clock_rate: chip8.clock_rate(),
+            seed: chip8.seed(),
+            events: Vec::new(),
+        }
+    }
+
+    /// Presses a key on the machine and records it.
+    pub fn press(&mut self, chip8: &mut Chip8, key: Key) {
+        self.record(chip8, key, true);
+        chip8.handle_key_press(key);
+    }
+
+    /// Releases a key on the machine and records it.
+    pub fn release(&mut self, chip8: &mut Chip8, key: Key) {
+        self.record(chip8, key, false);
+        chip8.handle_key_release(key);
+    }
+
+    fn record(&mut self, chip8: &Chip8, key: Key, pressed: bool) {
+        self.events.push(MovieEvent {
+            frame: chip8.frame_count(),
+            key,
+            pressed,
+        });
+    }
+
+    /// A fresh machine set up the way the recorded one was, ready to play
+    /// the movie back with a `Playback`. Fails if `rom` isn't the ROM the
+    /// movie was recorded with.
+    pub fn start(&self, rom: Vec<u8>) -> Result<Chip8, String> {
+        if sha1::sha1(&rom) != self.rom_hash {
+            return Err(format!("the movie was recorded with a different ROM, {}",
+                               sha1::to_hex(&self.rom_hash)));
+        }
+        let mut chip8 = Chip8::new(rom, self.platform, self.quirks).map_err(|error| error.to_string())?;
+        chip8.set_clock_rate(self.clock_rate);
+        chip8.set_seed(self.seed);
+        Ok(chip8)
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        let quirks = quirk_flags(&self.quirks);
+        let enabled: Vec<&str> = QUIRK_NAMES.iter()
+            .zip(quirks.iter())
+            .filter(|&(_, enabled)| *enabled)
+            .map(|(name, _)| *name)
+            .collect();
+
+        writeln!(text, "{}", HEADER).unwrap();
+        writeln!(text, "rom {}", sha1::to_hex(&self.rom_hash)).unwrap();
+        writeln!(text, "platform {}", self.platform).unwrap();
+        writeln!(text, "quirks {}", enabled.join(" ")).unwrap();
+        writeln!(text, "clock {}", self.clock_rate).unwrap();
+        writeln!(text, "seed {}", self.seed).unwrap();
+        for event in self.events.iter() {
+            writeln!(text, "{}:{}{:X}", event.frame, if event.pressed { '+' } else { '-' }, event.key).unwrap();
+        }
+        text
+    }
+
+    /// Reads a movie written by `to_text`. Errors give the line they are on.
+    pub fn parse(text: &str) -> Result<Movie, String> {
+        let mut lines = text.lines().enumerate().filter(|&(_, line)| !line.trim().is_empty());
+        match lines.next() {
+            Some((_, line)) if line.trim() == HEADER => {}
+            _ => return Err("not a movie".to_string()),
+        }
+
+        let mut rom_hash = None;
+        let mut platform = None;
+        let mut quirks = None;
+        let mut clock_rate = None;
+        let mut seed = None;
+        let mut events = Vec::new();
+        for (i, line) in lines {
+            let error = |message: &str| format!("line {}: {}", i + 1, message);
+            let line = line.trim();
+            if line.starts_with('#') {
+                continue;
+            }
+
+            let mut words = line.splitn(2, ' ');
+            let name = words.next().unwrap_or("");
+            let value = words.next().unwrap_or("").trim();
+            match name {
+                "rom" => rom_hash = Some(parse_hash(value).ok_or_else(|| error("bad ROM hash"))?),
+                "platform" => platform = Some(value.parse().map_err(|message: String| error(&message))?),
+                "quirks" => {
+                    let mut flags = [false; 6];
+                    for quirk in value.split_whitespace() {
+                        let index = QUIRK_NAMES.iter()
+                            .position(|name| *name == quirk)
+                            .ok_or_else(|| error(&format!("unknown quirk '{}'", quirk)))?;
+                        flags[index] = true;
+                    }
+                    quirks = Some(Quirks {
+                        shift_uses_vy: flags[0],
+                        load_store_increments_i: flags[1],
+                        load_store_increments_i_by_x: flags[2],
+                        logic_resets_vf: flags[3],
+                        jump_uses_vx: flags[4],
+                        wrap_sprites: flags[5],
+                    });
+                }
+                "clock" => clock_rate = Some(value.parse().map_err(|_| error("bad clock rate"))?),
+                "seed" => seed = Some(value.parse().map_err(|_| error("bad seed"))?),
+                _ => events.push(parse_event(line).ok_or_else(|| error("expected FRAME:+KEY or FRAME:-KEY"))?),
+            }
+        }
+
+        Ok(Movie {
+            rom_hash: rom_hash.ok_or("the movie has no ROM hash")?,
+            platform: platform.ok_or("the movie has no platform")?,
+            quirks: quirks.ok_or("the movie has no quirks")?,
+            clock_rate: clock_rate.ok_or("the movie has no clock rate")?,
+            seed: seed.ok_or("the movie has no seed")?,
+            events,
+        })
+    }
+}
+
+/// Feeds a movie's key presses to a machine as it runs.
+pub struct Playback<'a> {
+    movie: &'a Movie,
+    next_event: usize,
+}
+
+impl<'a> Playback<'a> {
+    pub fn new(movie: &'a Movie) -> Playback<'a> {
+        Playback {
+            movie,
+            next_event: 0,
+        }
+    }
+
+    /// Presses and releases the keys due before the machine's next frame.
+    /// Call it before every frame.
+    pub fn apply(&mut self, chip8: &mut Chip8) {
+        let frame = chip8.frame_count();
+        let events = &self.movie.events[self.next_event..];
+        for event in events.iter().take_while(|event| event.frame <= frame) {
+            if event.pressed {
+                chip8.handle_key_press(event.key);
+            } else {
+                chip8.handle_key_release(event.key);
+            }
+            self.next_event += 1;
+        }
+    }
+
+    /// Runs the next frame, after pressing the keys due before it.
+    pub fn step_frame(&mut self, chip8: &mut Chip8) -> Result<(), Chip8Error> {
+        self.apply(chip8);
+        chip8.step_frame()
+    }
+
+    /// Whether every event has been played.
+    pub fn is_finished(&self) -> bool {
+        self.next_event == self.movie.events.len()
+    }
+}
+
+fn quirk_flags(quirks: &Quirks) -> [bool; 6] {
+    [quirks.shift_uses_vy,
+     quirks.load_store_increments_i,
+     quirks.load_store_increments_i_by_x,
+     quirks.logic_resets_vf,
+     quirks.jump_uses_vx,
+     quirks.wrap_sprites]
+}
+
+fn parse_hash(text: &str) -> Option<Hash> {
+    if text.len() != 40 {
+        return None;
+    }
+    let mut hash = [0; 20];
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(text.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(hash)
+}
+
+fn parse_event(text: &str) -> Option<MovieEvent> {
+    let mut parts = text.splitn(2, ':');
+    let frame = parts.next()?.parse().ok()?;
+    let action = parts.next()?;
+    let pressed = match action.get(..1) {
+        Some("+") => true,
+        Some("-") => false,
+        _ => return None,
+    };
+    match Key::from_str_radix(&action[1..], 16) {
+        Ok(key) if (key as usize) < NUM_KEYS => {
+            Some(MovieEvent {
+                frame,
+                key,
+                pressed,
+            })
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Waits for a key, then draws a random sprite from the font every frame.
+    const ROM: [u8; 14] = [0x60, 0x00, // V0 := 0
+                           0xF1, 0x0A, // V1 := key
+                           0xC2, 0x0F, // loop: V2 := random 0x0F
+                           0xF2, 0x29, // I := font V2
+                           0xD0, 0x15, // sprite V0 V1 5
+                           0x70, 0x05, // V0 += 5
+                           0x12, 0x04]; // jump loop
+
+    fn play(rom: &[u8], movie: &Movie, frames: u64) -> Chip8 {
+        let mut chip8 = movie.start(rom.to_vec()).unwrap();
+        let mut playback = Playback::new(movie);
+        for _ in 0..frames {
+            playback.step_frame(&mut chip8).unwrap();
+        }
+        assert!(playback.is_finished());
+        chip8
+    }
+
+    #[test]
+    fn playback_repeats_the_recorded_game() {
+        let mut chip8 = Chip8::new(ROM.to_vec(), Platform::Chip8, Quirks::COSMAC_VIP).unwrap();
+        chip8.set_clock_rate(60.0);
+        let mut movie = Movie::new(&chip8);
+        for frame in 0..20 {
+            if frame == 3 {
+                movie.press(&mut chip8, 0x7);
+            }
+            if frame == 5 {
+                movie.release(&mut chip8, 0x7);
+            }
+            chip8.step_frame().unwrap();
+        }
+
+        let movie = Movie::parse(&movie.to_text()).unwrap();
+        assert_eq!(movie.events.len(), 2);
+        let replayed = play(&ROM, &movie, 20);
+        assert_eq!(replayed.display.get_buffer(), chip8.display.get_buffer());
+        assert_eq!(replayed.registers(), chip8.registers());
+    }
+
+    #[test]
+    fn movies_only_play_with_their_rom() {
+        let chip8 = Chip8::new(ROM.to_vec(), Platform::Chip8, Quirks::COSMAC_VIP).unwrap();
+        let movie = Movie::new(&chip8);
+        assert!(movie.start(vec![0x12, 0x00]).is_err());
+        assert!(Movie::parse("# rust-8 movie\nrom 00\n").unwrap_err().starts_with("line 2"));
+    }
+}
@@ -0,0 +1,1006 @@
+//! A compiler for Octo, the assembly language most modern CHIP-8 programs
+//! are written in.
+//!
+//! ```text
+//! :alias x v0
+//! :const SPEED 2
+//!
+//! : main
+//!     x := 0
+//!     loop
+//!         i := dot
+//!         sprite x x 1
+//!         x += SPEED
+//!         if x == 32 then x := 0
+//!     again
+//!
+//! : dot 0b10000000
+//! ```
+//!
+//! Like Octo, the program starts with a jump to `main`. The supported
+//! directives are `:`, `:const`, `:alias`, `:calc`, `:macro`, `:byte`,
+//! `:call` and `:org`. Statements are the ones from Octo's manual, along
+//! with `if ... then`, `if ... begin ... else ... end` and
+//! `loop ... while ... again`.
+
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::fmt;
+
+use instruction::{Address, Instruction, Register};
+
+const PROGRAM_START: usize = 0x200;
+const VF: Register = 0xF;
+/// Far more than any real program needs, so only a macro that expands
+/// itself forever reaches it.
+const MAX_EXPANSIONS: usize = 100000;
+
+/// Why a program could not be compiled, and where in the source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OctoError {
+    /// Counting from 1.
+    pub line: usize,
+    /// Counting from 1, in characters.
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for OctoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl Error for OctoError {}
+
+/// Compiles Octo source into a ROM to be loaded at 0x200.
+pub fn compile(source: &str) -> Result<Vec<u8>, OctoError> {
+    let mut compiler = Compiler::new(tokenize(source));
+    compiler.compile()?;
+    Ok(compiler.rom)
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+}
+
+impl Token {
+    fn error(&self, message: String) -> OctoError {
+        OctoError {
+            line: self.line,
+            column: self.column,
+            message,
+        }
+    }
+}
+
+/// Splits the source into words. Everything from a `#` at the start of a
+/// word to the end of the line is a comment.
+fn tokenize(source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+    for (i, line) in source.lines().enumerate() {
+        let mut word: Option<Token> = None;
+        for (column, c) in line.chars().enumerate() {
+            if c.is_whitespace() {
+                tokens.extend(word.take());
+            } else if let Some(ref mut word) = word {
+                word.text.push(c);
+            } else if c == '#' {
+                break;
+            } else {
+                word = Some(Token {
+                    text: c.to_string(),
+                    line: i + 1,
+                    column: column + 1,
+                });
+            }
+        }
+        tokens.extend(word);
+    }
+    tokens
+}
+
+struct Macro {
+    args: Vec<String>,
+    body: Vec<Token>,
+}
+
+/// A place in the ROM waiting for the address of a label that hasn't been
+/// defined yet.
+struct Fixup {
+    offset: usize,
+    label: Token,
+    long: bool,
+}
+
+/// An `if ... begin`, `else` or `loop` whose end hasn't been reached yet.
+enum Block {
+    If { jump: usize },
+    Else { jump: usize },
+    Loop { start: Address, whiles: Vec<usize> },
+}
+
+/// What a register is compared with in a condition.
+#[derive(Clone, Copy)]
+enum Operand {
+    Reg(Register),
+    Byte(u8),
+}
+
+struct Condition {
+    reg: Register,
+    comparison: String,
+    operand: Option<Operand>,
+}
+
+struct Compiler {
+    tokens: VecDeque<Token>,
+    rom: Vec<u8>,
+    /// Where in the ROM the next byte goes, which `:org` can move.
+    offset: usize,
+    labels: HashMap<String, Address>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, Register>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<(Token, Block)>,
+    expansions: usize,
+    /// The last token read, for errors at the end of the source.
+    last: Token,
+}
+
+impl Compiler {
+    fn new(tokens: VecDeque<Token>) -> Compiler {
+        Compiler {
+            tokens,
+            rom: Vec::new(),
+            offset: 0,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+            expansions: 0,
+            last: Token {
+                text: String::new(),
+                line: 1,
+                column: 1,
+            },
+        }
+    }
+
+    fn compile(&mut self) -> Result<(), OctoError> {
+        let main = Token { text: "main".to_string(), ..self.last.clone() };
+        self.jump_or_call(&main, false)?;
+
+        while let Some(token) = self.next() {
+            self.statement(token)?;
+        }
+
+        if let Some((token, _)) = self.blocks.pop() {
+            return Err(token.error(format!("'{}' is never closed", token.text)));
+        }
+        for fixup in std::mem::take(&mut self.fixups) {
+            let addr = match self.labels.get(&fixup.label.text) {
+                Some(addr) => *addr,
+                None => {
+                    let message = if fixup.label.text == "main" {
+                        "the program has no ': main' label".to_string()
+                    } else {
+                        format!("'{}' is not defined", fixup.label.text)
+                    };
+                    return Err(fixup.label.error(message));
+                }
+            };
+            if fixup.long {
+                self.rom[fixup.offset] = (addr >> 8) as u8;
+                self.rom[fixup.offset + 1] = addr as u8;
+            } else {
+                self.patch(fixup.offset, addr, &fixup.label)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, token: Token) -> Result<(), OctoError> {
+        if self.macros.contains_key(&token.text) {
+            return self.expand(&token);
+        }
+        if let Some(reg) = self.register(&token) {
+            return self.register_statement(reg);
+        }
+
+        match &token.text[..] {
+            ":" => {
+                let name = self.name()?;
+                let addr = self.here(&name)?;
+                if self.labels.insert(name.text.clone(), addr).is_some() {
+                    return Err(name.error(format!("'{}' is defined more than once", name.text)));
+                }
+            }
+            ":const" => {
+                let name = self.name()?;
+                let value = self.next_token()?;
+                let value = self.value(&value)?;
+                self.constants.insert(name.text, value);
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let reg = self.next_register()?;
+                self.aliases.insert(name.text, reg);
+            }
+            ":calc" => {
+                let name = self.name()?;
+                let value = self.calc()?;
+                self.constants.insert(name.text, value);
+            }
+            ":byte" => {
+                let (token, value) = if self.peek() == Some("{") {
+                    let token = self.last.clone();
+                    let value = self.calc()?;
+                    (token, value)
+                } else {
+                    let token = self.next_token()?;
+                    let value = self.value(&token)?;
+                    (token, value)
+                };
+                let byte = self.byte(&token, value)?;
+                self.emit_bytes(&[byte]);
+            }
+            ":macro" => self.define_macro()?,
+            ":call" => {
+                let target = self.next_token()?;
+                self.jump_or_call(&target, true)?;
+            }
+            ":org" => {
+                let token = self.next_token()?;
+                let addr = self.value(&token)?;
+                if addr < PROGRAM_START as f64 || addr > 0xFFFF as f64 {
+                    return Err(token.error(format!("can't put code at {}", addr)));
+                }
+                self.offset = addr as usize - PROGRAM_START;
+            }
+            "return" | ";" => self.emit(Instruction::Return),
+            "clear" => self.emit(Instruction::ClearDisplay),
+            "hires" => self.emit(Instruction::HighRes),
+            "lores" => self.emit(Instruction::LowRes),
+            "exit" => self.emit(Instruction::Exit),
+            "scroll-left" => self.emit(Instruction::ScrollLeft),
+            "scroll-right" => self.emit(Instruction::ScrollRight),
+            "audio" => self.emit(Instruction::LoadAudioPattern),
+            "scroll-down" => {
+                let n = self.next_nibble()?;
+                self.emit(Instruction::ScrollDown(n));
+            }
+            "scroll-up" => {
+                let n = self.next_nibble()?;
+                self.emit(Instruction::ScrollUp(n));
+            }
+            "plane" => {
+                let n = self.next_nibble()?;
+                self.emit(Instruction::SelectPlanes(n));
+            }
+            "jump" => {
+                let target = self.next_token()?;
+                self.jump_or_call(&target, false)?;
+            }
+            "jump0" => {
+                let target = self.next_token()?;
+                let addr = self.addr(&target)?;
+                self.emit(Instruction::JumpPlusZero(addr));
+            }
+            "sprite" => {
+                let x = self.next_register()?;
+                let y = self.next_register()?;
+                let n = self.next_nibble()?;
+                self.emit(if n == 0 { Instruction::DrawLarge(x, y) } else { Instruction::Draw(x, y, n) });
+            }
+            "save" | "load" => {
+                let x = self.next_register()?;
+                let instruction = if self.peek() == Some("-") {
+                    self.next();
+                    let y = self.next_register()?;
+                    if token.text == "save" { Instruction::SaveRange(x, y) } else { Instruction::LoadRange(x, y) }
+                } else if token.text == "save" {
+                    Instruction::StoreRegisters(x)
+                } else {
+                    Instruction::LoadRegisters(x)
+                };
+                self.emit(instruction);
+            }
+            "saveflags" => {
+                let x = self.next_register()?;
+                self.emit(Instruction::StoreFlags(x));
+            }
+            "loadflags" => {
+                let x = self.next_register()?;
+                self.emit(Instruction::LoadFlags(x));
+            }
+            "bcd" => {
+                let x = self.next_register()?;
+                self.emit(Instruction::BCDRepresentation(x));
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.next_register()?;
+                self.emit(match &token.text[..] {
+                    "delay" => Instruction::SetDelayTimer(x),
+                    "buzzer" => Instruction::SetSoundTimer(x),
+                    _ => Instruction::SetPitch(x),
+                });
+            }
+            "i" => self.i_statement()?,
+            "if" => self.if_statement(token)?,
+            "else" => {
+                let jump = match self.blocks.pop() {
+                    Some((_, Block::If { jump })) => jump,
+                    _ => return Err(token.error("'else' without 'if ... begin'".to_string())),
+                };
+                let end_jump = self.placeholder_jump();
+                let here = self.here(&token)?;
+                self.patch(jump, here, &token)?;
+                self.blocks.push((token, Block::Else { jump: end_jump }));
+            }
+            "end" => {
+                let jump = match self.blocks.pop() {
+                    Some((_, Block::If { jump })) |
+                    Some((_, Block::Else { jump })) => jump,
+                    _ => return Err(token.error("'end' without 'if ... begin'".to_string())),
+                };
+                let here = self.here(&token)?;
+                self.patch(jump, here, &token)?;
+            }
+            "loop" => {
+                let start = self.here(&token)?;
+                self.blocks.push((token,
+                                  Block::Loop {
+                    start,
+                    whiles: Vec::new(),
+                }));
+            }
+            "while" => {
+                // Skip the jump out of the loop while the condition holds
+                let condition = self.condition()?;
+                self.skip_unless(&negate(&condition));
+                let jump = self.placeholder_jump();
+                let innermost_loop = self.blocks.iter_mut().rev().filter_map(|block| match block.1 {
+                    Block::Loop { ref mut whiles, .. } => Some(whiles),
+                    _ => None,
+                }).next();
+                match innermost_loop {
+                    Some(whiles) => whiles.push(jump),
+                    None => return Err(token.error("'while' outside a loop".to_string())),
+                }
+            }
+            "again" => {
+                let (start, whiles) = match self.blocks.pop() {
+                    Some((_, Block::Loop { start, whiles })) => (start, whiles),
+                    _ => return Err(token.error("'again' without 'loop'".to_string())),
+                };
+                self.emit(Instruction::Jump(start));
+                let here = self.here(&token)?;
+                for jump in whiles {
+                    self.patch(jump, here, &token)?;
+                }
+            }
+            _ if token.text.starts_with(':') => {
+                return Err(token.error(format!("unknown directive '{}'", token.text)));
+            }
+            _ if is_number(&token.text) => {
+                let value = self.value(&token)?;
+                let byte = self.byte(&token, value)?;
+                self.emit_bytes(&[byte]);
+            }
+            _ if self.constants.contains_key(&token.text) => {
+                return Err(token.error(format!("'{}' is a constant, not code", token.text)));
+            }
+            // Anything else names a subroutine, which may not be defined yet
+            _ => self.jump_or_call(&token, true)?,
+        }
+        Ok(())
+    }
+
+    fn register_statement(&mut self, x: Register) -> Result<(), OctoError> {
+        let operator = self.next_token()?;
+        let operand = self.next_token()?;
+        let y = self.register(&operand);
+
+        let instruction = match (&operator.text[..], y) {
+            (":=", Some(y)) => Instruction::Move(x, y),
+            (":=", None) => {
+                match &operand.text[..] {
+                    "random" => Instruction::Random(x, self.next_byte()?),
+                    "key" => Instruction::WaitForKeyPress(x),
+                    "delay" => Instruction::LoadDelayTimer(x),
+                    _ => Instruction::LoadByte(x, self.operand_byte(&operand)?),
+                }
+            }
+            ("+=", Some(y)) => Instruction::Add(x, y),
+            ("+=", None) => Instruction::AddByte(x, self.operand_byte(&operand)?),
+            ("-=", Some(y)) => Instruction::Sub(x, y),
+            ("-=", None) => Instruction::AddByte(x, self.operand_byte(&operand)?.wrapping_neg()),
+            ("=-", Some(y)) => Instruction::ReverseSub(x, y),
+            ("|=", Some(y)) => Instruction::Or(x, y),
+            ("&=", Some(y)) => Instruction::And(x, y),
+            ("^=", Some(y)) => Instruction::Xor(x, y),
+            (">>=", Some(y)) => Instruction::ShiftRight(x, y),
+            ("<<=", Some(y)) => Instruction::ShiftLeft(x, y),
+            _ => {
+                return Err(operator.error(format!("can't use '{}' with '{}'", operator.text, operand.text)));
+            }
+        };
+        self.emit(instruction);
+        Ok(())
+    }
+
+    fn i_statement(&mut self) -> Result<(), OctoError> {
+        let operator = self.next_token()?;
+        let operand = self.next_token()?;
+        match &operator.text[..] {
+            "+=" => {
+                let x = self.expect_register(&operand)?;
+                self.emit(Instruction::AddToI(x));
+            }
+            ":=" => {
+                match &operand.text[..] {
+                    "hex" => {
+                        let x = self.next_register()?;
+                        self.emit(Instruction::LoadSprite(x));
+                    }
+                    "bighex" => {
+                        let x = self.next_register()?;
+                        self.emit(Instruction::LoadLargeSprite(x));
+                    }
+                    "long" => {
+                        let target = self.next_token()?;
+                        self.emit(Instruction::LoadLongI(0));
+                        let offset = self.offset - 2;
+                        match self.known_value(&target)? {
+                            Some(addr) if addr >= 0.0 && addr <= 0xFFFF as f64 => {
+                                self.rom[offset] = ((addr as u16) >> 8) as u8;
+                                self.rom[offset + 1] = addr as u8;
+                            }
+                            Some(_) => return Err(target.error(format!("{} is not an address", target.text))),
+                            None => {
+                                self.fixups.push(Fixup {
+                                    offset,
+                                    label: target,
+                                    long: true,
+                                })
+                            }
+                        }
+                    }
+                    _ => {
+                        self.emit(Instruction::LoadI(0));
+                        let offset = self.offset - 2;
+                        self.refer(offset, operand)?;
+                    }
+                }
+            }
+            _ => return Err(operator.error(format!("can't use '{}' with i", operator.text))),
+        }
+        Ok(())
+    }
+
+    fn if_statement(&mut self, token: Token) -> Result<(), OctoError> {
+        let condition = self.condition()?;
+        let keyword = self.next_token()?;
+        match &keyword.text[..] {
+            "then" => self.skip_unless(&condition),
+            "begin" => {
+                // Skip the jump past the block when the condition holds
+                self.skip_unless(&negate(&condition));
+                let jump = self.placeholder_jump();
+                self.blocks.push((token, Block::If { jump }));
+            }
+            _ => return Err(keyword.error(format!("expected 'then' or 'begin', not '{}'", keyword.text))),
+        }
+        Ok(())
+    }
+
+    fn condition(&mut self) -> Result<Condition, OctoError> {
+        let reg = self.next_register()?;
+        let comparison = self.next_token()?;
+        let operand = match &comparison.text[..] {
+            "key" | "-key" => None,
+            "==" | "!=" | "<" | ">" | "<=" | ">=" => {
+                let token = self.next_token()?;
+                Some(match self.register(&token) {
+                    Some(reg) => Operand::Reg(reg),
+                    None => Operand::Byte(self.operand_byte(&token)?),
+                })
+            }
+            _ => return Err(comparison.error(format!("unknown comparison '{}'", comparison.text))),
+        };
+        Ok(Condition {
+            reg,
+            comparison: comparison.text,
+            operand,
+        })
+    }
+
+    /// Emits the instructions that skip the next one unless the condition
+    /// holds. There are no instructions that compare by size, so `<` and
+    /// friends subtract into VF and test the borrow flag.
+    fn skip_unless(&mut self, condition: &Condition) {
+        let x = condition.reg;
+        match (&condition.comparison[..], condition.operand) {
+            ("key", _) => self.emit(Instruction::SkipIfNotPressed(x)),
+            ("-key", _) => self.emit(Instruction::SkipIfPressed(x)),
+            ("==", Some(Operand::Reg(y))) => self.emit(Instruction::SkipIfNotEqual(x, y)),
+            ("==", Some(Operand::Byte(n))) => self.emit(Instruction::SkipIfNotEqualsByte(x, n)),
+            ("!=", Some(Operand::Reg(y))) => self.emit(Instruction::SkipIfEqual(x, y)),
+            ("!=", Some(Operand::Byte(n))) => self.emit(Instruction::SkipIfEqualsByte(x, n)),
+            (comparison, Some(operand)) => {
+                // VF ends up 1 if there was no borrow, i.e. if the first is at least the second
+                let (no_borrow_runs, first, second) = match comparison {
+                    "<" => (false, Operand::Reg(x), operand),
+                    ">=" => (true, Operand::Reg(x), operand),
+                    ">" => (false, operand, Operand::Reg(x)),
+                    _ => (true, operand, Operand::Reg(x)),
+                };
+                match (first, second) {
+                    (Operand::Reg(first), Operand::Byte(second)) => {
+                        self.emit(Instruction::LoadByte(VF, second));
+                        self.emit(Instruction::ReverseSub(VF, first));
+                    }
+                    (first, Operand::Reg(second)) => {
+                        self.emit(match first {
+                            Operand::Reg(first) => Instruction::Move(VF, first),
+                            Operand::Byte(first) => Instruction::LoadByte(VF, first),
+                        });
+                        self.emit(Instruction::Sub(VF, second));
+                    }
+                    (Operand::Byte(_), Operand::Byte(_)) => unreachable!(),
+                }
+                self.emit(Instruction::SkipIfNotEqualsByte(VF, no_borrow_runs as u8));
+            }
+            (_, None) => unreachable!(),
+        }
+    }
+
+    fn define_macro(&mut self) -> Result<(), OctoError> {
+        let name = self.name()?;
+        let mut args = Vec::new();
+        loop {
+            let arg = self.next_token()?;
+            if arg.text == "{" {
+                break;
+            }
+            args.push(arg.text);
+        }
+        let body = self.block_body()?;
+        self.macros.insert(name.text,
+                           Macro {
+                               args,
+                               body,
+                           });
+        Ok(())
+    }
+
+    /// Replaces a macro's name and arguments with its body.
+    fn expand(&mut self, token: &Token) -> Result<(), OctoError> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(token.error(format!("macro '{}' expands forever", token.text)));
+        }
+
+        let arg_count = self.macros[&token.text].args.len();
+        let mut values = Vec::new();
+        for _ in 0..arg_count {
+            values.push(self.next_token()?.text);
+        }
+
+        let definition = &self.macros[&token.text];
+        let mut body: Vec<Token> = definition.body.clone();
+        for word in body.iter_mut() {
+            if let Some(arg) = definition.args.iter().position(|arg| *arg == word.text) {
+                word.text = values[arg].clone();
+            }
+        }
+        for word in body.into_iter().rev() {
+            self.tokens.push_front(word);
+        }
+        Ok(())
+    }
+
+    /// Reads everything up to the `}` that closes a `{`, which has already been read.
+    fn block_body(&mut self) -> Result<Vec<Token>, OctoError> {
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let token = self.next_token()?;
+            match &token.text[..] {
+                "{" => depth += 1,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                return Ok(body);
+            }
+            body.push(token);
+        }
+    }
+
+    /// Reads a `{ ... }` expression and evaluates it.
+    fn calc(&mut self) -> Result<f64, OctoError> {
+        let open = self.next_token()?;
+        if open.text != "{" {
+            return Err(open.error(format!("expected '{{', not '{}'", open.text)));
+        }
+        let body = self.block_body()?;
+        let mut position = 0;
+        let value = self.expression(&body, &mut position, &open)?;
+        match body.get(position) {
+            Some(extra) => Err(extra.error(format!("unexpected '{}'", extra.text))),
+            None => Ok(value),
+        }
+    }
+
+    /// Evaluates an expression the way Octo does: right to left, with no
+    /// precedence between operators, so `2 * 3 + 1` is 8.
+    fn expression(&self, tokens: &[Token], position: &mut usize, start: &Token) -> Result<f64, OctoError> {
+        let left = self.term(tokens, position, start)?;
+        let operator = match tokens.get(*position) {
+            Some(token) if token.text != ")" => token,
+            _ => return Ok(left),
+        };
+        *position += 1;
+        let right = self.expression(tokens, position, operator)?;
+
+        let (left_int, right_int) = (left as i64, right as i64);
+        Ok(match &operator.text[..] {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" => left / right,
+            "%" => left % right,
+            "&" => (left_int & right_int) as f64,
+            "|" => (left_int | right_int) as f64,
+            "^" => (left_int ^ right_int) as f64,
+            "<<" => (left_int << (right_int & 63)) as f64,
+            ">>" => (left_int >> (right_int & 63)) as f64,
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "<" => (left < right) as i64 as f64,
+            ">" => (left > right) as i64 as f64,
+            "<=" => (left <= right) as i64 as f64,
+            ">=" => (left >= right) as i64 as f64,
+            "==" => (left == right) as i64 as f64,
+            "!=" => (left != right) as i64 as f64,
+            _ => return Err(operator.error(format!("unknown operator '{}'", operator.text))),
+        })
+    }
+
+    fn term(&self, tokens: &[Token], position: &mut usize, start: &Token) -> Result<f64, OctoError> {
+        let token = match tokens.get(*position) {
+            Some(token) => token,
+            None => return Err(start.error("expected a value".to_string())),
+        };
+        *position += 1;
+
+        let unary: Option<fn(f64) -> f64> = match &token.text[..] {
+            "-" => Some(|value: f64| -value),
+            "~" => Some(|value: f64| !(value as i64) as f64),
+            "!" => Some(|value: f64| (value == 0.0) as i64 as f64),
+            "floor" => Some(f64::floor),
+            "ceil" => Some(f64::ceil),
+            "abs" => Some(f64::abs),
+            "sqrt" => Some(f64::sqrt),
+            "sin" => Some(f64::sin),
+            "cos" => Some(f64::cos),
+            "tan" => Some(f64::tan),
+            "exp" => Some(f64::exp),
+            "log" => Some(f64::ln),
+            "sign" => Some(f64::signum),
+            _ => None,
+        };
+        if let Some(unary) = unary {
+            return Ok(unary(self.term(tokens, position, token)?));
+        }
+
+        if token.text == "(" {
+            let value = self.expression(tokens, position, token)?;
+            return match tokens.get(*position) {
+                Some(close) if close.text == ")" => {
+                    *position += 1;
+                    Ok(value)
+                }
+                _ => Err(token.error("'(' is never closed".to_string())),
+            };
+        }
+        match &token.text[..] {
+            "PI" => Ok(::std::f64::consts::PI),
+            "E" => Ok(::std::f64::consts::E),
+            "HERE" => Ok((PROGRAM_START + self.offset) as f64),
+            _ => self.value(token),
+        }
+    }
+
+    /// A number, constant or label that has already been defined.
+    fn known_value(&self, token: &Token) -> Result<Option<f64>, OctoError> {
+        if is_number(&token.text) {
+            return parse_number(&token.text)
+                .map(Some)
+                .ok_or_else(|| token.error(format!("'{}' is not a valid number", token.text)));
+        }
+        Ok(self.constants.get(&token.text).cloned().or(self.labels.get(&token.text).map(|addr| *addr as f64)))
+    }
+
+    fn value(&self, token: &Token) -> Result<f64, OctoError> {
+        self.known_value(token)?.ok_or_else(|| token.error(format!("'{}' is not defined", token.text)))
+    }
+
+    fn byte(&self, token: &Token, value: f64) -> Result<u8, OctoError> {
+        if !(-128.0..=255.0).contains(&value) {
+            return Err(token.error(format!("{} does not fit in a byte", value)));
+        }
+        Ok(value as i64 as u8)
+    }
+
+    fn operand_byte(&self, token: &Token) -> Result<u8, OctoError> {
+        let value = self.value(token)?;
+        self.byte(token, value)
+    }
+
+    fn next_byte(&mut self) -> Result<u8, OctoError> {
+        let token = self.next_token()?;
+        self.operand_byte(&token)
+    }
+
+    fn next_nibble(&mut self) -> Result<u8, OctoError> {
+        let token = self.next_token()?;
+        let value = self.value(&token)?;
+        if !(0.0..=15.0).contains(&value) {
+            return Err(token.error(format!("{} is not from 0 to 15", value)));
+        }
+        Ok(value as u8)
+    }
+
+    fn addr(&self, token: &Token) -> Result<Address, OctoError> {
+        let value = self.value(token)?;
+        if value < 0.0 || value > 0xFFF as f64 {
+            return Err(token.error(format!("{} is not a 12-bit address", value)));
+        }
+        Ok(value as Address)
+    }
+
+    fn jump_or_call(&mut self, target: &Token, call: bool) -> Result<(), OctoError> {
+        self.emit(if call { Instruction::Call(0) } else { Instruction::Jump(0) });
+        let offset = self.offset - 2;
+        self.refer(offset, target.clone())
+    }
+
+    /// Fills in the address part of the instruction at `offset` with the
+    /// address of `target`, now or once the label is defined.
+    fn refer(&mut self, offset: usize, target: Token) -> Result<(), OctoError> {
+        match self.known_value(&target)? {
+            Some(_) => {
+                let addr = self.addr(&target)?;
+                self.patch(offset, addr, &target)
+            }
+            None if is_name(&target.text) => {
+                self.fixups.push(Fixup {
+                    offset,
+                    label: target,
+                    long: false,
+                });
+                Ok(())
+            }
+            None => Err(target.error(format!("'{}' is not a label", target.text))),
+        }
+    }
+
+    fn placeholder_jump(&mut self) -> usize {
+        self.emit(Instruction::Jump(0));
+        self.offset - 2
+    }
+
+    fn patch(&mut self, offset: usize, addr: Address, token: &Token) -> Result<(), OctoError> {
+        if addr > 0xFFF {
+            return Err(token.error(format!("{:#X} is out of reach of a 12-bit address", addr)));
+        }
+        self.rom[offset] = (self.rom[offset] & 0xF0) | (addr >> 8) as u8;
+        self.rom[offset + 1] = addr as u8;
+        Ok(())
+    }
+
+    fn here(&self, token: &Token) -> Result<Address, OctoError> {
+        let addr = PROGRAM_START + self.offset;
+        if addr > 0xFFFF {
+            return Err(token.error("the program is too big".to_string()));
+        }
+        Ok(addr as Address)
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        let opcode = instruction.encode();
+        self.emit_bytes(&[(opcode >> 8) as u8, opcode as u8]);
+        if let Instruction::LoadLongI(addr) = instruction {
+            self.emit_bytes(&[(addr >> 8) as u8, addr as u8]);
+        }
+    }
+
+    fn emit_bytes(&mut self, bytes: &[u8]) {
+        let end = self.offset + bytes.len();
+        if self.rom.len() < end {
+            self.rom.resize(end, 0);
+        }
+        self.rom[self.offset..end].copy_from_slice(bytes);
+        self.offset = end;
+    }
+
+    fn register(&self, token: &Token) -> Option<Register> {
+        if let Some(reg) = self.aliases.get(&token.text) {
+            return Some(*reg);
+        }
+        let text = token.text.to_lowercase();
+        if text.len() == 2 && text.starts_with('v') {
+            return u8::from_str_radix(&text[1..], 16).ok();
+        }
+        None
+    }
+
+    fn expect_register(&self, token: &Token) -> Result<Register, OctoError> {
+        self.register(token).ok_or_else(|| token.error(format!("expected a register, not '{}'", token.text)))
+    }
+
+    fn next_register(&mut self) -> Result<Register, OctoError> {
+        let token = self.next_token()?;
+        self.expect_register(&token)
+    }
+
+    fn name(&mut self) -> Result<Token, OctoError> {
+        let token = self.next_token()?;
+        if !is_name(&token.text) || self.register(&token).is_some() {
+            return Err(token.error(format!("'{}' can't be used as a name", token.text)));
+        }
+        Ok(token)
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), OctoError> {
+        let token = self.next_token()?;
+        if token.text != text {
+            return Err(token.error(format!("expected '{}', not '{}'", text, token.text)));
+        }
+        Ok(())
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|token| &token.text[..])
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.pop_front();
+        if let Some(ref token) = token {
+            self.last = token.clone();
+        }
+        token
+    }
+
+    fn next_token(&mut self) -> Result<Token, OctoError> {
+        match self.next() {
+            Some(token) => Ok(token),
+            None => Err(self.last.error(format!("unexpected end of program after '{}'", self.last.text))),
+        }
+    }
+}
+
+/// The condition that holds exactly when `condition` doesn't.
+fn negate(condition: &Condition) -> Condition {
+    let comparison = match &condition.comparison[..] {
+        "==" => "!=",
+        "!=" => "==",
+        "key" => "-key",
+        "-key" => "key",
+        "<" => ">=",
+        ">=" => "<",
+        ">" => "<=",
+        _ => ">",
+    };
+    Condition {
+        reg: condition.reg,
+        comparison: comparison.to_string(),
+        operand: condition.operand,
+    }
+}
+
+fn is_number(text: &str) -> bool {
+    text.trim_start_matches('-').starts_with(|c: char| c.is_ascii_digit())
+}
+
+fn parse_number(text: &str) -> Option<f64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()? as f64
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()? as f64
+    } else {
+        digits.parse().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn is_name(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_alphabetic() || c == '_') &&
+    text.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compiles_statements_and_data() {
+        let rom = compile("
+            :alias x v3
+            :const ROWS 2
+            : main
+                clear
+                x := 5
+                i := dot
+                sprite x x ROWS
+                x -= 1
+                draw-again
+            : draw-again
+                return
+            : dot 0x80 0b01000000
+        ");
+        assert_eq!(rom,
+                   Ok(vec![0x12, 0x02, 0x00, 0xE0, 0x63, 0x05, 0xA2, 0x10, 0xD3, 0x32, 0x73, 0xFF,
+                           0x22, 0x0E, 0x00, 0xEE, 0x80, 0x40]));
+    }
+
+    #[test]
+    fn compiles_control_flow() {
+        let rom = compile("
+            : main
+                loop
+                    while v0 != 3
+                    if v0 < v1 begin
+                        v0 += 1
+                    else
+                        v0 := 0
+                    end
+                again
+        ");
+        assert_eq!(rom,
+                   Ok(vec![0x12, 0x02, // jump main
+                           0x40, 0x03, 0x12, 0x16, // while
+                           0x8F, 0x00, 0x8F, 0x15, 0x4F, 0x01, 0x12, 0x12, // if < begin
+                           0x70, 0x01, 0x12, 0x14, // else
+                           0x60, 0x00, // end
+                           0x12, 0x02])); // again
+    }
+
+    #[test]
+    fn expands_macros_and_calculations() {
+        let rom = compile("
+            :macro set reg value { reg := value }
+            :calc SEVEN { 2 * 3 + 1 }
+            : main set v1 SEVEN
+            :byte { HERE - 0x200 }
+        ");
+        assert_eq!(rom, Ok(vec![0x12, 0x02, 0x61, 0x08, 0x04]));
+    }
+
+    #[test]
+    fn reports_the_line_and_column_of_an_error() {
+        let error = compile(": main\n  v0 := 300").unwrap_err();
+        assert_eq!((error.line, error.column), (2, 9));
+
+        let error = compile(": main\n  jump nowhere").unwrap_err();
+        assert_eq!((error.line, error.column), (2, 8));
+
+        assert!(compile("clear").is_err());
+        assert!(compile(": main loop").is_err());
+    }
+}
@@ -0,0 +1,81 @@
+use std::fmt;
+use std::str::FromStr;
+
+use instruction::Instruction;
+use quirks::Quirks;
+
+/// The family of interpreters a program was written for.
+///
+/// Each platform is a superset of the one before it, so a CHIP-8 program
+/// runs on all three while an XO-CHIP program only runs on XO-CHIP.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Platform {
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl Platform {
+    pub fn memory_size(&self) -> usize {
+        match *self {
+            Platform::Chip8 | Platform::SuperChip => 4 * 1024,
+            Platform::XoChip => 64 * 1024,
+        }
+    }
+
+    /// The quirks most programs for this platform expect.
+    pub fn default_quirks(&self) -> Quirks {
+        match *self {
+            Platform::Chip8 => Quirks::COSMAC_VIP,
+            Platform::SuperChip => Quirks::SUPER_CHIP,
+            Platform::XoChip => Quirks::MODERN,
+        }
+    }
+
+    pub fn supports(&self, instruction: &Instruction) -> bool {
+        match *instruction {
+            Instruction::ScrollDown(_) |
+            Instruction::ScrollRight |
+            Instruction::ScrollLeft |
+            Instruction::Exit |
+            Instruction::LowRes |
+            Instruction::HighRes |
+            Instruction::DrawLarge(_, _) |
+            Instruction::LoadLargeSprite(_) |
+            Instruction::StoreFlags(_) |
+            Instruction::LoadFlags(_) => *self != Platform::Chip8,
+            Instruction::ScrollUp(_) |
+            Instruction::LoadLongI(_) |
+            Instruction::SelectPlanes(_) |
+            Instruction::SaveRange(_, _) |
+            Instruction::LoadRange(_, _) |
+            Instruction::LoadAudioPattern |
+            Instruction::SetPitch(_) => *self == Platform::XoChip,
+            _ => true,
+        }
+    }
+}
+
+/// The name `from_str` reads back.
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Platform::Chip8 => write!(f, "chip8"),
+            Platform::SuperChip => write!(f, "schip"),
+            Platform::XoChip => write!(f, "xochip"),
+        }
+    }
+}
+
+impl FromStr for Platform {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Platform, String> {
+        match name {
+            "chip8" | "chip-8" => Ok(Platform::Chip8),
+            "schip" | "superchip" | "super-chip" => Ok(Platform::SuperChip),
+            "xochip" | "xo-chip" => Ok(Platform::XoChip),
+            _ => Err(format!("unknown platform '{}', expected chip8, schip or xochip", name)),
+        }
+    }
+}
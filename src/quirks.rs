@@ -0,0 +1,152 @@
+use std::str::FromStr;
+
+/// The behaviours that differ between CHIP-8 interpreters.
+///
+/// Programs were written against whichever interpreter their author had, so
+/// a ROM only runs correctly with the quirks of that interpreter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quirks {
+    /// 8XY6 and 8XYE shift VY into VX instead of shifting VX in place.
+    pub shift_uses_vy: bool,
+    /// FX55 and FX65 leave I pointing just past the last register copied.
+    pub load_store_increments_i: bool,
+    /// With `load_store_increments_i`, FX55 and FX65 leave I pointing at the
+    /// last register copied instead, as CHIP-48 did.
+    pub load_store_increments_i_by_x: bool,
+    /// 8XY1, 8XY2 and 8XY3 clear VF.
+    pub logic_resets_vf: bool,
+    /// BNNN is read as BXNN and jumps to XNN plus VX instead of NNN plus V0.
+    pub jump_uses_vx: bool,
+    /// Sprites that run off the edge of the screen wrap around instead of being clipped.
+    pub wrap_sprites: bool,
+}
+
+impl Quirks {
+    /// The original interpreter on the RCA COSMAC VIP.
+    pub const COSMAC_VIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_increments_i: true,
+        load_store_increments_i_by_x: false,
+        logic_resets_vf: true,
+        jump_uses_vx: false,
+        wrap_sprites: false,
+    };
+
+    /// CHIP-48 on the HP-48 calculators.
+    pub const CHIP_48: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_increments_i: true,
+        load_store_increments_i_by_x: true,
+        logic_resets_vf: false,
+        jump_uses_vx: true,
+        wrap_sprites: false,
+    };
+
+    /// SUPER-CHIP 1.1, the successor to CHIP-48, which stopped FX55 and FX65
+    /// moving I at all.
+    pub const SUPER_CHIP: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_increments_i: false,
+        load_store_increments_i_by_x: false,
+        logic_resets_vf: false,
+        jump_uses_vx: true,
+        wrap_sprites: false,
+    };
+
+    /// Octo and most other modern interpreters.
+    pub const MODERN: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_increments_i: true,
+        load_store_increments_i_by_x: false,
+        logic_resets_vf: false,
+        jump_uses_vx: false,
+        wrap_sprites: true,
+    };
+}
+
+impl FromStr for Quirks {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Quirks, String> {
+        match name {
+            "vip" | "cosmac" | "cosmac-vip" => Ok(Quirks::COSMAC_VIP),
+            "chip48" | "chip-48" => Ok(Quirks::CHIP_48),
+            "schip" | "superchip" | "super-chip" => Ok(Quirks::SUPER_CHIP),
+            "modern" | "octo" => Ok(Quirks::MODERN),
+            _ => Err(format!("unknown quirks '{}', expected vip, chip48, schip or modern", name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip8::Chip8;
+    use platform::Platform;
+
+    /// Runs each instruction of `program` once with the given quirks.
+    fn run(program: &[u8], quirks: Quirks) -> Chip8 {
+        let mut chip8 = Chip8::new(program.to_vec(), Platform::SuperChip, quirks).unwrap();
+        for _ in 0..program.len() / 2 {
+            chip8.step_instruction().unwrap();
+        }
+        chip8
+    }
+
+    #[test]
+    fn shift_uses_vy() {
+        // V0 := 1, V1 := 6, V0 >>= V1
+        let program = [0x60, 0x01, 0x61, 0x06, 0x80, 0x16];
+        assert_eq!(run(&program, Quirks::COSMAC_VIP).registers()[0], 3);
+        assert_eq!(run(&program, Quirks::SUPER_CHIP).registers()[0], 0);
+    }
+
+    #[test]
+    fn load_store_increments_i() {
+        // I := 0x300, save V2, load V2
+        let program = [0xA3, 0x00, 0xF2, 0x55, 0xF2, 0x65];
+        assert_eq!(run(&program[..4], Quirks::COSMAC_VIP).i_register(), 0x303);
+        assert_eq!(run(&program[..4], Quirks::CHIP_48).i_register(), 0x302);
+        assert_eq!(run(&program[..4], Quirks::SUPER_CHIP).i_register(), 0x300);
+        assert_eq!(run(&program, Quirks::COSMAC_VIP).i_register(), 0x306);
+        assert_eq!(run(&program, Quirks::CHIP_48).i_register(), 0x304);
+        assert_eq!(run(&program, Quirks::SUPER_CHIP).i_register(), 0x300);
+    }
+
+    #[test]
+    fn logic_resets_vf() {
+        // VF := 5, V0 &= V1
+        let program = [0x6F, 0x05, 0x80, 0x12];
+        assert_eq!(run(&program, Quirks::COSMAC_VIP).registers()[0xF], 0);
+        assert_eq!(run(&program, Quirks::CHIP_48).registers()[0xF], 5);
+    }
+
+    #[test]
+    fn jump_uses_vx() {
+        // V0 := 4, V2 := 8, jump0 0x210
+        let program = [0x60, 0x04, 0x62, 0x08, 0xB2, 0x10];
+        assert_eq!(run(&program, Quirks::COSMAC_VIP).program_counter(), 0x214);
+        assert_eq!(run(&program, Quirks::CHIP_48).program_counter(), 0x218);
+    }
+
+    #[test]
+    fn wrap_sprites() {
+        // V0 := 62, I := font 0 digit, sprite V0 V1 5
+        let program = [0x60, 0x3E, 0xF1, 0x29, 0xD0, 0x15];
+        let wrapped = run(&program, Quirks::MODERN);
+        assert_eq!(&wrapped.display.get_buffer()[0][..2], &[1, 1]);
+        let clipped = run(&program, Quirks::SUPER_CHIP);
+        assert_eq!(&clipped.display.get_buffer()[0][..2], &[0, 0]);
+        assert_eq!(&clipped.display.get_buffer()[0][62..], &[1, 1]);
+    }
+
+    #[test]
+    fn presets_all_differ() {
+        let presets = [Quirks::COSMAC_VIP, Quirks::CHIP_48, Quirks::SUPER_CHIP, Quirks::MODERN];
+        for (i, first) in presets.iter().enumerate() {
+            for second in presets[i + 1..].iter() {
+                assert!(first != second, "{:?} is repeated", first);
+            }
+        }
+    }
+}
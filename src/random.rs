@@ -0,0 +1,182 @@
+#[cfg(feature = "os-random")]
+use std::io;
+
+#[cfg(feature = "os-random")]
+use rand::{OsRng, Rng};
+use state::{StateError, StateReader, StateWriter};
+
+/// Where CXNN gets its random numbers from.
+///
+/// Sources that keep state say so through `save_state` and `load_state`,
+/// which puts it in the machine's snapshots. Rewinding or loading a
+/// snapshot then gives the same numbers as the first time around.
+pub trait RandomSource {
+    /// A random byte, from 0 to 255.
+    fn next_byte(&mut self) -> u8;
+
+    fn save_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Restores state from `save_state`. If it can't be restored the source
+    /// is left as it was.
+    fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        if state.is_empty() {
+            Ok(())
+        } else {
+            Err(StateError::Corrupt)
+        }
+    }
+}
+
+/// The xorshift64* generator. The same seed always gives the same numbers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct XorShift {
+    state: u64,
+}
+
+impl XorShift {
+    pub fn new(seed: u64) -> XorShift {
+        // Spread the seed's bits out so that nearby seeds don't start alike,
+        // and keep away from zero, which xorshift never leaves
+        let mut state = (seed ^ 0x9E3779B97F4A7C15).wrapping_mul(0xBF58476D1CE4E5B9);
+        state ^= state >> 31;
+        XorShift { state: if state == 0 { 0x9E3779B97F4A7C15 } else { state } }
+    }
+}
+
+impl RandomSource for XorShift {
+    fn next_byte(&mut self) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545F4914F6CDD1D) >> 56) as u8
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.u64(self.state);
+        writer.into_bytes()
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let mut reader = StateReader::new(state);
+        let value = reader.u64()?;
+        reader.finish()?;
+        if value == 0 {
+            return Err(StateError::Corrupt);
+        }
+        self.state = value;
+        Ok(())
+    }
+}
+
+/// Numbers from the operating system, which can't be repeated.
+#[cfg(feature = "os-random")]
+pub struct OsEntropy {
+    rng: OsRng,
+}
+
+#[cfg(feature = "os-random")]
+impl OsEntropy {
+    pub fn new() -> io::Result<OsEntropy> {
+        Ok(OsEntropy { rng: OsRng::new()? })
+    }
+}
+
+#[cfg(feature = "os-random")]
+impl RandomSource for OsEntropy {
+    fn next_byte(&mut self) -> u8 {
+        self.rng.gen()
+    }
+}
+
+/// Hands out a fixed list of numbers in order, starting again from the
+/// beginning when they run out. For tests that need to know what CXNN
+/// will draw.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scripted {
+    bytes: Vec<u8>,
+    position: usize,
+}
+
+impl Scripted {
+    pub fn new(bytes: Vec<u8>) -> Scripted {
+        assert!(!bytes.is_empty(), "A scripted source needs at least one number");
+        Scripted {
+            bytes,
+            position: 0,
+        }
+    }
+}
+
+impl RandomSource for Scripted {
+    fn next_byte(&mut self) -> u8 {
+        let byte = self.bytes[self.position];
+        self.position = (self.position + 1) % self.bytes.len();
+        byte
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.u32(self.position as u32);
+        writer.into_bytes()
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let mut reader = StateReader::new(state);
+        let position = reader.u32()? as usize;
+        reader.finish()?;
+        if position >= self.bytes.len() {
+            return Err(StateError::Corrupt);
+        }
+        self.position = position;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip8::Chip8;
+    use platform::Platform;
+
+    #[test]
+    fn xorshift_repeats_for_a_seed_and_reaches_every_byte() {
+        let mut first = XorShift::new(0);
+        let mut second = XorShift::new(0);
+        let mut seen = [false; 256];
+        for _ in 0..10000 {
+            let byte = first.next_byte();
+            assert_eq!(byte, second.next_byte());
+            seen[byte as usize] = true;
+        }
+        assert!(seen.iter().all(|&seen| seen));
+
+        let snapshot = first.save_state();
+        let expected: Vec<u8> = (0..8).map(|_| first.next_byte()).collect();
+        first.load_state(&snapshot).unwrap();
+        assert_eq!((0..8).map(|_| first.next_byte()).collect::<Vec<u8>>(), expected);
+        assert_eq!(first.load_state(&[0; 8]), Err(StateError::Corrupt));
+    }
+
+    #[test]
+    fn snapshots_hold_the_sources_place() {
+        // V0 := random 0xFF, forever
+        let rom = vec![0xC0, 0xFF, 0x12, 0x00];
+        let mut chip8 = Chip8::new(rom, Platform::Chip8, Platform::Chip8.default_quirks()).unwrap();
+        chip8.random = Box::new(Scripted::new(vec![0xFF, 0x12, 0x34]));
+
+        chip8.step_instruction().unwrap();
+        assert_eq!(chip8.registers()[0], 0xFF);
+        let snapshot = chip8.save_state();
+        chip8.step_instruction().unwrap();
+        chip8.step_instruction().unwrap();
+        assert_eq!(chip8.registers()[0], 0x12);
+
+        chip8.load_state(&snapshot).unwrap();
+        chip8.step_instruction().unwrap();
+        chip8.step_instruction().unwrap();
+        assert_eq!(chip8.registers()[0], 0x12);
+    }
+}
@@ -0,0 +1,219 @@
+//! Rewinding, built on `Chip8::save_state`.
+//!
+//! Only the newest snapshot is kept whole. Each older frame is stored as the
+//! XOR of it and the frame after it, run-length encoded. Consecutive frames
+//! differ in very few bytes, so a frame usually costs tens of bytes rather
+//! than the several kilobytes of a full snapshot.
+
+use std::collections::VecDeque;
+
+use state::StateError;
+
+/// A minute of history at 60 frames a second.
+pub const DEFAULT_CAPACITY: usize = 60 * 60;
+
+/// How to get from a snapshot back to the one taken before it.
+struct Delta {
+    /// The older snapshot's length, since a change of screen mode changes the size.
+    len: usize,
+    runs: Vec<u8>,
+}
+
+pub struct Rewind {
+    capacity: usize,
+    latest: Option<Vec<u8>>,
+    deltas: VecDeque<Delta>,
+}
+
+impl Rewind {
+    /// Keeps up to `capacity` frames of history before the newest one.
+    pub fn new(capacity: usize) -> Rewind {
+        Rewind {
+            capacity,
+            latest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    /// Records the snapshot of a new frame, forgetting the oldest frame if
+    /// the buffer is full.
+    pub fn push(&mut self, snapshot: Vec<u8>) {
+        if let Some(previous) = self.latest.take() {
+            self.deltas.push_back(Delta {
+                len: previous.len(),
+                runs: compress(&xor(&previous, &snapshot)),
+            });
+            if self.deltas.len() > self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+        self.latest = Some(snapshot);
+    }
+
+    /// Steps back one frame and returns its snapshot, or `None` if there is
+    /// no older frame left.
+    pub fn step_back(&mut self) -> Result<Option<&[u8]>, StateError> {
+        let delta = match self.deltas.pop_back() {
+            Some(delta) => delta,
+            None => return Ok(None),
+        };
+        let newer = self.latest.take().unwrap_or_default();
+        let mut older = xor(&newer, &decompress(&delta.runs)?);
+        older.truncate(delta.len);
+        self.latest = Some(older);
+        Ok(self.latest.as_ref().map(|snapshot| &snapshot[..]))
+    }
+
+    /// The number of frames that can be stepped back.
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    /// Forgets all history, for when the machine jumps somewhere new.
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+    }
+
+    /// The number of bytes of history being kept.
+    pub fn memory_used(&self) -> usize {
+        let latest = self.latest.as_ref().map_or(0, |snapshot| snapshot.len());
+        self.deltas.iter().fold(latest, |total, delta| total + delta.runs.len())
+    }
+}
+
+/// XORs two snapshots, treating the shorter one as if it were padded with zeros.
+fn xor(first: &[u8], second: &[u8]) -> Vec<u8> {
+    let len = first.len().max(second.len());
+    (0..len)
+        .map(|i| first.get(i).cloned().unwrap_or(0) ^ second.get(i).cloned().unwrap_or(0))
+        .collect()
+}
+
+/// Encodes the bytes as alternating runs: a count of zero bytes, then a
+/// count of literal bytes followed by the bytes themselves. Counts are varints.
+fn compress(bytes: &[u8]) -> Vec<u8> {
+    let mut runs = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let zeros_start = i;
+        while i < bytes.len() && bytes[i] == 0 {
+            i += 1;
+        }
+        let literals_start = i;
+        while i < bytes.len() && bytes[i] != 0 {
+            i += 1;
+        }
+        write_varint(&mut runs, literals_start - zeros_start);
+        write_varint(&mut runs, i - literals_start);
+        runs.extend_from_slice(&bytes[literals_start..i]);
+    }
+    runs
+}
+
+fn decompress(runs: &[u8]) -> Result<Vec<u8>, StateError> {
+    let mut bytes = Vec::new();
+    let mut i = 0;
+    while i < runs.len() {
+        let zeros = read_varint(runs, &mut i)?;
+        let literals = read_varint(runs, &mut i)?;
+        let end = i.checked_add(literals).ok_or(StateError::Corrupt)?;
+        let literals = runs.get(i..end).ok_or(StateError::Truncated)?;
+        bytes.resize(bytes.len() + zeros, 0);
+        bytes.extend_from_slice(literals);
+        i = end;
+    }
+    Ok(bytes)
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        bytes.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn read_varint(bytes: &[u8], i: &mut usize) -> Result<usize, StateError> {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *bytes.get(*i).ok_or(StateError::Truncated)?;
+        *i += 1;
+        let bits = (byte & 0x7F) as usize;
+        if shift >= usize::MAX.count_ones() || (bits << shift) >> shift != bits {
+            return Err(StateError::Corrupt);
+        }
+        value |= bits << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compression_round_trips() {
+        let mut bytes = vec![0; 300];
+        bytes.extend_from_slice(&[1, 2, 3, 0, 0, 0xFF]);
+        bytes.extend(vec![0; 5]);
+        bytes.extend((1..=200).map(|n| n as u8));
+        for bytes in [vec![], vec![0], vec![7], bytes].iter() {
+            let runs = compress(bytes);
+            assert_eq!(&decompress(&runs).unwrap(), bytes);
+        }
+        // 300 zeros take a two-byte varint
+        assert_eq!(&compress(&[0; 300])[..], &[0xAC, 0x02, 0x00]);
+    }
+
+    #[test]
+    fn bad_runs_are_an_error() {
+        // A varint that never ends
+        assert_eq!(decompress(&[0x80]), Err(StateError::Truncated));
+        // Three literals promised, two given
+        assert_eq!(decompress(&[0x00, 0x03, 0x01, 0x02]), Err(StateError::Truncated));
+        assert_eq!(decompress(&[0xFF; 20]), Err(StateError::Corrupt));
+    }
+
+    #[test]
+    fn steps_back_across_a_change_of_screen_size() {
+        // Snapshots grow when the screen switches to hi-res
+        let lo_res = vec![1; 100];
+        let mut hi_res = vec![1; 400];
+        hi_res[50] = 9;
+        let mut rewind = Rewind::new(10);
+        rewind.push(lo_res.clone());
+        rewind.push(hi_res.clone());
+        rewind.push(lo_res.clone());
+        assert_eq!(rewind.len(), 2);
+
+        assert_eq!(rewind.step_back(), Ok(Some(&hi_res[..])));
+        assert_eq!(rewind.step_back(), Ok(Some(&lo_res[..])));
+        assert_eq!(rewind.step_back(), Ok(None));
+        assert!(rewind.is_empty());
+    }
+
+    #[test]
+    fn the_oldest_frames_are_forgotten_at_capacity() {
+        let mut rewind = Rewind::new(3);
+        for frame in 0..10 {
+            rewind.push(vec![frame; 8]);
+        }
+        assert_eq!(rewind.len(), 3);
+        for frame in (6..9).rev() {
+            assert_eq!(rewind.step_back(), Ok(Some(&[frame; 8][..])));
+        }
+        assert_eq!(rewind.step_back(), Ok(None));
+
+        rewind.clear();
+        assert_eq!(rewind.memory_used(), 0);
+    }
+}
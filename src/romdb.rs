@@ -0,0 +1,344 @@
+//! Settings for known ROMs, looked up by the SHA-1 of the ROM.
+//!
+//! The database is read from the `programs.json` of the community
+//! [CHIP-8 database](https://github.com/chip-8/chip-8-database), which lists
+//! programs with their ROMs keyed by hash:
+//!
+//! ```text
+//! [{"title": "Pong", "roms": {"<sha1>": {"platforms": ["originalChip8"],
+//!                                        "tickrate": 15,
+//!                                        "keys": {"up": 1, "down": 4},
+//!                                        "colors": {"pixels": ["#000000", "#ffffff"]}}}}]
+//! ```
+//!
+//! A local file of the same form can be merged over it to change entries.
+//! It can also leave out the programs and be just an object of ROMs keyed
+//! by hash.
+
+use std::collections::HashMap;
+
+use chip8::{Key, NUM_KEYS};
+use json::{self, Value};
+use keymap::Keymap;
+use platform::Platform;
+use quirks::Quirks;
+use sha1::{self, Hash};
+
+/// The database's ticks are instructions per 60 Hz frame.
+const TICKS_PER_SECOND: f64 = 60.0;
+
+/// What every frontend runs a ROM on when neither the command line nor the
+/// database says otherwise. Most ROMs still around were written for
+/// SUPER-CHIP or later.
+pub const DEFAULT_PLATFORM: Platform = Platform::SuperChip;
+pub const DEFAULT_QUIRKS: Quirks = Quirks::MODERN;
+
+/// The database's platform ids that we can run, in the database's order,
+/// with the quirks of each.
+const PLATFORMS: [(&str, Platform, Quirks); 8] = [("originalChip8", Platform::Chip8, Quirks::COSMAC_VIP),
+                                                          ("hybridVIP", Platform::Chip8, Quirks::COSMAC_VIP),
+                                                          ("modernChip8", Platform::Chip8, MODERN_CHIP_8),
+                                                          ("chip8x", Platform::Chip8, Quirks::COSMAC_VIP),
+                                                          ("chip48", Platform::SuperChip, Quirks::CHIP_48),
+                                                          ("superchip1", Platform::SuperChip, Quirks::SUPER_CHIP),
+                                                          ("superchip", Platform::SuperChip, Quirks::SUPER_CHIP),
+                                                          ("xochip", Platform::XoChip, Quirks::MODERN)];
+
+/// What the database calls a plain CHIP-8 interpreter written today, which
+/// is the VIP without its bugs.
+const MODERN_CHIP_8: Quirks = Quirks {
+    shift_uses_vy: true,
+    load_store_increments_i: true,
+    load_store_increments_i_by_x: false,
+    logic_resets_vf: false,
+    jump_uses_vx: false,
+    wrap_sprites: false,
+};
+
+/// The host keys bound for each of the database's key hints.
+const KEY_HINTS: [(&str, &[&str]); 6] = [("up", &["Up"]),
+                                                                 ("down", &["Down"]),
+                                                                 ("left", &["Left"]),
+                                                                 ("right", &["Right"]),
+                                                                 ("a", &["Space"]),
+                                                                 ("b", &["LShift"])];
+
+/// What the database knows about one ROM. Anything it doesn't say is left
+/// to the frontend.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RomConfig {
+    pub title: Option<String>,
+    pub platform: Option<Platform>,
+    pub quirks: Option<Quirks>,
+    /// Instructions per second.
+    pub clock_rate: Option<f64>,
+    /// The colour of each combination of bitplanes, starting with the background.
+    pub colours: Vec<[u8; 3]>,
+    /// The keys the game uses for each of its actions, e.g. `("up", 0x5)`.
+    pub keys: Vec<(String, Key)>,
+}
+
+impl RomConfig {
+    /// Takes everything `other` says over what this one says.
+    pub fn merge(&mut self, other: RomConfig) {
+        if other.title.is_some() {
+            self.title = other.title;
+        }
+        if other.platform.is_some() {
+            self.platform = other.platform;
+        }
+        if other.quirks.is_some() {
+            self.quirks = other.quirks;
+        }
+        if other.clock_rate.is_some() {
+            self.clock_rate = other.clock_rate;
+        }
+        if !other.colours.is_empty() {
+            self.colours = other.colours;
+        }
+        for (action, key) in other.keys {
+            self.keys.retain(|(existing, _)| *existing != action);
+            self.keys.push((action, key));
+        }
+    }
+
+    /// The platform and quirks to run the ROM with, given the ones asked for
+    /// on the command line. Given a platform but no quirks, the platform's
+    /// own quirks are more likely to be right than whatever the database says.
+    pub fn machine(&self, platform: Option<Platform>, quirks: Option<Quirks>) -> (Platform, Quirks) {
+        match (platform, quirks) {
+            (Some(platform), Some(quirks)) => (platform, quirks),
+            (Some(platform), None) => (platform, platform.default_quirks()),
+            (None, quirks) => {
+                (self.platform.unwrap_or(DEFAULT_PLATFORM),
+                 quirks.or(self.quirks).unwrap_or(DEFAULT_QUIRKS))
+            }
+        }
+    }
+
+    /// Binds the arrow keys to the directions the game uses, and Space and
+    /// LShift to its actions. Gamepads are left to their own profiles, since
+    /// every pad numbers its buttons differently.
+    pub fn bind_keys(&self, keyboard: &mut Keymap) {
+        for &(action, keyboard_keys) in KEY_HINTS.iter() {
+            let key = match self.keys.iter().find(|&(name, _)| name == action) {
+                Some(&(_, key)) => key,
+                None => continue,
+            };
+            for host_key in keyboard_keys {
+                keyboard.bind(host_key, key);
+            }
+        }
+    }
+}
+
+pub struct Database {
+    /// Entries by the lowercase hex of their hash.
+    roms: HashMap<String, RomConfig>,
+}
+
+impl Database {
+    pub fn new() -> Database {
+        Database { roms: HashMap::new() }
+    }
+
+    /// Reads a `programs.json`, or an object of ROMs keyed by hash.
+    pub fn parse(text: &str) -> Result<Database, String> {
+        let document = json::parse(text)?;
+        let mut database = Database::new();
+        match document {
+            Value::Array(ref programs) => {
+                for program in programs {
+                    let title = program.get("title").and_then(Value::as_str);
+                    let roms = program.get("roms").and_then(Value::as_object).unwrap_or(&[]);
+                    for (hash, rom) in roms {
+                        let mut config = parse_rom(hash, rom)?;
+                        if config.title.is_none() {
+                            config.title = title.map(str::to_string);
+                        }
+                        database.roms.insert(hash.to_lowercase(), config);
+                    }
+                }
+            }
+            Value::Object(ref roms) => {
+                for (hash, rom) in roms {
+                    database.roms.insert(hash.to_lowercase(), parse_rom(hash, rom)?);
+                }
+            }
+            _ => return Err("expected a list of programs or an object of ROMs".to_string()),
+        }
+        Ok(database)
+    }
+
+    /// Adds the entries of `other`, which win wherever both say something
+    /// about the same ROM.
+    pub fn merge(&mut self, other: Database) {
+        for (hash, config) in other.roms {
+            self.roms.entry(hash).or_default().merge(config);
+        }
+    }
+
+    pub fn get(&self, hash: &Hash) -> Option<&RomConfig> {
+        self.roms.get(&sha1::to_hex(hash))
+    }
+
+    /// The entry for a ROM, given the bytes that will be handed to `Chip8::new`.
+    pub fn lookup(&self, rom: &[u8]) -> Option<&RomConfig> {
+        self.get(&sha1::sha1(rom))
+    }
+
+    pub fn len(&self) -> usize {
+        self.roms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roms.is_empty()
+    }
+}
+
+impl Default for Database {
+    fn default() -> Database {
+        Database::new()
+    }
+}
+
+/// Reads colours written as hex, like `FFB000` or `#FFB000`.
+pub fn parse_colour(value: &str) -> Option<[u8; 3]> {
+    let digits = value.trim_start_matches('#');
+    match u32::from_str_radix(digits, 16) {
+        Ok(rgb) if digits.len() == 6 => Some([(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8]),
+        _ => None,
+    }
+}
+
+fn parse_rom(hash: &str, rom: &Value) -> Result<RomConfig, String> {
+    let bad = |what: &str| format!("ROM {}: bad {}", hash, what);
+    if rom.as_object().is_none() {
+        return Err(bad("entry"));
+    }
+    let mut config = RomConfig {
+        title: rom.get("title").and_then(Value::as_str).map(str::to_string),
+        ..RomConfig::default()
+    };
+
+    if let Some(tickrate) = rom.get("tickrate") {
+        let tickrate = tickrate.as_f64().filter(|&rate| rate > 0.0).ok_or_else(|| bad("tickrate"))?;
+        config.clock_rate = Some(tickrate * TICKS_PER_SECOND);
+    }
+
+    // The first platform we can run is the one to use
+    let platforms = rom.get("platforms").and_then(Value::as_array).unwrap_or(&[]);
+    let platform = platforms.iter()
+        .filter_map(Value::as_str)
+        .filter_map(|id| PLATFORMS.iter().find(|&&(known, _, _)| known == id))
+        .next();
+    if let Some(&(id, platform, quirks)) = platform {
+        config.platform = Some(platform);
+        let tweaks = rom.get("quirkyPlatforms").and_then(|quirky| quirky.get(id));
+        config.quirks = Some(match tweaks {
+            Some(tweaks) => apply_quirks(quirks, tweaks).ok_or_else(|| bad("quirks"))?,
+            None => quirks,
+        });
+    }
+
+    if let Some(keys) = rom.get("keys") {
+        for (action, key) in keys.as_object().ok_or_else(|| bad("keys"))? {
+            match key.as_f64() {
+                Some(key) if key >= 0.0 && (key as usize) < NUM_KEYS => {
+                    config.keys.push((action.clone(), key as Key))
+                }
+                _ => return Err(bad("keys")),
+            }
+        }
+    }
+
+    if let Some(pixels) = rom.get("colors").and_then(|colours| colours.get("pixels")) {
+        for colour in pixels.as_array().ok_or_else(|| bad("colors"))? {
+            config.colours.push(colour.as_str().and_then(parse_colour).ok_or_else(|| bad("colors"))?);
+        }
+    }
+    Ok(config)
+}
+
+/// Changes the quirks the database describes. `vblank` is left out because
+/// we don't emulate it.
+fn apply_quirks(mut quirks: Quirks, tweaks: &Value) -> Option<Quirks> {
+    for (name, value) in tweaks.as_object()? {
+        let value = value.as_bool()?;
+        match name.as_str() {
+            "shift" => quirks.shift_uses_vy = !value,
+            "memoryLeaveIUnchanged" => quirks.load_store_increments_i = !value,
+            "memoryIncrementByX" => quirks.load_store_increments_i_by_x = value,
+            "logic" => quirks.logic_resets_vf = value,
+            "jump" => quirks.jump_uses_vx = value,
+            "wrap" => quirks.wrap_sprites = value,
+            _ => {}
+        }
+    }
+    Some(quirks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAMS: &str = r##"[
+        {"title": "Pong", "roms": {
+            "DA39A3EE5E6B4B0D3255BFEF95601890AFD80709": {
+                "platforms": ["megachip8", "superchip"],
+                "quirkyPlatforms": {"superchip": {"wrap": true}},
+                "tickrate": 30,
+                "keys": {"up": 1, "down": 4},
+                "colors": {"pixels": ["#000000", "#ffb000"]}
+            }
+        }}
+    ]"##;
+
+    #[test]
+    fn reads_the_community_database() {
+        let database = Database::parse(PROGRAMS).unwrap();
+        let config = database.lookup(&[]).unwrap();
+        assert_eq!(config.title, Some("Pong".to_string()));
+        assert_eq!(config.platform, Some(Platform::SuperChip));
+        assert_eq!(config.quirks, Some(Quirks { wrap_sprites: true, ..Quirks::SUPER_CHIP }));
+        assert_eq!(config.clock_rate, Some(1800.0));
+        assert_eq!(config.colours, vec![[0, 0, 0], [0xFF, 0xB0, 0]]);
+
+        let mut keyboard = Keymap::standard();
+        config.bind_keys(&mut keyboard);
+        assert_eq!(keyboard.get("up"), Some(0x1));
+        assert_eq!(keyboard.get("down"), Some(0x4));
+        assert_eq!(keyboard.get("space"), None);
+    }
+
+    #[test]
+    fn local_entries_override_fields_they_give() {
+        let mut database = Database::parse(PROGRAMS).unwrap();
+        database.merge(Database::parse(r#"{"da39a3ee5e6b4b0d3255bfef95601890afd80709": {
+                                               "tickrate": 10, "keys": {"up": 2}}}"#)
+            .unwrap());
+        let config = database.lookup(&[]).unwrap();
+        assert_eq!(config.clock_rate, Some(600.0));
+        assert_eq!(config.platform, Some(Platform::SuperChip));
+        assert_eq!(config.keys, vec![("down".to_string(), 0x4), ("up".to_string(), 0x2)]);
+
+        assert!(Database::parse(r#"{"00": {"tickrate": "fast"}}"#).is_err());
+    }
+
+    #[test]
+    fn the_command_line_wins_over_the_database() {
+        let unknown = RomConfig::default();
+        assert_eq!(unknown.machine(None, None), (DEFAULT_PLATFORM, DEFAULT_QUIRKS));
+        assert_eq!(unknown.machine(Some(Platform::Chip8), None), (Platform::Chip8, Quirks::COSMAC_VIP));
+
+        let config = RomConfig {
+            platform: Some(Platform::XoChip),
+            quirks: Some(Quirks::CHIP_48),
+            ..RomConfig::default()
+        };
+        assert_eq!(config.machine(None, None), (Platform::XoChip, Quirks::CHIP_48));
+        assert_eq!(config.machine(None, Some(Quirks::SUPER_CHIP)), (Platform::XoChip, Quirks::SUPER_CHIP));
+        assert_eq!(config.machine(Some(Platform::Chip8), None), (Platform::Chip8, Quirks::COSMAC_VIP));
+        assert_eq!(config.machine(Some(Platform::Chip8), Some(Quirks::MODERN)), (Platform::Chip8, Quirks::MODERN));
+    }
+}
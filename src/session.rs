@@ -0,0 +1,229 @@
+//! What every frontend does with the machine, whatever it draws on or takes
+//! input from: running it in real time, pausing, rewinding, quick-save
+//! slots and recording or playing back a movie.
+//!
+//! A frontend calls `update` with the time since the last call, sends key
+//! presses with `send_key` and draws `session.computer.display`.
+
+use std::fs::File;
+use std::io::{Read, Write};
+
+use chip8::{Chip8, Chip8Error, Key};
+use movie::{Movie, Playback};
+use rewind::{self, Rewind};
+
+/// How long a frame lasts, which is how fast movies play and rewinding goes.
+pub const FRAME_TIME: f64 = 1.0 / 60.0;
+
+pub struct Session<'a> {
+    pub computer: Chip8,
+    playback: Option<Playback<'a>>,
+    recording: Option<Movie>,
+    rewind: Rewind,
+    paused: bool,
+    rewinding: bool,
+    /// Time not yet used up by whole frames.
+    frame_time: f64,
+}
+
+impl<'a> Session<'a> {
+    /// Runs `computer`, playing back `movie` if there is one, which should
+    /// be what `computer` was started from. `record` records a new movie.
+    pub fn new(computer: Chip8, movie: Option<&'a Movie>, record: bool) -> Session<'a> {
+        let mut rewind = Rewind::new(rewind::DEFAULT_CAPACITY);
+        rewind.push(computer.save_state());
+        Session {
+            recording: if record { Some(Movie::new(&computer)) } else { None },
+            computer,
+            playback: movie.map(Playback::new),
+            rewind,
+            paused: false,
+            rewinding: false,
+            frame_time: 0.0,
+        }
+    }
+
+    /// Runs the machine, or rewinds it, for the time that has passed.
+    /// Nothing happens while paused.
+    pub fn update(&mut self, seconds: f64) -> Result<(), Chip8Error> {
+        if self.rewinding {
+            self.frame_time += seconds;
+            while self.frame_time >= FRAME_TIME {
+                self.frame_time -= FRAME_TIME;
+                if let Some(snapshot) = self.rewind.step_back().map_err(Chip8Error::BadSnapshot)? {
+                    self.computer.load_state(snapshot).map_err(Chip8Error::BadSnapshot)?;
+                }
+            }
+            return Ok(());
+        }
+        if self.paused {
+            return Ok(());
+        }
+
+        // A frame at a time, so a movie's keys go in before the right frame
+        // and every frame can be rewound to
+        self.frame_time += seconds;
+        while self.frame_time >= FRAME_TIME {
+            self.frame_time -= FRAME_TIME;
+            match self.playback {
+                Some(ref mut playback) => playback.step_frame(&mut self.computer)?,
+                None => self.computer.step_frame()?,
+            }
+            self.rewind.push(self.computer.save_state());
+        }
+        Ok(())
+    }
+
+    /// Passes a key on to the machine, through the movie if one is being
+    /// recorded. Keys do nothing while a movie plays.
+    pub fn send_key(&mut self, key: Key, pressed: bool) {
+        match self.recording {
+            _ if self.playback.is_some() => {}
+            Some(ref mut movie) if pressed => movie.press(&mut self.computer, key),
+            Some(ref mut movie) => movie.release(&mut self.computer, key),
+            None if pressed => self.computer.handle_key_press(key),
+            None => self.computer.handle_key_release(key),
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    pub fn is_rewinding(&self) -> bool {
+        self.rewinding
+    }
+
+    /// Steps back a frame every `FRAME_TIME` until `stop_rewinding`.
+    pub fn start_rewinding(&mut self) {
+        if self.can_travel() && !self.rewinding {
+            self.rewinding = true;
+            self.frame_time = 0.0;
+        }
+    }
+
+    pub fn stop_rewinding(&mut self) {
+        self.rewinding = false;
+    }
+
+    /// Whether going back in time is allowed. It isn't while recording or
+    /// playing a movie, since neither rewinding nor loading a slot can be
+    /// put in one.
+    pub fn can_travel(&self) -> bool {
+        self.playback.is_none() && self.recording.is_none()
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playback.is_some()
+    }
+
+    /// Saves the machine to a slot. Slots are kept next to the game so they
+    /// survive restarts.
+    pub fn save_slot(&self, game: &str, slot: u8) -> Result<(), String> {
+        let path = slot_path(game, slot);
+        File::create(&path)
+            .and_then(|mut file| file.write_all(&self.computer.save_state()))
+            .map_err(|error| format!("Could not save to {}: {}", path, error))
+    }
+
+    pub fn load_slot(&mut self, game: &str, slot: u8) -> Result<(), String> {
+        if !self.can_travel() {
+            return Err("Slots can't be loaded while a movie is recording or playing".to_string());
+        }
+        let path = slot_path(game, slot);
+        let mut snapshot = Vec::new();
+        File::open(&path)
+            .and_then(|mut file| file.read_to_end(&mut snapshot))
+            .map_err(|error| format!("Could not load {}: {}", path, error))?;
+        self.computer.load_state(&snapshot).map_err(|error| format!("Could not load {}: {}", path, error))?;
+        // The history leads up to where we were, not to the loaded state
+        self.rewind.clear();
+        self.rewind.push(self.computer.save_state());
+        Ok(())
+    }
+
+    /// The movie recorded so far, if one is being recorded.
+    pub fn recording(&self) -> Option<&Movie> {
+        self.recording.as_ref()
+    }
+}
+
+fn slot_path(game: &str, slot: u8) -> String {
+    format!("{}.state{}", game, slot)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use platform::Platform;
+
+    /// Counts up in V0 while key 5 is held.
+    const ROM: [u8; 8] = [0x61, 0x05, // V1 := 5
+                          0xE1, 0xA1, // loop: if V1 key then
+                          0x70, 0x01, //   V0 += 1
+                          0x12, 0x02]; // jump loop
+
+    fn session<'a>(record: bool) -> Session<'a> {
+        let mut chip8 = Chip8::new(ROM.to_vec(), Platform::SuperChip, Platform::SuperChip.default_quirks()).unwrap();
+        chip8.set_clock_rate(60.0);
+        Session::new(chip8, None, record)
+    }
+
+    #[test]
+    fn rewinding_goes_back_a_frame_at_a_time() {
+        let mut session = session(false);
+        session.send_key(0x5, true);
+        for _ in 0..10 {
+            session.update(FRAME_TIME).unwrap();
+        }
+        let counted = session.computer.registers()[0];
+        assert!(counted > 0);
+
+        session.set_paused(true);
+        session.update(FRAME_TIME).unwrap();
+        assert_eq!(session.computer.registers()[0], counted);
+
+        session.start_rewinding();
+        session.update(FRAME_TIME * 4.5).unwrap();
+        session.stop_rewinding();
+        assert!(session.computer.registers()[0] < counted);
+        assert_eq!(session.computer.frame_count(), 6);
+    }
+
+    #[test]
+    fn every_frame_is_kept_for_rewinding() {
+        let mut session = session(false);
+        session.send_key(0x5, true);
+        // One slow update that has to catch up ten frames
+        session.update(FRAME_TIME * 10.5).unwrap();
+        assert_eq!(session.computer.frame_count(), 10);
+
+        session.start_rewinding();
+        for frame_count in (7..10).rev() {
+            session.update(FRAME_TIME).unwrap();
+            assert_eq!(session.computer.frame_count(), frame_count);
+        }
+    }
+
+    #[test]
+    fn recording_keeps_the_past_fixed() {
+        let mut session = session(true);
+        assert!(!session.can_travel());
+        session.start_rewinding();
+        assert!(!session.is_rewinding());
+        assert!(session.load_slot("game.ch8", 1).is_err());
+
+        session.send_key(0x5, true);
+        session.update(FRAME_TIME).unwrap();
+        session.send_key(0x5, false);
+        assert_eq!(session.recording().unwrap().events.len(), 2);
+    }
+}
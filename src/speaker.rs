@@ -0,0 +1,71 @@
+use std::time::Duration;
+
+use rodio::{self, Sink, Source};
+
+use rust_8::audio::{AudioSink, PatternWave, SquareWave, BEEP_FREQUENCY, PATTERN_SIZE, SAMPLE_RATE};
+
+/// Plays the buzzer on the default output device.
+pub struct Speaker {
+    device: rodio::Device,
+    sink: Sink,
+}
+
+impl Speaker {
+    /// Returns `None` if there is no output device to play on.
+    pub fn new() -> Option<Speaker> {
+        let device = rodio::default_output_device()?;
+        let sink = Sink::new(&device);
+        sink.append(Tone(Box::new(SquareWave::new(BEEP_FREQUENCY))));
+        sink.pause();
+        Some(Speaker { device, sink })
+    }
+}
+
+impl AudioSink for Speaker {
+    fn set_beeping(&mut self, beeping: bool) {
+        if beeping {
+            self.sink.play();
+        } else {
+            self.sink.pause();
+        }
+    }
+
+    fn set_pattern(&mut self, pattern: [u8; PATTERN_SIZE], pitch: u8) {
+        // A stopped sink can't be restarted, so the new tone gets a new sink
+        let sink = Sink::new(&self.device);
+        sink.append(Tone(Box::new(PatternWave::new(pattern, pitch))));
+        if self.sink.is_paused() {
+            sink.pause();
+        }
+        self.sink.stop();
+        self.sink = sink;
+    }
+}
+
+struct Tone(Box<dyn Iterator<Item = f32> + Send>);
+
+impl Iterator for Tone {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        self.0.next()
+    }
+}
+
+impl Source for Tone {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
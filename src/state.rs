@@ -0,0 +1,260 @@
+//! The building blocks of the save state format.
+//!
+//! A save state is a header followed by the machine's fields in a fixed
+//! order. All multi-byte numbers are big-endian, like CHIP-8 itself.
+
+use std::error::Error;
+use std::fmt;
+
+use sha1::Hash;
+
+pub const MAGIC: &[u8; 4] = b"R8ST";
+/// Bump this when the layout changes, and keep reading the old layouts too,
+/// since quick-save slots outlive the build that wrote them.
+///
+/// 1. The first layout.
+/// 2. Adds the frame count after the pending instructions.
+/// 3. Adds the random source's state after the pitch.
+pub const VERSION: u8 = 3;
+/// The oldest layout that can still be read.
+pub const OLDEST_VERSION: u8 = 1;
+
+/// Why a save state could not be loaded.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateError {
+    /// The data isn't a save state at all.
+    NotASaveState,
+    /// The save state was written by a newer or older version of the format.
+    UnsupportedVersion(u8),
+    /// The save state was taken while running a different ROM.
+    WrongRom,
+    /// The save state was taken while running on a different platform.
+    WrongPlatform,
+    /// The save state ends before all of the machine has been read.
+    Truncated,
+    /// A field holds a value the machine could never be in.
+    Corrupt,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StateError::NotASaveState => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "save state version {} is not supported", version)
+            }
+            StateError::WrongRom => write!(f, "save state is for a different ROM"),
+            StateError::WrongPlatform => write!(f, "save state is for a different platform"),
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::Corrupt => write!(f, "save state is corrupt"),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+pub struct StateWriter {
+    bytes: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        StateWriter { bytes: Vec::new() }
+    }
+
+    pub fn header(&mut self, platform: u8, rom_hash: &Hash) {
+        self.bytes(MAGIC);
+        self.u8(VERSION);
+        self.u8(platform);
+        self.bytes(rom_hash);
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.u8((value >> 8) as u8);
+        self.u8(value as u8);
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.u16((value >> 16) as u16);
+        self.u16(value as u16);
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.u32((value >> 32) as u32);
+        self.u32(value as u32);
+    }
+
+    pub fn f64(&mut self, value: f64) {
+        self.u64(value.to_bits());
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+pub struct StateReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> StateReader<'a> {
+        StateReader { bytes }
+    }
+
+    /// Checks the header against the machine the state is being loaded into,
+    /// and returns the version of the layout that follows.
+    pub fn header(&mut self, platform: u8, rom_hash: &Hash) -> Result<u8, StateError> {
+        if self.bytes.len() < MAGIC.len() || &self.bytes[..MAGIC.len()] != MAGIC {
+            return Err(StateError::NotASaveState);
+        }
+        self.bytes = &self.bytes[MAGIC.len()..];
+
+        let version = self.u8()?;
+        if !(OLDEST_VERSION..=VERSION).contains(&version) {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        if self.u8()? != platform {
+            return Err(StateError::WrongPlatform);
+        }
+        if self.bytes(rom_hash.len())? != &rom_hash[..] {
+            return Err(StateError::WrongRom);
+        }
+        Ok(version)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Corrupt),
+        }
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        let high = self.u8()? as u16;
+        let low = self.u8()? as u16;
+        Ok(high << 8 | low)
+    }
+
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        let high = self.u16()? as u32;
+        let low = self.u16()? as u32;
+        Ok(high << 16 | low)
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        let high = self.u32()? as u64;
+        let low = self.u32()? as u64;
+        Ok(high << 32 | low)
+    }
+
+    pub fn f64(&mut self) -> Result<f64, StateError> {
+        Ok(f64::from_bits(self.u64()?))
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.bytes.len() < len {
+            return Err(StateError::Truncated);
+        }
+        let (bytes, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(bytes)
+    }
+
+    /// Fails unless every byte has been read.
+    pub fn finish(self) -> Result<(), StateError> {
+        if self.bytes.is_empty() {
+            Ok(())
+        } else {
+            Err(StateError::Corrupt)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROM_HASH: Hash = [7; 20];
+
+    fn state() -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.header(2, &ROM_HASH);
+        writer.u8(0xAB);
+        writer.bool(true);
+        writer.u16(0x1234);
+        writer.u32(0xDEADBEEF);
+        writer.u64(0x0123456789ABCDEF);
+        writer.f64(-0.5);
+        writer.bytes(b"rest");
+        writer.into_bytes()
+    }
+
+    #[test]
+    fn reads_back_what_was_written() {
+        let state = state();
+        let mut reader = StateReader::new(&state);
+        assert_eq!(reader.header(2, &ROM_HASH), Ok(VERSION));
+        assert_eq!(reader.u8(), Ok(0xAB));
+        assert_eq!(reader.bool(), Ok(true));
+        assert_eq!(reader.u16(), Ok(0x1234));
+        assert_eq!(reader.u32(), Ok(0xDEADBEEF));
+        assert_eq!(reader.u64(), Ok(0x0123456789ABCDEF));
+        assert_eq!(reader.f64(), Ok(-0.5));
+        assert_eq!(reader.bytes(4), Ok(&b"rest"[..]));
+        assert_eq!(reader.finish(), Ok(()));
+    }
+
+    #[test]
+    fn headers_must_match_the_machine() {
+        let state = state();
+        let header = |state: &[u8], platform: u8, rom_hash: &Hash| StateReader::new(state).header(platform, rom_hash);
+
+        let mut bad_magic = state.clone();
+        bad_magic[3] = b'X';
+        assert_eq!(header(&bad_magic, 2, &ROM_HASH), Err(StateError::NotASaveState));
+        assert_eq!(header(&state[..2], 2, &ROM_HASH), Err(StateError::NotASaveState));
+        assert_eq!(header(&state, 2, &[8; 20]), Err(StateError::WrongRom));
+        assert_eq!(header(&state, 1, &ROM_HASH), Err(StateError::WrongPlatform));
+        let mut newer = state.clone();
+        newer[4] = VERSION + 1;
+        assert_eq!(header(&newer, 2, &ROM_HASH), Err(StateError::UnsupportedVersion(VERSION + 1)));
+        for length in 4..26 {
+            assert_eq!(header(&state[..length], 2, &ROM_HASH), Err(StateError::Truncated), "{} bytes", length);
+        }
+    }
+
+    #[test]
+    fn reading_past_the_end_or_stopping_short_fails() {
+        let state = state();
+        let mut reader = StateReader::new(&state[..state.len() - 2]);
+        reader.header(2, &ROM_HASH).unwrap();
+        reader.bytes(1 + 1 + 2 + 4 + 8 + 8).unwrap();
+        assert_eq!(reader.bytes(4), Err(StateError::Truncated));
+        assert_eq!(reader.u32(), Err(StateError::Truncated));
+
+        let mut reader = StateReader::new(&state);
+        reader.header(2, &ROM_HASH).unwrap();
+        reader.u8().unwrap();
+        assert_eq!(reader.finish(), Err(StateError::Corrupt));
+
+        assert_eq!(StateReader::new(&[2]).bool(), Err(StateError::Corrupt));
+    }
+}
@@ -0,0 +1,314 @@
+//! Plays a game in the terminal, for when there's no window to open, e.g.
+//! over SSH.
+//!
+//! Each character shows two pixels, one above the other, with the Unicode
+//! half blocks, or with `'`, `.` and `:` where the locale isn't UTF-8. The
+//! registers are shown beside the screen.
+//!
+//! Most terminals only say when a key goes down. Those that can also say
+//! when it comes up are asked to; with the rest a key is let go once the
+//! terminal stops repeating it.
+
+use std::collections::HashMap;
+use std::env;
+use std::io::{self, Stdout, Write};
+use std::time::{Duration, Instant};
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+                       PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+
+use rust_8::keymap::Keymap;
+use rust_8::session::{self, Session};
+use rust_8::Key;
+
+/// How long a key stays down after the terminal says it was pressed, if it
+/// can't say when it comes up. Long enough for the terminal to start
+/// repeating it if it is held.
+const PRESS_HOLD: f64 = 0.5;
+/// How long each repeat keeps the key down for after that.
+const REPEAT_HOLD: f64 = 0.1;
+/// Columns between the screen and the registers.
+const PANEL_GAP: usize = 2;
+const PANEL_WIDTH: usize = 24;
+const HELP: [&str; 3] = ["Esc quit  F9 pause", "Backspace rewind", "F1-F4 save  F5-F8 load"];
+
+/// Something that is held down: a CHIP-8 key, or rewinding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Control {
+    Key(Key),
+    Rewind,
+}
+
+struct Terminal {
+    out: Stdout,
+    ascii: bool,
+    /// Whether the terminal says when keys come up.
+    reports_releases: bool,
+    /// What is held down, and when to let go of it if the terminal won't say.
+    held: HashMap<Control, Option<Instant>>,
+    /// The last slot saved or loaded, or why it couldn't be.
+    message: String,
+    /// The size the screen was last drawn at, to clear away the old one.
+    layout: (usize, usize, u16, u16),
+}
+
+/// Runs the game until it exits or Esc is pressed.
+pub fn run(session: &mut Session,
+           game: &str,
+           keymap: &Keymap,
+           palette: &[[f32; 4]; 4],
+           ascii: bool)
+           -> Result<(), String> {
+    let mut terminal = Terminal::open(ascii || !supports_unicode()).map_err(terminal_error)?;
+    let frame = Duration::from_millis((session::FRAME_TIME * 1000.0) as u64);
+    let mut last_update = Instant::now();
+
+    loop {
+        let now = Instant::now();
+        terminal.release_expired(session, now);
+        session.update(now.duration_since(last_update).as_secs_f64())
+            .map_err(|error| format!("The emulator stopped: {}\n{:?}", error, session.computer))?;
+        last_update = now;
+        if session.computer.has_exited() {
+            return Ok(());
+        }
+        terminal.draw(session, palette).map_err(terminal_error)?;
+
+        // Handles keys as they come in until the next frame is due
+        let next_frame = now + frame;
+        loop {
+            let now = Instant::now();
+            if now >= next_frame {
+                break;
+            }
+            if event::poll(next_frame - now).map_err(terminal_error)? {
+                if let Event::Key(key) = event::read().map_err(terminal_error)? {
+                    if !terminal.handle_key(session, game, keymap, key) {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn terminal_error(error: io::Error) -> String {
+    format!("The terminal stopped working: {}", error)
+}
+
+/// Whether the locale says the terminal takes UTF-8.
+fn supports_unicode() -> bool {
+    ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .is_some_and(|locale| {
+            let locale = locale.to_lowercase();
+            locale.contains("utf-8") || locale.contains("utf8")
+        })
+}
+
+impl Terminal {
+    fn open(ascii: bool) -> io::Result<Terminal> {
+        terminal::enable_raw_mode()?;
+        let mut terminal = Terminal {
+            out: io::stdout(),
+            ascii,
+            reports_releases: false,
+            held: HashMap::new(),
+            message: String::new(),
+            layout: (0, 0, 0, 0),
+        };
+        // From here on dropping the terminal puts it back the way it was
+        execute!(terminal.out, EnterAlternateScreen, Hide)?;
+        if terminal::supports_keyboard_enhancement().unwrap_or(false) {
+            execute!(terminal.out,
+                     PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES))?;
+            terminal.reports_releases = true;
+        }
+        Ok(terminal)
+    }
+
+    /// Acts on a key. Returns false if it was the one to quit.
+    fn handle_key(&mut self, session: &mut Session, game: &str, keymap: &Keymap, key: KeyEvent) -> bool {
+        let control = match key.code {
+            KeyCode::Backspace => Some(Control::Rewind),
+            code => host_key_name(code).and_then(|name| keymap.get(&name)).map(Control::Key),
+        };
+        if key.kind == KeyEventKind::Release {
+            if let Some(control) = control {
+                if self.held.remove(&control).is_some() {
+                    release(session, control);
+                }
+            }
+            return true;
+        }
+
+        match key.code {
+            KeyCode::Esc => return false,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return false,
+            KeyCode::F(9) if key.kind == KeyEventKind::Press => {
+                let paused = session.is_paused();
+                session.set_paused(!paused);
+            }
+            KeyCode::F(slot @ 1..=4) if key.kind == KeyEventKind::Press => {
+                self.message = match session.save_slot(game, slot) {
+                    Ok(()) => format!("Saved to slot {}", slot),
+                    Err(error) => error,
+                };
+            }
+            KeyCode::F(slot @ 5..=8) if key.kind == KeyEventKind::Press => {
+                self.message = match session.load_slot(game, slot - 4) {
+                    Ok(()) => format!("Loaded slot {}", slot - 4),
+                    Err(error) => error,
+                };
+            }
+            _ => {}
+        }
+
+        if let Some(control) = control {
+            let now = Instant::now();
+            // Without releases, a key that is already down is being repeated
+            let hold = if self.held.contains_key(&control) { REPEAT_HOLD } else { PRESS_HOLD };
+            let release_at = if self.reports_releases {
+                None
+            } else {
+                Some(now + Duration::from_millis((hold * 1000.0) as u64))
+            };
+            if self.held.insert(control, release_at).is_none() {
+                match control {
+                    Control::Key(key) => session.send_key(key, true),
+                    Control::Rewind => session.start_rewinding(),
+                }
+            }
+        }
+        true
+    }
+
+    /// Lets go of the keys the terminal has stopped repeating.
+    fn release_expired(&mut self, session: &mut Session, now: Instant) {
+        let expired: Vec<Control> = self.held
+            .iter()
+            .filter(|&(_, release_at)| release_at.is_some_and(|release_at| release_at <= now))
+            .map(|(control, _)| *control)
+            .collect();
+        for control in expired {
+            self.held.remove(&control);
+            release(session, control);
+        }
+    }
+
+    fn draw(&mut self, session: &Session, palette: &[[f32; 4]; 4]) -> io::Result<()> {
+        let display = &session.computer.display;
+        let (columns, rows) = terminal::size()?;
+        let layout = (display.width(), display.height(), columns, rows);
+        if layout != self.layout {
+            queue!(self.out, ResetColor, Clear(ClearType::All))?;
+            self.layout = layout;
+        }
+
+        let panel = self.panel(session);
+        let width = display.width() + PANEL_GAP + PANEL_WIDTH;
+        let height = (display.height() / 2).max(panel.len());
+        if (columns as usize) < width || (rows as usize) < height {
+            queue!(self.out,
+                   MoveTo(0, 0),
+                   Print(format!("Make the terminal at least {}x{}", width, height)))?;
+            return self.out.flush();
+        }
+
+        if !self.ascii {
+            queue!(self.out,
+                   SetForegroundColor(colour(&palette[1])),
+                   SetBackgroundColor(colour(&palette[0])))?;
+        }
+        for (y, line) in display.to_half_blocks(self.ascii).lines().enumerate() {
+            queue!(self.out, MoveTo(0, y as u16), Print(line))?;
+        }
+        queue!(self.out, ResetColor)?;
+        for (y, line) in panel.iter().enumerate() {
+            queue!(self.out,
+                   MoveTo((display.width() + PANEL_GAP) as u16, y as u16),
+                   Print(format!("{:<width$.width$}", line, width = PANEL_WIDTH)))?;
+        }
+        self.out.flush()
+    }
+
+    /// The registers, what the session is doing and the keys to control it.
+    fn panel(&self, session: &Session) -> Vec<String> {
+        let computer = &session.computer;
+        let registers = computer.registers();
+        let mut lines = vec![format!("PC {:04X}  I {:04X}", computer.program_counter(), computer.i_register()),
+                             format!("SP {:X}  DT {:02X}  ST {:02X}",
+                                     computer.stack_pointer(),
+                                     computer.delay_timer(),
+                                     computer.sound_timer()),
+                             String::new()];
+        for row in 0..8 {
+            lines.push(format!("V{:X} {:02X}   V{:X} {:02X}", row, registers[row], row + 8, registers[row + 8]));
+        }
+
+        let status = if session.is_rewinding() {
+            "Rewinding"
+        } else if session.is_paused() {
+            "Paused"
+        } else if session.is_playing() {
+            "Playing the movie"
+        } else if session.is_recording() {
+            "Recording"
+        } else {
+            "Running"
+        };
+        lines.push(String::new());
+        lines.push(status.to_string());
+        lines.push(self.message.clone());
+        lines.push(String::new());
+        lines.extend(HELP.iter().map(|line| line.to_string()));
+        lines
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        if self.reports_releases {
+            let _ = execute!(self.out, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(self.out, ResetColor, Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn release(session: &mut Session, control: Control) {
+    match control {
+        Control::Key(key) => session.send_key(key, false),
+        Control::Rewind => session.stop_rewinding(),
+    }
+}
+
+/// The name a keymap file uses for a key, which is the same as in the
+/// window so the same keymap files work in both.
+fn host_key_name(code: KeyCode) -> Option<String> {
+    match code {
+        KeyCode::Char(' ') => Some("Space".to_string()),
+        KeyCode::Char(character) => Some(character.to_string()),
+        KeyCode::Up => Some("Up".to_string()),
+        KeyCode::Down => Some("Down".to_string()),
+        KeyCode::Left => Some("Left".to_string()),
+        KeyCode::Right => Some("Right".to_string()),
+        KeyCode::Enter => Some("Return".to_string()),
+        KeyCode::Tab => Some("Tab".to_string()),
+        _ => None,
+    }
+}
+
+fn colour(rgba: &[f32; 4]) -> Color {
+    Color::Rgb {
+        r: (rgba[0] * 255.0).round() as u8,
+        g: (rgba[1] * 255.0).round() as u8,
+        b: (rgba[2] * 255.0).round() as u8,
+    }
+}
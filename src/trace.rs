@@ -0,0 +1,302 @@
+//! Logging every instruction the machine runs, to compare with other emulators.
+//!
+//! Each entry is the state of the machine just before an instruction runs.
+//! Text traces have one entry per line, with the instruction at the end for
+//! people to read:
+//!
+//! ```text
+//! # frame pc op v0 .. vf i sp dt st instruction
+//! 12 0204 C20F 00 07 0D 00 00 00 00 00 00 00 00 00 00 00 00 00 0041 00 00 00 RND V2, #0F
+//! ```
+//!
+//! Binary traces are `R8TR` and a version byte followed by fixed-size
+//! records of the same fields, big-endian, and are much smaller. Both read
+//! back with `read` so either can be compared with `first_difference`.
+
+use std::fmt;
+use std::io::{self, Write};
+use std::str;
+
+use chip8::NUM_GENERAL_PURPOSE_REGS;
+use instruction::{Address, Instruction};
+use state::{StateError, StateReader, StateWriter};
+
+const MAGIC: &[u8; 4] = b"R8TR";
+const VERSION: u8 = 1;
+/// The frame, pc, opcode, registers, I, SP and the two timers.
+const RECORD_SIZE: usize = 8 + 2 + 2 + NUM_GENERAL_PURPOSE_REGS + 2 + 1 + 1 + 1;
+const TEXT_HEADER: &str = "# frame pc op v0 .. vf i sp dt st instruction";
+
+/// The machine as an instruction was about to run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TraceEntry {
+    pub frame: u64,
+    pub pc: Address,
+    pub opcode: u16,
+    pub registers: [u8; NUM_GENERAL_PURPOSE_REGS],
+    pub i: Address,
+    pub sp: u8,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
+
+/// Formats the entry as a line of a text trace, without the instruction.
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {:04X} {:04X}", self.frame, self.pc, self.opcode)?;
+        for value in self.registers.iter() {
+            write!(f, " {:02X}", value)?;
+        }
+        write!(f,
+               " {:04X} {:02X} {:02X} {:02X}",
+               self.i,
+               self.sp,
+               self.delay_timer,
+               self.sound_timer)
+    }
+}
+
+/// Something the machine tells about every instruction it runs.
+pub trait Tracer {
+    fn trace(&mut self, entry: &TraceEntry, instruction: &Instruction);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceFormat {
+    Text,
+    Binary,
+}
+
+/// Which entries to keep. Both ranges are inclusive, and an entry has to be
+/// in both to be kept.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TraceFilter {
+    pub addresses: Option<(Address, Address)>,
+    pub frames: Option<(u64, u64)>,
+}
+
+impl TraceFilter {
+    pub fn matches(&self, entry: &TraceEntry) -> bool {
+        let in_range = |value, range: Option<(_, _)>| match range {
+            Some((first, last)) => first <= value && value <= last,
+            None => true,
+        };
+        in_range(entry.pc as u64, self.addresses.map(|(first, last)| (first as u64, last as u64))) &&
+        in_range(entry.frame, self.frames)
+    }
+}
+
+/// Writes the entries a filter lets through to a file, or anything else.
+///
+/// The first write error ends the trace, since it can't stop the machine.
+/// Wrap files in a `BufWriter`; a trace is written an instruction at a time.
+pub struct TraceWriter<W: Write> {
+    writer: W,
+    format: TraceFormat,
+    filter: TraceFilter,
+    started: bool,
+    failed: bool,
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(writer: W, format: TraceFormat, filter: TraceFilter) -> TraceWriter<W> {
+        TraceWriter {
+            writer,
+            format,
+            filter,
+            started: false,
+            failed: false,
+        }
+    }
+
+    fn write(&mut self, entry: &TraceEntry, instruction: &Instruction) -> io::Result<()> {
+        match self.format {
+            TraceFormat::Text => {
+                if !self.started {
+                    writeln!(self.writer, "{}", TEXT_HEADER)?;
+                }
+                writeln!(self.writer, "{} {}", entry, instruction)
+            }
+            TraceFormat::Binary => {
+                let mut record = StateWriter::new();
+                if !self.started {
+                    record.bytes(MAGIC);
+                    record.u8(VERSION);
+                }
+                record.u64(entry.frame);
+                record.u16(entry.pc);
+                record.u16(entry.opcode);
+                record.bytes(&entry.registers);
+                record.u16(entry.i);
+                record.u8(entry.sp);
+                record.u8(entry.delay_timer);
+                record.u8(entry.sound_timer);
+                self.writer.write_all(&record.into_bytes())
+            }
+        }
+    }
+}
+
+impl<W: Write> Tracer for TraceWriter<W> {
+    fn trace(&mut self, entry: &TraceEntry, instruction: &Instruction) {
+        if self.failed || !self.filter.matches(entry) {
+            return;
+        }
+        self.failed = self.write(entry, instruction).is_err();
+        self.started = true;
+    }
+}
+
+/// Reads a text or binary trace.
+pub fn read(bytes: &[u8]) -> Result<Vec<TraceEntry>, String> {
+    if bytes.starts_with(MAGIC) {
+        read_binary(&bytes[MAGIC.len()..])
+    } else {
+        let text = str::from_utf8(bytes).map_err(|_| "not a trace".to_string())?;
+        read_text(text)
+    }
+}
+
+fn read_binary(bytes: &[u8]) -> Result<Vec<TraceEntry>, String> {
+    match bytes.first() {
+        Some(&VERSION) => {}
+        Some(&version) => return Err(format!("trace version {} is not supported", version)),
+        None => return Err("the trace is truncated".to_string()),
+    }
+    let records = &bytes[1..];
+    if !records.len().is_multiple_of(RECORD_SIZE) {
+        return Err("the trace is truncated".to_string());
+    }
+
+    let entries = records.chunks(RECORD_SIZE)
+        .map(|record| {
+            let mut reader = StateReader::new(record);
+            let frame = reader.u64()?;
+            let pc = reader.u16()?;
+            let opcode = reader.u16()?;
+            let mut registers = [0; NUM_GENERAL_PURPOSE_REGS];
+            registers.copy_from_slice(reader.bytes(NUM_GENERAL_PURPOSE_REGS)?);
+            Ok(TraceEntry {
+                frame,
+                pc,
+                opcode,
+                registers,
+                i: reader.u16()?,
+                sp: reader.u8()?,
+                delay_timer: reader.u8()?,
+                sound_timer: reader.u8()?,
+            })
+        })
+        .collect::<Result<_, StateError>>();
+    // Every record is complete, so reading one can't fail
+    Ok(entries.expect("Trace records are a fixed size"))
+}
+
+fn read_text(text: &str) -> Result<Vec<TraceEntry>, String> {
+    let mut entries = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let error = || format!("line {}: expected frame, pc, opcode, v0 to vf, i, sp, dt and st", i + 1);
+        let fields: Vec<&str> = line.split_whitespace().take(7 + NUM_GENERAL_PURPOSE_REGS).collect();
+        if fields.len() < 7 + NUM_GENERAL_PURPOSE_REGS {
+            return Err(error());
+        }
+        // Out of range values are errors rather than cut down to size, so a
+        // corrupt trace can't pass for a different machine state
+        let hex = |field: &str| u16::from_str_radix(field, 16).map_err(|_| error());
+        let byte = |field: &str| u8::from_str_radix(field, 16).map_err(|_| error());
+        let mut registers = [0; NUM_GENERAL_PURPOSE_REGS];
+        for (register, field) in registers.iter_mut().zip(fields[3..].iter()) {
+            *register = byte(field)?;
+        }
+        let rest = &fields[3 + NUM_GENERAL_PURPOSE_REGS..];
+        entries.push(TraceEntry {
+            frame: fields[0].parse().map_err(|_| error())?,
+            pc: hex(fields[1])?,
+            opcode: hex(fields[2])?,
+            registers,
+            i: hex(rest[0])?,
+            sp: byte(rest[1])?,
+            delay_timer: byte(rest[2])?,
+            sound_timer: byte(rest[3])?,
+        });
+    }
+    Ok(entries)
+}
+
+/// The index of the first entry where two traces differ, counting one
+/// ending before the other as a difference.
+pub fn first_difference(first: &[TraceEntry], second: &[TraceEntry]) -> Option<usize> {
+    match first.iter().zip(second.iter()).position(|(a, b)| a != b) {
+        Some(index) => Some(index),
+        None if first.len() != second.len() => Some(first.len().min(second.len())),
+        None => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(frame: u64, pc: Address) -> TraceEntry {
+        TraceEntry {
+            frame,
+            pc,
+            opcode: 0xC20F,
+            registers: [0x07; NUM_GENERAL_PURPOSE_REGS],
+            i: 0x41,
+            sp: 1,
+            delay_timer: 2,
+            sound_timer: 3,
+        }
+    }
+
+    #[test]
+    fn both_formats_read_back_what_was_written() {
+        let filter = TraceFilter {
+            addresses: Some((0x200, 0x2FF)),
+            frames: Some((1, 2)),
+        };
+        let entries = [entry(0, 0x200), entry(1, 0x200), entry(1, 0x300), entry(2, 0x2FF), entry(3, 0x200)];
+        for &format in [TraceFormat::Text, TraceFormat::Binary].iter() {
+            let mut output = Vec::new();
+            {
+                let mut writer = TraceWriter::new(&mut output, format, filter.clone());
+                for entry in entries.iter() {
+                    writer.trace(entry, &Instruction::Random(2, 0x0F));
+                }
+            }
+            assert_eq!(read(&output).unwrap(), vec![entries[1], entries[3]]);
+        }
+    }
+
+    #[test]
+    fn finds_where_traces_part() {
+        let first = [entry(0, 0x200), entry(0, 0x202)];
+        assert_eq!(first_difference(&first, &first), None);
+        assert_eq!(first_difference(&first, &[entry(0, 0x200), entry(0, 0x204)]), Some(1));
+        assert_eq!(first_difference(&first, &first[..1]), Some(1));
+    }
+
+    #[test]
+    fn malformed_text_lines_are_errors() {
+        let line = entry(4, 0x200).to_string();
+        assert_eq!(read(line.as_bytes()).unwrap(), vec![entry(4, 0x200)]);
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        // Each of sp, dt and st set to a value too big for a byte
+        for field in 20..23 {
+            let mut bad = fields.clone();
+            bad[field] = "1FF";
+            let text = format!("# a comment\n{}\n", bad.join(" "));
+            assert_eq!(read(text.as_bytes()),
+                       Err("line 2: expected frame, pc, opcode, v0 to vf, i, sp, dt and st".to_string()));
+        }
+        assert!(read(fields[..10].join(" ").as_bytes()).is_err());
+        assert!(read(line.replace("0200", "XYZ").as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,220 @@
+//! The functions a web page calls to run the emulator, when the library is
+//! built for WebAssembly without the operating system's random numbers:
+//!
+//! ```text
+//! cargo rustc --lib --release --target wasm32-unknown-unknown --no-default-features --crate-type cdylib
+//! ```
+//!
+//! They only take and return numbers, so the module can be used straight
+//! from JavaScript without any generated glue. A ROM is copied into the
+//! buffer `rom_buffer` makes for it and then loaded:
+//!
+//! ```text
+//! const emulator = (await WebAssembly.instantiate(wasm)).instance.exports;
+//! new Uint8Array(emulator.memory.buffer, emulator.rom_buffer(rom.length), rom.length).set(rom);
+//! emulator.load_rom(0, seed);
+//! // then, 60 times a second
+//! emulator.step_frame();
+//! const size = emulator.framebuffer_width() * emulator.framebuffer_height() * 4;
+//! image.data.set(new Uint8Array(emulator.memory.buffer, emulator.framebuffer(), size));
+//! ```
+//!
+//! Each instance of the module runs one machine.
+
+use std::cell::RefCell;
+
+use chip8::{Chip8, NUM_KEYS};
+use display;
+use platform::Platform;
+
+/// The colours for each combination of the two XO-CHIP bitplanes, as in
+/// the desktop frontend.
+const PALETTE: [[u8; 4]; 4] = [[0, 0, 0, 255], [255, 255, 255, 255], [171, 171, 171, 255], [84, 84, 84, 255]];
+
+struct Emulator {
+    chip8: Option<Chip8>,
+    rom: Vec<u8>,
+    framebuffer: Vec<u8>,
+    error: String,
+}
+
+thread_local! {
+    static EMULATOR: RefCell<Emulator> = const {
+        RefCell::new(Emulator {
+            chip8: None,
+            rom: Vec::new(),
+            framebuffer: Vec::new(),
+            error: String::new(),
+        })
+    };
+}
+
+fn with_emulator<T, F: FnOnce(&mut Emulator) -> T>(f: F) -> T {
+    EMULATOR.with(|emulator| f(&mut emulator.borrow_mut()))
+}
+
+/// Makes room for a ROM of `length` bytes and returns where to copy it to.
+#[no_mangle]
+pub extern "C" fn rom_buffer(length: u32) -> *mut u8 {
+    with_emulator(|emulator| {
+        emulator.rom = vec![0; length as usize];
+        emulator.rom.as_mut_ptr()
+    })
+}
+
+/// Starts the ROM in `rom_buffer` over on a new machine, with the
+/// platform's own quirks. Platforms are 0 for CHIP-8, 1 for SUPER-CHIP and
+/// 2 for XO-CHIP. Returns 0, or -1 if the ROM can't be loaded.
+#[no_mangle]
+pub extern "C" fn load_rom(platform: u32, seed: u32) -> i32 {
+    with_emulator(|emulator| {
+        let platform = match platform {
+            0 => Platform::Chip8,
+            1 => Platform::SuperChip,
+            2 => Platform::XoChip,
+            _ => {
+                emulator.error = format!("unknown platform {}, expected 0, 1 or 2", platform);
+                return -1;
+            }
+        };
+        match Chip8::new(emulator.rom.clone(), platform, platform.default_quirks()) {
+            Ok(mut chip8) => {
+                chip8.set_seed(seed as u64);
+                emulator.chip8 = Some(chip8);
+                0
+            }
+            Err(error) => {
+                emulator.error = error.to_string();
+                -1
+            }
+        }
+    })
+}
+
+/// Runs a 60th of a second. Returns 0 while the program runs, 1 once it
+/// has exited and -1 if it stopped with an error.
+#[no_mangle]
+pub extern "C" fn step_frame() -> i32 {
+    with_emulator(|emulator| {
+        let result = match emulator.chip8 {
+            Some(ref mut chip8) => chip8.step_frame().map(|()| chip8.has_exited()).map_err(|error| error.to_string()),
+            None => Err("no ROM is loaded".to_string()),
+        };
+        match result {
+            Ok(false) => 0,
+            Ok(true) => 1,
+            Err(error) => {
+                emulator.error = error;
+                -1
+            }
+        }
+    })
+}
+
+/// Presses one of the keys from 0 to 0xF. Others are ignored.
+#[no_mangle]
+pub extern "C" fn key_down(key: u32) {
+    send_key(key, true);
+}
+
+#[no_mangle]
+pub extern "C" fn key_up(key: u32) {
+    send_key(key, false);
+}
+
+fn send_key(key: u32, pressed: bool) {
+    with_emulator(|emulator| {
+        match emulator.chip8 {
+            Some(ref mut chip8) if (key as usize) < NUM_KEYS && pressed => chip8.handle_key_press(key as u8),
+            Some(ref mut chip8) if (key as usize) < NUM_KEYS => chip8.handle_key_release(key as u8),
+            _ => {}
+        }
+    })
+}
+
+/// Draws the screen as RGBA pixels, a row at a time, and returns where
+/// they are. They stay there until the next call.
+#[no_mangle]
+pub extern "C" fn framebuffer() -> *const u8 {
+    with_emulator(|emulator| {
+        emulator.framebuffer.clear();
+        if let Some(ref chip8) = emulator.chip8 {
+            for row in chip8.display.get_buffer() {
+                for pixel in row {
+                    emulator.framebuffer.extend_from_slice(&PALETTE[pixel as usize]);
+                }
+            }
+        }
+        emulator.framebuffer.as_ptr()
+    })
+}
+
+/// The width of the screen in pixels, which is larger in hi-res mode.
+#[no_mangle]
+pub extern "C" fn framebuffer_width() -> u32 {
+    with_emulator(|emulator| emulator.chip8.as_ref().map_or(display::WIDTH, |chip8| chip8.display.width()) as u32)
+}
+
+#[no_mangle]
+pub extern "C" fn framebuffer_height() -> u32 {
+    with_emulator(|emulator| emulator.chip8.as_ref().map_or(display::HEIGHT, |chip8| chip8.display.height()) as u32)
+}
+
+/// The sound timer. The buzzer sounds while it is above zero.
+#[no_mangle]
+pub extern "C" fn sound_timer() -> u32 {
+    with_emulator(|emulator| emulator.chip8.as_ref().map_or(0, |chip8| chip8.sound_timer() as u32))
+}
+
+/// Why `load_rom` or `step_frame` last failed, as UTF-8 `error_length`
+/// bytes long.
+#[no_mangle]
+pub extern "C" fn error_message() -> *const u8 {
+    with_emulator(|emulator| emulator.error.as_ptr())
+}
+
+#[no_mangle]
+pub extern "C" fn error_length() -> u32 {
+    with_emulator(|emulator| emulator.error.len() as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{ptr, slice, str};
+
+    /// Draws a 5 at the top left, then buzzes forever.
+    const ROM: [u8; 10] = [0x60, 0x05, // V0 := 5
+                           0xF0, 0x29, // I := font V0
+                           0xD1, 0x15, // sprite V1 V1 5
+                           0xF0, 0x18, // loop: buzzer := V0
+                           0x12, 0x06]; // jump loop
+
+    fn error() -> String {
+        let bytes = unsafe { slice::from_raw_parts(error_message(), error_length() as usize) };
+        str::from_utf8(bytes).unwrap().to_string()
+    }
+
+    #[test]
+    fn runs_a_rom_through_the_exports() {
+        assert_eq!(step_frame(), -1);
+        assert_eq!(error(), "no ROM is loaded");
+
+        unsafe {
+            ptr::copy_nonoverlapping(ROM.as_ptr(), rom_buffer(ROM.len() as u32), ROM.len());
+        }
+        assert_eq!(load_rom(3, 0), -1);
+        assert!(error().contains("platform"));
+        assert_eq!(load_rom(0, 0), 0);
+        key_down(0x20);
+        assert_eq!(step_frame(), 0);
+        assert!(sound_timer() > 0);
+
+        let width = framebuffer_width() as usize;
+        let size = width * framebuffer_height() as usize * 4;
+        let pixels = unsafe { slice::from_raw_parts(framebuffer(), size) };
+        // The 5's top row is lit, but not the pixel under its second column
+        assert_eq!(&pixels[..4], &PALETTE[1]);
+        assert_eq!(&pixels[(width + 1) * 4..(width + 2) * 4], &PALETTE[0]);
+    }
+}
@@ -0,0 +1,186 @@
+//! Runs the test ROMs in `tests/roms` without a window and compares the
+//! screen each one ends on with a picture in `tests/golden`.
+//!
+//! Run with `UPDATE_GOLDEN=1` to write the pictures instead, after
+//! checking the new screens are right. A test without a picture fails until
+//! one is written.
+//!
+//! The ROMs here are home-made and their pictures were drawn by this
+//! emulator, so they only guard against regressions. Timendus'
+//! chip8-test-suite and the IBM logo, checked against a reference
+//! interpreter, are still to be added.
+
+extern crate rust_8;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use rust_8::{Chip8, Key, Platform, Quirks};
+
+struct Case {
+    name: &'static str,
+    rom: &'static str,
+    platform: Platform,
+    quirks: Quirks,
+    frames: u64,
+    /// Keys pressed (true) or released (false) before the given frame.
+    keys: &'static [(u64, Key, bool)],
+}
+
+fn check(case: Case) {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests");
+    let rom = fs::read(root.join("roms").join(case.rom)).expect("Could not read the ROM");
+    let mut chip8 = Chip8::new(rom, case.platform, case.quirks).unwrap();
+    // The same random numbers every run
+    chip8.set_seed(0);
+
+    for frame in 0..case.frames {
+        for &(_, key, pressed) in case.keys.iter().filter(|&&(at, _, _)| at == frame) {
+            if pressed {
+                chip8.handle_key_press(key);
+            } else {
+                chip8.handle_key_release(key);
+            }
+        }
+        chip8.step_frame().unwrap();
+    }
+    let actual = chip8.display.to_ascii();
+
+    let golden = root.join("golden").join(format!("{}.txt", case.name));
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&golden, &actual).expect("Could not write the golden image");
+        return;
+    }
+    let expected = match fs::read_to_string(&golden) {
+        Ok(expected) => expected,
+        Err(_) => {
+            panic!("{} has no golden image. This is what it drew:\n{}\nRun with UPDATE_GOLDEN=1 to keep it.",
+                   case.name,
+                   actual)
+        }
+    };
+    if actual != expected {
+        panic!("{} drew something else (expected on the left, actual on the right, ! marks rows that differ):\n{}",
+               case.name,
+               diff(&expected, &actual));
+    }
+}
+
+/// The two screens side by side.
+fn diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+    let width = expected.iter().map(|line| line.len()).max().unwrap_or(0);
+    (0..expected.len().max(actual.len()))
+        .map(|row| {
+            let left = expected.get(row).cloned().unwrap_or("");
+            let right = actual.get(row).cloned().unwrap_or("");
+            let marker = if left == right { ' ' } else { '!' };
+            format!("{} {:<width$}   {}\n", marker, left, right, width = width)
+        })
+        .collect()
+}
+
+#[test]
+fn logo() {
+    check(Case {
+        name: "logo",
+        rom: "logo.ch8",
+        platform: Platform::Chip8,
+        quirks: Quirks::COSMAC_VIP,
+        frames: 30,
+        keys: &[],
+    });
+}
+
+#[test]
+fn opcodes() {
+    check(Case {
+        name: "opcodes",
+        rom: "opcodes.ch8",
+        platform: Platform::Chip8,
+        quirks: Quirks::COSMAC_VIP,
+        frames: 60,
+        keys: &[],
+    });
+}
+
+#[test]
+fn flags() {
+    check(Case {
+        name: "flags",
+        rom: "flags.ch8",
+        platform: Platform::Chip8,
+        quirks: Quirks::COSMAC_VIP,
+        frames: 60,
+        keys: &[],
+    });
+}
+
+#[test]
+fn quirks_vip() {
+    check(Case {
+        name: "quirks-vip",
+        rom: "quirks.ch8",
+        platform: Platform::Chip8,
+        quirks: Quirks::COSMAC_VIP,
+        frames: 30,
+        keys: &[],
+    });
+}
+
+#[test]
+fn quirks_chip48() {
+    check(Case {
+        name: "quirks-chip48",
+        rom: "quirks.ch8",
+        platform: Platform::SuperChip,
+        quirks: Quirks::CHIP_48,
+        frames: 30,
+        keys: &[],
+    });
+}
+
+#[test]
+fn quirks_schip() {
+    check(Case {
+        name: "quirks-schip",
+        rom: "quirks.ch8",
+        platform: Platform::SuperChip,
+        quirks: Quirks::SUPER_CHIP,
+        frames: 30,
+        keys: &[],
+    });
+}
+
+#[test]
+fn quirks_modern() {
+    check(Case {
+        name: "quirks-modern",
+        rom: "quirks.ch8",
+        platform: Platform::XoChip,
+        quirks: Quirks::MODERN,
+        frames: 30,
+        keys: &[],
+    });
+}
+
+#[test]
+fn keypad() {
+    check(Case {
+        name: "keypad",
+        rom: "keypad.ch8",
+        platform: Platform::Chip8,
+        quirks: Quirks::COSMAC_VIP,
+        frames: 60,
+        keys: &[(5, 0x1, true),
+                (10, 0x1, false),
+                (15, 0xA, true),
+                (20, 0xA, false),
+                (25, 0xF, true),
+                (30, 0xF, false),
+                (35, 0x0, true),
+                (40, 0x0, false)],
+    });
+}
@@ -0,0 +1,129 @@
+; Checks what each instruction leaves in VF, one tick or cross per test,
+; including when VF is also one of the operands.
+
+            CLS
+            LD VA, 0
+            LD VB, 2
+            LD VC, 0
+            LD VD, 1
+
+; 0: 8XY4 without a carry
+            LD V0, 5
+            LD V1, 7
+            ADD V0, V1
+            SE VF, 0
+            LD VD, 0
+            CALL report
+
+; 1: 8XY4 with a carry
+            LD V0, #FF
+            LD V1, 2
+            ADD V0, V1
+            SE VF, 1
+            LD VD, 0
+            CALL report
+
+; 2: 8XY5 without a borrow
+            LD V0, 7
+            LD V1, 5
+            SUB V0, V1
+            SE VF, 1
+            LD VD, 0
+            CALL report
+
+; 3: 8XY5 with a borrow
+            LD V0, 5
+            LD V1, 7
+            SUB V0, V1
+            SE VF, 0
+            LD VD, 0
+            CALL report
+
+; 4: 8XY5 of two equal numbers, which doesn't borrow
+            LD V0, 5
+            LD V1, 5
+            SUB V0, V1
+            SE VF, 1
+            LD VD, 0
+            CALL report
+
+; 5: 8XY7 without a borrow
+            LD V0, 5
+            LD V1, 7
+            SUBN V0, V1
+            SE VF, 1
+            LD VD, 0
+            CALL report
+
+; 6: 8XY7 with a borrow
+            LD V0, 7
+            LD V1, 5
+            SUBN V0, V1
+            SE VF, 0
+            LD VD, 0
+            CALL report
+
+; 7: 8XY6 shifts the low bit into VF
+            LD V0, 5
+            LD V1, 5
+            SHR V0, V1
+            SE VF, 1
+            LD VD, 0
+            LD V0, 4
+            LD V1, 4
+            SHR V0, V1
+            SE VF, 0
+            LD VD, 0
+            CALL report
+
+; 8: 8XYE shifts the high bit into VF
+            LD V0, #81
+            LD V1, #81
+            SHL V0, V1
+            SE VF, 1
+            LD VD, 0
+            LD V0, #41
+            LD V1, #41
+            SHL V0, V1
+            SE VF, 0
+            LD VD, 0
+            CALL report
+
+; 9: 8XY4 into VF keeps the carry, not the sum
+            LD VF, #FF
+            LD V1, 2
+            ADD VF, V1
+            SE VF, 1
+            LD VD, 0
+            CALL report
+
+; A: 8XY5 into VF keeps the borrow flag, not the difference
+            LD VF, 5
+            LD V1, 7
+            SUB VF, V1
+            SE VF, 0
+            LD VD, 0
+            CALL report
+
+; B: 8XY6 of VF keeps the bit shifted out
+            LD VF, 3
+            SHR VF, VF
+            SE VF, 1
+            LD VD, 0
+            CALL report
+
+; C: DXYN sets VF when a sprite turns a pixel off
+            LD V0, 56
+            LD V1, 24
+            LD I, tick
+            DRW V0, V1, 5
+            SE VF, 0
+            LD VD, 0
+            DRW V0, V1, 5
+            SE VF, 1
+            LD VD, 0
+            CALL report
+
+done:       JP done
+
+            include "report.asm"
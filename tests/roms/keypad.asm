@@ -0,0 +1,18 @@
+; Waits for each key with FX0A and draws it, with a tick if EX9E then
+; sees it held down. Then waits for it to be let go with EXA1.
+
+            CLS
+            LD VA, 0
+            LD VB, 2
+            LD VD, 1
+
+loop:       LD V0, K
+            LD VC, V0
+            SKP V0
+            LD VD, 0
+            CALL report
+held:       SKNP V0
+            JP held
+            JP loop
+
+            include "report.asm"
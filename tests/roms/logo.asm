@@ -0,0 +1,32 @@
+; Clears the screen and spells out RUST-8, like the IBM logo ROM does for
+; IBM. Only needs 00E0, 6XNN, 7XNN, ANNN, FX29, DXYN and 1NNN.
+
+            CLS
+            LD V0, 16
+            LD V1, 13
+            LD I, letter_r
+            DRW V0, V1, 5
+            ADD V0, 6
+            LD I, letter_u
+            DRW V0, V1, 5
+            ADD V0, 6
+            LD I, letter_s
+            DRW V0, V1, 5
+            ADD V0, 6
+            LD I, letter_t
+            DRW V0, V1, 5
+            ADD V0, 6
+            LD I, dash
+            DRW V0, V1, 5
+            ADD V0, 6
+            LD V2, 8
+            LD F, V2
+            DRW V0, V1, 5
+
+done:       JP done
+
+letter_r:   DB #E0, #90, #E0, #A0, #90
+letter_u:   DB #90, #90, #90, #90, #60
+letter_s:   DB #70, #80, #60, #10, #E0
+letter_t:   DB #E0, #40, #40, #40, #40
+dash:       DB #00, #00, #E0, #00, #00
@@ -0,0 +1,183 @@
+; Checks what each instruction computes, one tick or cross per test. VF is
+; left to flags.asm, and anything that depends on quirks to quirks.asm.
+
+            CLS
+            LD VA, 0
+            LD VB, 2
+            LD VC, 0
+            LD VD, 1
+
+; 0: 3XNN, 4XNN, 5XY0 and 9XY0
+            LD V0, #12
+            LD V1, #12
+            LD V2, #34
+            SE V0, #12
+            LD VD, 0
+            SNE V0, #34
+            LD VD, 0
+            SE V0, V1
+            LD VD, 0
+            SNE V0, V2
+            LD VD, 0
+            CALL report
+
+; 1: 6XNN, and 7XNN, which wraps around and leaves VF alone
+            LD VF, 5
+            LD V0, #FF
+            ADD V0, 2
+            SE V0, 1
+            LD VD, 0
+            SE VF, 5
+            LD VD, 0
+            CALL report
+
+; 2: 8XY0
+            LD V1, #5A
+            LD V0, V1
+            SE V0, #5A
+            LD VD, 0
+            CALL report
+
+; 3: 8XY1
+            LD V0, #C3
+            LD V1, #0F
+            OR V0, V1
+            SE V0, #CF
+            LD VD, 0
+            CALL report
+
+; 4: 8XY2
+            LD V0, #C3
+            AND V0, V1
+            SE V0, #03
+            LD VD, 0
+            CALL report
+
+; 5: 8XY3
+            LD V0, #C3
+            XOR V0, V1
+            SE V0, #CC
+            LD VD, 0
+            CALL report
+
+; 6: 8XY4, with and without overflow
+            LD V0, #05
+            LD V1, #07
+            ADD V0, V1
+            SE V0, #0C
+            LD VD, 0
+            LD V0, #FF
+            LD V1, #02
+            ADD V0, V1
+            SE V0, #01
+            LD VD, 0
+            CALL report
+
+; 7: 8XY5, with and without underflow
+            LD V0, #07
+            LD V1, #05
+            SUB V0, V1
+            SE V0, #02
+            LD VD, 0
+            LD V0, #05
+            LD V1, #07
+            SUB V0, V1
+            SE V0, #FE
+            LD VD, 0
+            CALL report
+
+; 8: 8XY7
+            LD V0, #05
+            LD V1, #07
+            SUBN V0, V1
+            SE V0, #02
+            LD VD, 0
+            CALL report
+
+; 9: 8XY6, with VX and VY the same so either shift quirk gives the same answer
+            LD V0, #0A
+            LD V1, #0A
+            SHR V0, V1
+            SE V0, #05
+            LD VD, 0
+            CALL report
+
+; A: 8XYE
+            LD V0, #85
+            LD V1, #85
+            SHL V0, V1
+            SE V0, #0A
+            LD VD, 0
+            CALL report
+
+; B: FX55, FX65, ANNN and FX1E
+            LD V0, #11
+            LD V1, #22
+            LD V2, #33
+            LD I, scratch
+            LD [I], V2
+            LD V0, 0
+            LD V1, 0
+            LD V2, 0
+            LD I, scratch - 1
+            LD V3, 1
+            ADD I, V3
+            LD V2, [I]
+            SE V0, #11
+            LD VD, 0
+            SE V1, #22
+            LD VD, 0
+            SE V2, #33
+            LD VD, 0
+            CALL report
+
+; C: FX33
+            LD V0, 234
+            LD I, scratch
+            LD B, V0
+            LD V2, [I]
+            SE V0, 2
+            LD VD, 0
+            SE V1, 3
+            LD VD, 0
+            SE V2, 4
+            LD VD, 0
+            CALL report
+
+; D: 2NNN and 00EE
+            LD V0, 0
+            CALL set_v0
+            SE V0, #42
+            LD VD, 0
+            CALL report
+
+; E: FX15 and FX07. The timer may have ticked once in between.
+            LD V0, 10
+            LD DT, V0
+            LD V1, DT
+            SE V1, 10
+            SNE V1, 9
+            JP timer_ok
+            LD VD, 0
+timer_ok:   CALL report
+
+; F: 1NNN, and CXNN keeping to its mask
+            JP jumped
+            LD VD, 0
+jumped:     RND V0, 0
+            SE V0, 0
+            LD VD, 0
+            RND V1, #0F
+            LD V2, #F0
+            AND V2, V1
+            SE V2, 0
+            LD VD, 0
+            CALL report
+
+done:       JP done
+
+set_v0:     LD V0, #42
+            RET
+
+scratch:    DB 0, 0, 0
+            include "report.asm"
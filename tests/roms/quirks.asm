@@ -0,0 +1,57 @@
+; Shows which way each quirk goes, as a digit per quirk along the top:
+;
+;   shift      4 if 8XY6 shifts VY, 0 if it shifts VX
+;   load/store 9 if FX55 moves I on, 7 if it leaves it
+;   logic      0 if 8XY1 resets VF, 5 if it leaves it
+;   jump       2 if BNNN adds VX, 1 if it adds V0
+;
+; and a block at the bottom right corner that shows up in all four corners
+; if sprites wrap.
+
+            CLS
+            LD V5, 0
+            LD V6, 2
+
+            LD V0, 1
+            LD V1, 8
+            SHR V0, V1
+            CALL show
+
+            LD I, buffer
+            LD V0, 7
+            LD [I], V0
+            LD V0, [I]
+            CALL show
+
+            LD VF, 5
+            LD V0, 1
+            LD V1, 2
+            OR V0, V1
+            LD V0, VF
+            CALL show
+
+            LD V0, 0
+            LD V2, 2
+            JP V0, table
+table:      JP jump_v0
+            JP jump_vx
+jump_v0:    LD V0, 1
+            JP jumped
+jump_vx:    LD V0, 2
+jumped:     CALL show
+
+            LD V0, 60
+            LD V1, 30
+            LD I, block
+            DRW V0, V1, 4
+
+done:       JP done
+
+; Draws the digit in V0 at (V5, V6) and moves along.
+show:       LD F, V0
+            DRW V5, V6, 5
+            ADD V5, 8
+            RET
+
+buffer:     DB 0, 9
+block:      DB #FF, #FF, #FF, #FF
@@ -0,0 +1,25 @@
+; Shared by the self-checking ROMs. A test clears VD when one of its checks
+; fails and then calls report, which draws the test's number from VC with
+; a tick beside it if it passed or a cross if it failed. Eight tests fit on
+; a row.
+;
+; VA, VB, VC and VD belong to report; tests can use the other registers.
+
+report:     LD F, VC
+            DRW VA, VB, 5
+            ADD VA, 4
+            LD I, tick
+            SE VD, 1
+            LD I, cross
+            DRW VA, VB, 5
+            ADD VA, 4
+            SE VA, 64
+            JP next_test
+            LD VA, 0
+            ADD VB, 8
+next_test:  ADD VC, 1
+            LD VD, 1
+            RET
+
+tick:       DB #00, #20, #20, #A0, #40
+cross:      DB #A0, #A0, #40, #A0, #A0
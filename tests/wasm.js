@@ -0,0 +1,97 @@
+// Runs the test ROMs in the WebAssembly build of the emulator with Node, so
+// no browser is needed, and compares their screens with the same golden
+// images as tests/golden.rs:
+//
+//     cargo rustc --lib --release --target wasm32-unknown-unknown --no-default-features --crate-type cdylib
+//     node tests/wasm.js target/wasm32-unknown-unknown/release/rust_8.wasm
+
+'use strict';
+
+const fs = require('fs');
+const path = require('path');
+
+// The cases from tests/golden.rs that use their platform's own quirks.
+// Platforms are 0 for CHIP-8, 1 for SUPER-CHIP and 2 for XO-CHIP.
+const CASES = [
+    { name: 'logo', rom: 'logo.ch8', platform: 0, frames: 30 },
+    { name: 'opcodes', rom: 'opcodes.ch8', platform: 0, frames: 60 },
+    { name: 'flags', rom: 'flags.ch8', platform: 0, frames: 60 },
+    { name: 'quirks-vip', rom: 'quirks.ch8', platform: 0, frames: 30 },
+    { name: 'quirks-schip', rom: 'quirks.ch8', platform: 1, frames: 30 },
+    { name: 'quirks-modern', rom: 'quirks.ch8', platform: 2, frames: 30 },
+    {
+        name: 'keypad', rom: 'keypad.ch8', platform: 0, frames: 60,
+        // Keys pressed (true) or released (false) before the given frame
+        keys: [[5, 0x1, true], [10, 0x1, false], [15, 0xA, true], [20, 0xA, false],
+               [25, 0xF, true], [30, 0xF, false], [35, 0x0, true], [40, 0x0, false]],
+    },
+];
+
+function error(emulator) {
+    const bytes = new Uint8Array(emulator.memory.buffer, emulator.error_message(), emulator.error_length());
+    return Buffer.from(bytes).toString('utf8');
+}
+
+// The screen in the same text as Display::to_ascii, with any pixel that
+// isn't the background colour lit.
+function screen(emulator) {
+    const width = emulator.framebuffer_width();
+    const height = emulator.framebuffer_height();
+    const pixels = new Uint8Array(emulator.memory.buffer, emulator.framebuffer(), width * height * 4);
+    let text = '';
+    for (let y = 0; y < height; y++) {
+        text += '|';
+        for (let x = 0; x < width; x++) {
+            const i = (y * width + x) * 4;
+            text += pixels[i] || pixels[i + 1] || pixels[i + 2] ? '*' : '.';
+        }
+        text += '|\n';
+    }
+    return text;
+}
+
+function run(emulator, test) {
+    const rom = fs.readFileSync(path.join(__dirname, 'roms', test.rom));
+    new Uint8Array(emulator.memory.buffer, emulator.rom_buffer(rom.length), rom.length).set(rom);
+    if (emulator.load_rom(test.platform, 0) < 0) {
+        throw new Error(`could not load ${test.rom}: ${error(emulator)}`);
+    }
+    for (let frame = 0; frame < test.frames; frame++) {
+        for (const [at, key, pressed] of test.keys || []) {
+            if (at === frame) {
+                pressed ? emulator.key_down(key) : emulator.key_up(key);
+            }
+        }
+        if (emulator.step_frame() < 0) {
+            throw new Error(`${test.rom} stopped: ${error(emulator)}`);
+        }
+    }
+    return screen(emulator);
+}
+
+async function main() {
+    if (process.argv.length !== 3) {
+        console.error('Usage: node tests/wasm.js RUST_8_WASM');
+        process.exit(2);
+    }
+    const { instance } = await WebAssembly.instantiate(fs.readFileSync(process.argv[2]));
+    const emulator = instance.exports;
+
+    let failures = 0;
+    for (const test of CASES) {
+        const actual = run(emulator, test);
+        const expected = fs.readFileSync(path.join(__dirname, 'golden', `${test.name}.txt`), 'utf8');
+        if (actual === expected) {
+            console.log(`ok ${test.name}`);
+        } else {
+            failures++;
+            console.log(`FAILED ${test.name} drew something else.\nExpected:\n${expected}Actual:\n${actual}`);
+        }
+    }
+    process.exit(failures ? 1 : 0);
+}
+
+main().catch(error => {
+    console.error(error.message);
+    process.exit(1);
+});